@@ -0,0 +1,68 @@
+-- the same schema the sqlite migrations build up to
+CREATE TABLE posts (
+  uri TEXT PRIMARY KEY,
+  cid TEXT NOT NULL,
+  author TEXT NOT NULL,
+  indexed_at TIMESTAMPTZ NOT NULL,
+  reply_root TEXT,
+  reply_parent TEXT,
+  quote TEXT,
+  fingerprint TEXT
+);
+CREATE INDEX posts_author_indexed_at ON posts (author, indexed_at);
+CREATE INDEX posts_feed_order ON posts (indexed_at DESC, cid DESC, uri, author, reply_root, quote);
+
+CREATE TABLE links (
+  url TEXT PRIMARY KEY,
+  kind TEXT NOT NULL,
+  site TEXT NOT NULL,
+  created_at TIMESTAMPTZ NOT NULL,
+  count BIGINT NOT NULL DEFAULT 1
+);
+
+CREATE TABLE post_links (
+  post_uri TEXT NOT NULL REFERENCES posts (uri) ON DELETE CASCADE,
+  url TEXT NOT NULL,
+  PRIMARY KEY (post_uri, url)
+);
+CREATE INDEX post_links_url ON post_links (url);
+
+CREATE TABLE labels (
+  src TEXT NOT NULL,
+  uri TEXT NOT NULL,
+  val TEXT NOT NULL,
+  cts TIMESTAMPTZ NOT NULL,
+  exp TIMESTAMPTZ,
+  PRIMARY KEY (src, uri, val)
+);
+CREATE INDEX labels_uri ON labels (uri);
+
+CREATE TABLE blocked_authors (
+  did TEXT PRIMARY KEY,
+  reason TEXT,
+  created_at TIMESTAMPTZ NOT NULL
+);
+CREATE TABLE blocked_links (
+  url TEXT PRIMARY KEY,
+  reason TEXT,
+  created_at TIMESTAMPTZ NOT NULL
+);
+
+CREATE TABLE interactions (
+  id BIGSERIAL PRIMARY KEY,
+  requester TEXT NOT NULL,
+  item TEXT NOT NULL,
+  event TEXT NOT NULL,
+  feed_context TEXT,
+  created_at TIMESTAMPTZ NOT NULL
+);
+CREATE INDEX interactions_item ON interactions (item);
+
+CREATE TABLE viewer_preferences (
+  viewer TEXT NOT NULL,
+  dimension TEXT NOT NULL,
+  value TEXT NOT NULL,
+  score DOUBLE PRECISION NOT NULL,
+  updated_at TIMESTAMPTZ NOT NULL,
+  PRIMARY KEY (viewer, dimension, value)
+);
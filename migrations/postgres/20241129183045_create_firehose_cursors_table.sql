@@ -0,0 +1,5 @@
+CREATE TABLE firehose_cursors (
+  service TEXT PRIMARY KEY,
+  seq BIGINT NOT NULL,
+  updated_at TIMESTAMPTZ NOT NULL
+);
@@ -0,0 +1,4 @@
+-- the post's language tags, lowercased
+ALTER TABLE posts ADD COLUMN langs TEXT[];
+-- how many likes and reposts the post has gotten since it was indexed
+ALTER TABLE posts ADD COLUMN engagement BIGINT NOT NULL DEFAULT 0;
@@ -0,0 +1,10 @@
+-- written every few seconds by the process ingesting each relay, so processes that only serve can
+-- tell whether ingest is alive
+CREATE TABLE ingest_heartbeats (
+  relay TEXT PRIMARY KEY,
+  connected BOOLEAN NOT NULL,
+  seq BIGINT,
+  started_at TIMESTAMPTZ NOT NULL,
+  last_commit_at TIMESTAMPTZ,
+  updated_at TIMESTAMPTZ NOT NULL
+);
@@ -0,0 +1,44 @@
+-- Blocked links used to match any link containing them, so blocking `scam.example` also blocked
+-- `notscam.example`. They're now stored as `host[/path]`, without a scheme, and block links to
+-- that host and its subdomains, or to that path and anything under it.
+--
+-- Only links in post_links can be matched, so posts indexed before post_links was created are
+-- never purged or filtered by a blocked link
+UPDATE blocked_links
+SET url = substr(url, strpos(url, '://') + 3)
+WHERE strpos(url, '://') > 0
+AND NOT EXISTS (
+  SELECT 1 FROM blocked_links AS other
+  WHERE other.url = substr(blocked_links.url, strpos(blocked_links.url, '://') + 3)
+);
+
+CREATE VIEW blocked_post_links AS
+SELECT links.post_uri, links.url, blocked_links.url AS blocked_url
+FROM (
+  SELECT
+    post_uri,
+    url,
+    CASE WHEN host LIKE 'www.%' THEN substr(host, 5) ELSE host END AS host,
+    path
+  FROM (
+    SELECT
+      post_uri,
+      url,
+      lower(substr(rest, 1, strpos(rest || '/', '/') - 1)) AS host,
+      substr(rest, strpos(rest || '/', '/')) AS path
+    FROM (
+      SELECT
+        post_uri,
+        url,
+        CASE WHEN strpos(url, '://') > 0 THEN substr(url, strpos(url, '://') + 3) ELSE url END AS rest
+      FROM post_links
+    ) AS with_rest
+  ) AS with_host
+) AS links
+JOIN blocked_links ON
+  links.host || links.path = blocked_links.url
+  OR left(links.host || links.path, length(blocked_links.url) + 1) = blocked_links.url || '/'
+  OR (
+    strpos(blocked_links.url, '/') = 0
+    AND right(links.host, length(blocked_links.url) + 1) = '.' || blocked_links.url
+  );
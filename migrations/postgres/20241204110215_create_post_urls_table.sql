@@ -0,0 +1,44 @@
+-- every url in a post, not only its music links, so blocked links match scam and phishing links
+-- too. Posts indexed before this only have their music links, in post_links
+CREATE TABLE post_urls (
+  post_uri TEXT NOT NULL REFERENCES posts (uri) ON DELETE CASCADE,
+  url TEXT NOT NULL,
+  PRIMARY KEY (post_uri, url)
+);
+
+DROP VIEW blocked_post_links;
+
+CREATE VIEW blocked_post_links AS
+SELECT links.post_uri, links.url, blocked_links.url AS blocked_url
+FROM (
+  SELECT
+    post_uri,
+    url,
+    CASE WHEN host LIKE 'www.%' THEN substr(host, 5) ELSE host END AS host,
+    path
+  FROM (
+    SELECT
+      post_uri,
+      url,
+      lower(substr(rest, 1, strpos(rest || '/', '/') - 1)) AS host,
+      substr(rest, strpos(rest || '/', '/')) AS path
+    FROM (
+      SELECT
+        post_uri,
+        url,
+        CASE WHEN strpos(url, '://') > 0 THEN substr(url, strpos(url, '://') + 3) ELSE url END AS rest
+      FROM (
+        SELECT post_uri, url FROM post_links
+        UNION
+        SELECT post_uri, url FROM post_urls
+      ) AS urls
+    ) AS with_rest
+  ) AS with_host
+) AS links
+JOIN blocked_links ON
+  links.host || links.path = blocked_links.url
+  OR left(links.host || links.path, length(blocked_links.url) + 1) = blocked_links.url || '/'
+  OR (
+    strpos(blocked_links.url, '/') = 0
+    AND right(links.host, length(blocked_links.url) + 1) = '.' || blocked_links.url
+  );
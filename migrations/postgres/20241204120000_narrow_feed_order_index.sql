@@ -0,0 +1,3 @@
+-- the feeds select and filter on more columns than an index could cover, so this only orders them
+DROP INDEX posts_feed_order;
+CREATE INDEX posts_feed_order ON posts (indexed_at DESC, cid DESC);
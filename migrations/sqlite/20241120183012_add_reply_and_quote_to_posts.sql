@@ -0,0 +1,3 @@
+ALTER TABLE posts ADD COLUMN reply_root TEXT;
+ALTER TABLE posts ADD COLUMN reply_parent TEXT;
+ALTER TABLE posts ADD COLUMN quote TEXT;
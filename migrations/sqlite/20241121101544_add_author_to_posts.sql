@@ -0,0 +1,3 @@
+ALTER TABLE posts ADD COLUMN author TEXT NOT NULL DEFAULT '';
+-- uris look like `at://did:plc:asdfghjkl/app.bsky.feed.post/qwertyuiop`
+UPDATE posts SET author = substr(uri, 6, instr(substr(uri, 6), '/') - 1);
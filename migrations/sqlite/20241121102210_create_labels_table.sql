@@ -0,0 +1,9 @@
+CREATE TABLE labels (
+  src TEXT NOT NULL,
+  uri TEXT NOT NULL,
+  val TEXT NOT NULL,
+  cts DATETIME NOT NULL,
+  exp DATETIME,
+  PRIMARY KEY (src, uri, val)
+);
+CREATE INDEX labels_uri ON labels (uri);
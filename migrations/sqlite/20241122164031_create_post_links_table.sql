@@ -0,0 +1,6 @@
+CREATE TABLE post_links (
+  post_uri TEXT NOT NULL REFERENCES posts (uri) ON DELETE CASCADE,
+  url TEXT NOT NULL,
+  PRIMARY KEY (post_uri, url)
+);
+CREATE INDEX post_links_url ON post_links (url);
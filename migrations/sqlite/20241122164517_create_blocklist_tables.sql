@@ -0,0 +1,10 @@
+CREATE TABLE blocked_authors (
+  did TEXT PRIMARY KEY NOT NULL,
+  reason TEXT,
+  created_at DATETIME NOT NULL
+);
+CREATE TABLE blocked_links (
+  url TEXT PRIMARY KEY NOT NULL,
+  reason TEXT,
+  created_at DATETIME NOT NULL
+);
@@ -0,0 +1,2 @@
+ALTER TABLE posts ADD COLUMN fingerprint TEXT;
+CREATE INDEX posts_author_indexed_at ON posts (author, indexed_at);
@@ -0,0 +1,9 @@
+CREATE TABLE interactions (
+  id INTEGER PRIMARY KEY AUTOINCREMENT,
+  requester TEXT NOT NULL,
+  item TEXT NOT NULL,
+  event TEXT NOT NULL,
+  feed_context TEXT,
+  created_at DATETIME NOT NULL
+);
+CREATE INDEX interactions_item ON interactions (item);
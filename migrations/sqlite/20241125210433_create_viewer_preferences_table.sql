@@ -0,0 +1,8 @@
+CREATE TABLE viewer_preferences (
+  viewer TEXT NOT NULL,
+  dimension TEXT NOT NULL,
+  value TEXT NOT NULL,
+  score REAL NOT NULL,
+  updated_at DATETIME NOT NULL,
+  PRIMARY KEY (viewer, dimension, value)
+);
@@ -0,0 +1,2 @@
+-- covers the columns the feeds select and filter on, in the order they're sorted by
+CREATE INDEX posts_feed_order ON posts (indexed_at DESC, cid DESC, uri, author, reply_root, quote);
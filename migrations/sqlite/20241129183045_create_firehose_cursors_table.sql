@@ -0,0 +1,5 @@
+CREATE TABLE firehose_cursors (
+  service TEXT PRIMARY KEY NOT NULL,
+  seq INTEGER NOT NULL,
+  updated_at DATETIME NOT NULL
+);
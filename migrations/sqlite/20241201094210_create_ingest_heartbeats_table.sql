@@ -0,0 +1,10 @@
+-- written every few seconds by the process ingesting each relay, so processes that only serve can
+-- tell whether ingest is alive
+CREATE TABLE ingest_heartbeats (
+  relay TEXT PRIMARY KEY NOT NULL,
+  connected BOOLEAN NOT NULL,
+  seq INTEGER,
+  started_at DATETIME NOT NULL,
+  last_commit_at DATETIME,
+  updated_at DATETIME NOT NULL
+);
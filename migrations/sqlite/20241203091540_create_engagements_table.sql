@@ -0,0 +1,7 @@
+-- the likes and reposts counted in posts.engagement, by the uri of the like or repost record, so
+-- deleting one takes it back off its post's count. Only engagement with indexed posts is kept
+CREATE TABLE engagements (
+  uri TEXT PRIMARY KEY NOT NULL,
+  subject TEXT NOT NULL REFERENCES posts (uri) ON DELETE CASCADE
+);
+CREATE INDEX engagements_subject ON engagements (subject);
@@ -0,0 +1,197 @@
+use std::sync::Arc;
+
+use axum::{
+    extract::{Request, State},
+    http::{header::AUTHORIZATION, StatusCode},
+    middleware::{self, Next},
+    response::{IntoResponse, Response},
+    routing::get,
+    Json, Router,
+};
+use serde::Deserialize;
+use serde_json::json;
+use sha2::{Digest, Sha256};
+
+use crate::{
+    atproto::validate_did, models::blocked_links::BlockedLink, storage::Storage, AppState,
+};
+
+/// Routes for managing the blocklists.
+///
+/// Every request needs an `Authorization: Bearer <FEEDGEN_ADMIN_TOKEN>` header. If no admin token
+/// is configured, all of these routes respond with 404.
+///
+/// Blocked authors have to be a did:plc or did:web DID, since handles and other identifiers would
+/// never match. Blocked links are normalised to `host[/path]` before they're stored or deleted,
+/// so either a url or a bare domain can be sent
+pub fn router(state: Arc<AppState>) -> Router<Arc<AppState>> {
+    Router::new()
+        .route(
+            "/admin/blocked-authors",
+            get(list_blocked_authors)
+                .post(create_blocked_author)
+                .delete(delete_blocked_author),
+        )
+        .route(
+            "/admin/blocked-links",
+            get(list_blocked_links)
+                .post(create_blocked_link)
+                .delete(delete_blocked_link),
+        )
+        .route_layer(middleware::from_fn_with_state(state, authenticate))
+}
+
+async fn authenticate(
+    State(state): State<Arc<AppState>>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let Some(token) = &state.config.admin_token else {
+        return StatusCode::NOT_FOUND.into_response();
+    };
+
+    let authorized = request
+        .headers()
+        .get(AUTHORIZATION)
+        .and_then(|header| header.to_str().ok())
+        .and_then(|header| header.strip_prefix("Bearer "))
+        .is_some_and(|bearer| tokens_match(bearer, token));
+
+    if !authorized {
+        return StatusCode::UNAUTHORIZED.into_response();
+    }
+
+    next.run(request).await
+}
+
+/// Compares the digests of both tokens, so that how long the comparison takes doesn't reveal how
+/// much of the token was guessed right, or its length
+fn tokens_match(a: &str, b: &str) -> bool {
+    let (a, b) = (Sha256::digest(a), Sha256::digest(b));
+    a.iter()
+        .zip(b.iter())
+        .fold(0, |diff, (a, b)| diff | (a ^ b))
+        == 0
+}
+
+type Result<T> = std::result::Result<T, (StatusCode, &'static str)>;
+
+fn internal_error(err: anyhow::Error) -> (StatusCode, &'static str) {
+    tracing::error!("{err:?}");
+    (StatusCode::INTERNAL_SERVER_ERROR, "Error")
+}
+
+fn check_did(did: &str) -> Result<()> {
+    validate_did(did).map_err(|_| (StatusCode::BAD_REQUEST, "Invalid did"))
+}
+
+fn normalise_link(url: &str) -> Result<String> {
+    BlockedLink::normalise(url).ok_or((StatusCode::BAD_REQUEST, "Invalid url"))
+}
+
+#[derive(Deserialize)]
+struct BlockAuthor {
+    did: String,
+    reason: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct BlockLink {
+    url: String,
+    reason: Option<String>,
+}
+
+async fn list_blocked_authors(State(state): State<Arc<AppState>>) -> Result<impl IntoResponse> {
+    let blocked = state.db.blocked_authors().await.map_err(internal_error)?;
+
+    Ok(Json(blocked))
+}
+
+async fn create_blocked_author(
+    State(state): State<Arc<AppState>>,
+    Json(block): Json<BlockAuthor>,
+) -> Result<impl IntoResponse> {
+    check_did(&block.did)?;
+    let purged = state
+        .db
+        .block_author(&block.did, block.reason.as_deref())
+        .await
+        .map_err(internal_error)?;
+
+    Ok(Json(json!({ "purged": purged })))
+}
+
+async fn delete_blocked_author(
+    State(state): State<Arc<AppState>>,
+    Json(block): Json<BlockAuthor>,
+) -> Result<impl IntoResponse> {
+    check_did(&block.did)?;
+    state
+        .db
+        .unblock_author(&block.did)
+        .await
+        .map_err(internal_error)?;
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
+async fn list_blocked_links(State(state): State<Arc<AppState>>) -> Result<impl IntoResponse> {
+    let blocked = state.db.blocked_links().await.map_err(internal_error)?;
+
+    Ok(Json(blocked))
+}
+
+async fn create_blocked_link(
+    State(state): State<Arc<AppState>>,
+    Json(block): Json<BlockLink>,
+) -> Result<impl IntoResponse> {
+    let url = normalise_link(&block.url)?;
+    let purged = state
+        .db
+        .block_link(&url, block.reason.as_deref())
+        .await
+        .map_err(internal_error)?;
+
+    Ok(Json(json!({ "purged": purged })))
+}
+
+async fn delete_blocked_link(
+    State(state): State<Arc<AppState>>,
+    Json(block): Json<BlockLink>,
+) -> Result<impl IntoResponse> {
+    let url = normalise_link(&block.url)?;
+    state.db.unblock_link(&url).await.map_err(internal_error)?;
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_tokens_match() {
+        assert!(tokens_match("secret", "secret"));
+        assert!(!tokens_match("secret", "secreT"));
+        assert!(!tokens_match("secret", "secret2"));
+        assert!(!tokens_match("", "secret"));
+    }
+
+    #[test]
+    fn test_check_did() {
+        assert!(check_did("did:plc:z72i7hdynmk6r22z27h6tvur").is_ok());
+        assert!(check_did("did:web:spam.example.com").is_ok());
+
+        for did in [
+            "alice.bsky.social",
+            "did:plc:typo",
+            "at://did:plc:z72i7hdynmk6r22z27h6tvur/app.bsky.feed.post/3lb3tt5kwha2w",
+        ] {
+            assert_eq!(
+                Err((StatusCode::BAD_REQUEST, "Invalid did")),
+                check_did(did),
+                "{did}"
+            );
+        }
+    }
+}
@@ -0,0 +1,144 @@
+use anyhow::Result;
+use atrium_api::app::bsky::feed::get_feed_skeleton::{OutputData, ParametersData};
+use chrono::{DateTime, Utc};
+use futures::future::BoxFuture;
+
+use super::{diversify, invalid_cursor, limit, skeleton, Context, FeedAlgorithm};
+use crate::{feed_context::Reason, storage::Storage, xrpc::XrpcError};
+
+/// Recent posts, newest first
+pub struct Chronological {
+    name: String,
+    description: Option<String>,
+}
+
+impl Chronological {
+    pub fn new(name: String, description: Option<String>) -> Self {
+        Self { name, description }
+    }
+
+    async fn page(
+        &self,
+        ctx: &Context<'_>,
+        params: &ParametersData,
+    ) -> Result<OutputData, XrpcError> {
+        let limit = limit(params);
+        let cursor = params.cursor.as_deref().map(parse_cursor).transpose()?;
+
+        // fetch extra, so the page can still fill up when posts are skipped for being over quota
+        let posts = ctx
+            .db
+            .get_posts(limit.saturating_mul(2), cursor, &ctx.filter)
+            .await?;
+        let (posts, _) = diversify(posts, limit.into(), ctx.max_posts_per_author_per_page);
+
+        // update the cursor to be the timestamp of the last post we return. over quota posts
+        // after it weren't shown, so they start the next page
+        let cursor = posts
+            .last()
+            .map(|post| post.indexed_at.timestamp_micros().to_string());
+
+        Ok(OutputData {
+            cursor,
+            feed: skeleton(posts, &self.name, Reason::Recent),
+        })
+    }
+}
+
+/// The cursor is the time in microseconds the page starts before
+fn parse_cursor(cursor: &str) -> Result<DateTime<Utc>, XrpcError> {
+    cursor
+        .parse()
+        .ok()
+        .and_then(DateTime::from_timestamp_micros)
+        .ok_or_else(|| invalid_cursor(cursor))
+}
+
+impl FeedAlgorithm for Chronological {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn description(&self) -> Option<&str> {
+        self.description.as_deref()
+    }
+
+    fn skeleton<'a>(
+        &'a self,
+        ctx: &'a Context<'a>,
+        params: &'a ParametersData,
+    ) -> BoxFuture<'a, Result<OutputData, XrpcError>> {
+        Box::pin(self.page(ctx, params))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::algos::tests::{context, db, items, params, pending};
+
+    #[tokio::test]
+    async fn test_newest_first() {
+        let db = db(vec![pending("older"), pending("newer")]).await;
+        let algorithm = Chronological::new("music".to_string(), None);
+
+        let output = algorithm
+            .skeleton(&context(&db), &params(None))
+            .await
+            .unwrap();
+
+        assert_eq!(
+            vec![
+                ("newer", "music:spotify:track:recent"),
+                ("older", "music:spotify:track:recent")
+            ],
+            items(&output)
+        );
+    }
+
+    #[tokio::test]
+    async fn test_skips_a_prolific_authors_extra_posts() {
+        let mut posts = vec![pending("first")];
+        for i in 0..7 {
+            posts.push(pending(&format!("spam{i}")));
+        }
+        posts.push(pending("last"));
+        for post in &mut posts[1..8] {
+            post.author = "did:plc:spammer".to_string();
+        }
+        let db = db(posts).await;
+        let algorithm = Chronological::new("music".to_string(), None);
+        let ctx = context(&db);
+
+        let mut pages = vec![];
+        let mut cursor = None;
+        loop {
+            let output = algorithm
+                .skeleton(
+                    &ctx,
+                    &ParametersData {
+                        limit: Some(5.try_into().unwrap()),
+                        ..params(cursor.as_deref())
+                    },
+                )
+                .await
+                .unwrap();
+            let page = items(&output)
+                .into_iter()
+                .map(|(uri, _)| uri.to_string())
+                .collect::<Vec<_>>();
+            if page.is_empty() {
+                break;
+            }
+            pages.push(page);
+            cursor = output.cursor;
+        }
+
+        // at most 3 posts by the spammer per page, and the rest of the page is filled by others
+        assert_eq!(
+            vec![vec!["last", "spam6", "spam5", "spam4", "first"]],
+            pages
+        );
+    }
+}
@@ -0,0 +1,122 @@
+use anyhow::Result;
+use atrium_api::app::bsky::feed::get_feed_skeleton::{OutputData, ParametersData};
+use futures::future::BoxFuture;
+
+use super::{Context, FeedAlgorithm};
+use crate::{feeds::Filters, models::posts::PostFilter, xrpc::XrpcError};
+
+/// Narrows down the posts another algorithm can show.
+///
+/// Exclusions add up with the ones of filters further out. Sites, kinds, and languages replace
+/// theirs when set
+pub struct Filtered<A> {
+    inner: A,
+    filters: Filters,
+}
+
+impl<A: FeedAlgorithm> Filtered<A> {
+    pub fn new(inner: A, filters: Filters) -> Self {
+        Self { inner, filters }
+    }
+}
+
+impl<A: FeedAlgorithm> FeedAlgorithm for Filtered<A> {
+    fn name(&self) -> &str {
+        self.inner.name()
+    }
+
+    fn description(&self) -> Option<&str> {
+        self.inner.description()
+    }
+
+    fn skeleton<'a>(
+        &'a self,
+        ctx: &'a Context<'a>,
+        params: &'a ParametersData,
+    ) -> BoxFuture<'a, Result<OutputData, XrpcError>> {
+        Box::pin(async move {
+            let filters = &self.filters;
+            let outer = &ctx.filter;
+            let excluded_labels = [outer.excluded_labels, &filters.excluded_labels].concat();
+            let excluded_authors = [outer.excluded_authors, &filters.excluded_authors].concat();
+
+            let ctx = Context {
+                filter: PostFilter {
+                    reply_policy: filters.reply_policy.unwrap_or(outer.reply_policy),
+                    excluded_labels: &excluded_labels,
+                    sites: or_outer(&filters.sites, outer.sites),
+                    kinds: or_outer(&filters.kinds, outer.kinds),
+                    languages: or_outer(&filters.languages, outer.languages),
+                    min_engagement: filters.min_engagement.max(outer.min_engagement),
+                    excluded_authors: &excluded_authors,
+                },
+                ..*ctx
+            };
+
+            self.inner.skeleton(&ctx, params).await
+        })
+    }
+}
+
+/// `ours` if it's set, otherwise `outer`
+fn or_outer<'a, T>(ours: &'a [T], outer: &'a [T]) -> &'a [T] {
+    if ours.is_empty() {
+        outer
+    } else {
+        ours
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::{
+        algos::{
+            tests::{context, db, items, params, pending},
+            Chronological,
+        },
+        batch::PendingLink,
+        link_finder::{Kind, Site},
+    };
+
+    #[tokio::test]
+    async fn test_narrows_inner_algorithm() {
+        let mut album = pending("album");
+        album.links = vec![PendingLink {
+            url: "https://x.bandcamp.com/album/album".to_string(),
+            kind: Kind::Album,
+            site: Site::Bandcamp,
+        }];
+        let mut blocked = pending("blocked");
+        blocked.author = "did:plc:blocked".to_string();
+        let db = db(vec![album, pending("track"), blocked]).await;
+
+        let chronological = || Chronological::new("music".to_string(), None);
+        let tracks = Filtered::new(
+            chronological(),
+            Filters {
+                kinds: vec![Kind::Track],
+                ..Default::default()
+            },
+        );
+        let unblocked_tracks = Filtered::new(
+            tracks,
+            Filters {
+                excluded_authors: vec!["did:plc:blocked".to_string()],
+                ..Default::default()
+            },
+        );
+
+        let output = unblocked_tracks
+            .skeleton(&context(&db), &params(None))
+            .await
+            .unwrap();
+
+        assert_eq!("music", unblocked_tracks.name());
+        assert_eq!(
+            vec![("track", "music:spotify:track:recent")],
+            items(&output)
+        );
+    }
+}
@@ -0,0 +1,628 @@
+//! Feed algorithms.
+//!
+//! Every feed is served by a [`FeedAlgorithm`], looked up by rkey in the [`Registry`] on
+//! [`AppState`]. Algorithms get the db and the posts they're allowed to show through a [`Context`],
+//! so they can be tested against an in-memory db, and wrapped by others like [`Filtered`]
+
+mod chronological;
+mod filtered;
+mod personalised;
+mod trending;
+
+use std::{collections::HashMap, time::Duration};
+
+use anyhow::Result;
+use atrium_api::{
+    app::bsky::feed::{
+        defs::{
+            SkeletonFeedPost, SkeletonFeedPostData, SkeletonFeedPostReasonRefs,
+            SkeletonReasonPinData,
+        },
+        get_feed_skeleton::{OutputData, ParametersData},
+    },
+    types::{Object, Union},
+};
+use chrono::{DateTime, Utc};
+use futures::future::BoxFuture;
+
+use crate::{
+    feed_context::{FeedContext, Reason},
+    feeds::{Algorithm, Feeds},
+    models::posts::{Post, PostFilter},
+    storage::{Database, Storage},
+    xrpc::XrpcError,
+    AppState,
+};
+
+pub use chronological::Chronological;
+pub use filtered::Filtered;
+pub use personalised::Personalised;
+pub use trending::Trending;
+
+/// Which replies and quotes a feed should include
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, sqlx::Type)]
+#[sqlx(rename_all = "snake_case")]
+pub enum ReplyPolicy {
+    /// Include every post, regardless of whether it's a reply or quote
+    #[default]
+    All,
+    /// Exclude replies, but keep quotes
+    ExcludeReplies,
+    /// Only include posts that are neither replies nor quotes
+    TopLevel,
+    /// Include replies only if the root of the thread is itself a music post
+    MusicRoot,
+}
+
+impl ReplyPolicy {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::All => "all",
+            Self::ExcludeReplies => "exclude_replies",
+            Self::TopLevel => "top_level",
+            Self::MusicRoot => "music_root",
+        }
+    }
+}
+
+impl std::str::FromStr for ReplyPolicy {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "all" => Ok(Self::All),
+            "exclude_replies" => Ok(Self::ExcludeReplies),
+            "top_level" => Ok(Self::TopLevel),
+            "music_root" => Ok(Self::MusicRoot),
+            _ => Err(anyhow::anyhow!("unknown reply policy {s}")),
+        }
+    }
+}
+
+/// Builds the pages of a feed.
+///
+/// `skeleton` returns a boxed future, so that algorithms can be stored in the [`Registry`] as
+/// trait objects
+pub trait FeedAlgorithm: Send + Sync {
+    /// The rkey of the feed this serves. Eg: `music`
+    fn name(&self) -> &str;
+
+    fn description(&self) -> Option<&str>;
+
+    /// Builds the page of the feed after `params.cursor`. Pinned posts are added afterwards.
+    ///
+    /// Cursors this algorithm didn't make are an [`XrpcError::InvalidRequest`]
+    fn skeleton<'a>(
+        &'a self,
+        ctx: &'a Context<'a>,
+        params: &'a ParametersData,
+    ) -> BoxFuture<'a, Result<OutputData, XrpcError>>;
+}
+
+/// What an algorithm needs to build a page
+pub struct Context<'a> {
+    pub db: &'a Database,
+    /// The DID of the account requesting the feed, if we know it
+    pub viewer: Option<&'a str>,
+    /// Which posts the feed can show
+    pub filter: PostFilter<'a>,
+    /// The maximum amount of posts by a single author in a page
+    pub max_posts_per_author_per_page: usize,
+    /// How long it takes for a viewer's "show more" and "show less" feedback to lose half its weight
+    pub preference_half_life: Duration,
+}
+
+/// Every algorithm we serve, by name
+#[derive(Default)]
+pub struct Registry {
+    algorithms: Vec<Box<dyn FeedAlgorithm>>,
+}
+
+impl Registry {
+    /// The algorithms for the feeds in the config
+    pub fn from_feeds(feeds: &Feeds) -> Self {
+        let mut registry = Self::default();
+
+        for feed in feeds.iter() {
+            let name = feed.rkey.clone();
+            let description = feed.description.clone();
+            let filters = feed.filters.clone();
+            match feed.algorithm {
+                Algorithm::Chronological => registry.register(Filtered::new(
+                    Chronological::new(name, description),
+                    filters,
+                )),
+                Algorithm::Personalised => {
+                    registry.register(Filtered::new(Personalised::new(name, description), filters))
+                }
+                Algorithm::Trending => {
+                    registry.register(Filtered::new(Trending::new(name, description), filters))
+                }
+            }
+        }
+
+        registry
+    }
+
+    /// Adds `algorithm`, replacing any with the same name
+    pub fn register(&mut self, algorithm: impl FeedAlgorithm + 'static) {
+        self.algorithms
+            .retain(|existing| existing.name() != algorithm.name());
+        self.algorithms.push(Box::new(algorithm));
+    }
+
+    pub fn get(&self, name: &str) -> Option<&dyn FeedAlgorithm> {
+        self.algorithms
+            .iter()
+            .find(|algorithm| algorithm.name() == name)
+            .map(Box::as_ref)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &dyn FeedAlgorithm> {
+        self.algorithms.iter().map(Box::as_ref)
+    }
+}
+
+/// Builds a page of the feed with record key `rkey`. `viewer` is the DID of the account requesting
+/// it, if we know it
+pub async fn feed(
+    rkey: &str,
+    state: &AppState,
+    params: &ParametersData,
+    viewer: Option<&str>,
+) -> Result<OutputData, XrpcError> {
+    let Some(algorithm) = state.algos.get(rkey) else {
+        return Err(XrpcError::UnknownFeed);
+    };
+
+    let ctx = Context {
+        db: &state.db,
+        viewer,
+        filter: PostFilter {
+            reply_policy: state.config.reply_policy,
+            excluded_labels: &state.config.excluded_labels,
+            ..Default::default()
+        },
+        max_posts_per_author_per_page: state.config.max_posts_per_author_per_page,
+        preference_half_life: state.config.preference_half_life,
+    };
+
+    // pins count toward the limit, so the algorithm is asked for a page that leaves room for them
+    let pins = pins(&state.config.pinned_posts, params);
+    let params = &ParametersData {
+        limit: Some(
+            (limit(params) - pins.len() as u8)
+                .try_into()
+                .expect("pins leave room for at least one post"),
+        ),
+        ..params.clone()
+    };
+
+    let mut output = algorithm
+        .skeleton(&ctx, params)
+        .await
+        .map_err(|err| match err {
+            XrpcError::InternalServerError(err) => {
+                XrpcError::InternalServerError(err.context(format!("could not build feed {rkey}")))
+            }
+            err => err,
+        })?;
+    pin(&mut output, rkey, &state.config.pinned_posts, pins);
+
+    Ok(output)
+}
+
+/// How many posts a page has if the request doesn't say, as in the lexicon
+const DEFAULT_LIMIT: u8 = 50;
+
+/// How many posts a page has
+fn limit(params: &ParametersData) -> u8 {
+    params
+        .limit
+        .map(|limit| limit.into())
+        .unwrap_or(DEFAULT_LIMIT)
+}
+
+fn invalid_cursor(cursor: &str) -> XrpcError {
+    XrpcError::InvalidRequest(format!("Malformed cursor {cursor}"))
+}
+
+/// How many candidates are ranked at once by ranked algorithms
+const CANDIDATES: u8 = 100;
+
+/// The window of candidates a page of a ranked algorithm comes from.
+///
+/// Posts are ranked in windows of [`CANDIDATES`] posts. The cursor is `{start}:{offset}`, where
+/// `start` is the time in microseconds the window starts before, and `offset` how many of its
+/// ranked posts were already shown
+struct Window {
+    start: DateTime<Utc>,
+    offset: usize,
+}
+
+impl Window {
+    /// The window in the cursor, or a new one starting now for the first page
+    fn from_cursor(cursor: Option<&str>) -> Result<Self, XrpcError> {
+        let Some(cursor) = cursor else {
+            return Ok(Self {
+                start: Utc::now(),
+                offset: 0,
+            });
+        };
+
+        cursor
+            .split_once(':')
+            .and_then(|(start, offset)| {
+                Some(Self {
+                    start: DateTime::from_timestamp_micros(start.parse().ok()?)?,
+                    offset: offset.parse().ok()?,
+                })
+            })
+            .ok_or_else(|| invalid_cursor(cursor))
+    }
+}
+
+/// Ranks a window of candidates with `rank`, and returns the page of it after the cursor
+async fn ranked(
+    ctx: &Context<'_>,
+    params: &ParametersData,
+    name: &str,
+    window: Window,
+    reason: Reason,
+    rank: impl FnOnce(Vec<Post>, DateTime<Utc>) -> Vec<Post>,
+) -> Result<OutputData, XrpcError> {
+    let candidates = ctx
+        .db
+        .get_posts(CANDIDATES, Some(window.start), &ctx.filter)
+        .await?;
+
+    let oldest = candidates.last().map(|post| post.indexed_at);
+    let window_is_full = candidates.len() == usize::from(CANDIDATES);
+    let ranked = rank(candidates, window.start);
+    let remaining = ranked.into_iter().skip(window.offset).collect::<Vec<_>>();
+    let available = remaining.len();
+
+    let (posts, used) = diversify(
+        remaining,
+        limit(params).into(),
+        ctx.max_posts_per_author_per_page,
+    );
+
+    let cursor = if used < available {
+        Some(format!(
+            "{}:{}",
+            window.start.timestamp_micros(),
+            window.offset + used
+        ))
+    } else if window_is_full {
+        oldest.map(|oldest| format!("{}:0", oldest.timestamp_micros()))
+    } else {
+        None
+    };
+
+    Ok(OutputData {
+        cursor,
+        feed: skeleton(posts, name, reason),
+    })
+}
+
+/// Turns posts into feed items, with a feed context saying they came from `name`
+fn skeleton(posts: Vec<Post>, name: &str, reason: Reason) -> Vec<SkeletonFeedPost> {
+    posts
+        .into_iter()
+        .map(|post| {
+            let context = FeedContext {
+                algo: name.to_string(),
+                site: post.site,
+                kind: post.kind,
+                reason,
+            };
+            Object::from(SkeletonFeedPostData {
+                post: post.uri,
+                feed_context: Some(context.to_string()),
+                reason: None,
+            })
+        })
+        .collect()
+}
+
+/// The pinned posts that go on top of the page. Only the first page has them, and they take at
+/// most all but one of its posts, so that the feed itself still moves the cursor along
+fn pins<'a>(pinned: &'a [String], params: &ParametersData) -> &'a [String] {
+    if params.cursor.is_some() {
+        return &[];
+    }
+
+    &pinned[..pinned.len().min(usize::from(limit(params)) - 1)]
+}
+
+/// Removes the `pinned` posts from the feed, and puts `pins` at the top of it
+fn pin(output: &mut OutputData, algo: &str, pinned: &[String], pins: &[String]) {
+    output.feed.retain(|item| !pinned.contains(&item.post));
+
+    if !pins.is_empty() {
+        let context = FeedContext {
+            algo: algo.to_string(),
+            site: None,
+            kind: None,
+            reason: Reason::Pinned,
+        };
+        let pins = pins.iter().map(|uri| {
+            Object::from(SkeletonFeedPostData {
+                post: uri.clone(),
+                feed_context: Some(context.to_string()),
+                reason: Some(Union::Refs(SkeletonFeedPostReasonRefs::SkeletonReasonPin(
+                    Box::new(SkeletonReasonPinData {}.into()),
+                ))),
+            })
+        });
+        output.feed.splice(0..0, pins);
+    }
+}
+
+/// Takes up to `limit` posts, in order, skipping posts by authors that already have
+/// `max_per_author` posts in the page.
+///
+/// Also returns how many of `posts` the page covers, up to and including its last post. Skipped
+/// posts before that are dropped for good, but the ones after it start the next page
+fn diversify(posts: Vec<Post>, limit: usize, max_per_author: usize) -> (Vec<Post>, usize) {
+    let mut counts = HashMap::<String, usize>::new();
+    let mut page = vec![];
+    let mut covered = 0;
+
+    for (i, post) in posts.into_iter().enumerate() {
+        if page.len() == limit {
+            break;
+        }
+
+        let count = counts.entry(post.author.clone()).or_default();
+        *count += 1;
+        if *count > max_per_author {
+            continue;
+        }
+        page.push(post);
+        covered = i + 1;
+    }
+
+    (page, covered)
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+
+    use crate::{
+        batch::{PendingLink, PendingPost, Write},
+        link_finder::{Kind, Site},
+        storage::SqliteStorage,
+    };
+
+    pub fn post(uri: &str, author: &str) -> Post {
+        Post {
+            uri: uri.to_string(),
+            cid: "cid".to_string(),
+            author: author.to_string(),
+            indexed_at: Utc::now(),
+            reply_root: None,
+            reply_parent: None,
+            quote: None,
+            site: None,
+            kind: None,
+            engagement: 0,
+        }
+    }
+
+    /// A post by its own author, with a link to a spotify track
+    pub fn pending(uri: &str) -> PendingPost {
+        PendingPost {
+            uri: uri.to_string(),
+            cid: format!("cid-{uri}"),
+            author: format!("did:plc:{uri}"),
+            reply_root: None,
+            reply_parent: None,
+            quote: None,
+            fingerprint: None,
+            langs: vec![],
+            links: vec![PendingLink {
+                url: format!("https://open.spotify.com/track/{uri}"),
+                kind: Kind::Track,
+                site: Site::Spotify,
+            }],
+            urls: vec![format!("https://open.spotify.com/track/{uri}")],
+        }
+    }
+
+    /// An in-memory db with `posts`, written oldest first
+    pub async fn db(posts: Vec<PendingPost>) -> Database {
+        let storage = SqliteStorage::memory().await;
+        for post in posts {
+            storage.write_batch(&[Write::Create(post)]).await.unwrap();
+            // so every post gets its own indexed_at
+            tokio::time::sleep(Duration::from_millis(2)).await;
+        }
+        Database::Sqlite(storage)
+    }
+
+    pub fn context(db: &Database) -> Context<'_> {
+        Context {
+            db,
+            viewer: None,
+            filter: PostFilter::default(),
+            max_posts_per_author_per_page: 3,
+            preference_half_life: Duration::from_secs(14 * 24 * 60 * 60),
+        }
+    }
+
+    pub fn params(cursor: Option<&str>) -> ParametersData {
+        ParametersData {
+            feed: "at://did:plc:pub6kzfhyqdrwv3lm2xe5a7t/app.bsky.feed.generator/music".to_string(),
+            limit: None,
+            cursor: cursor.map(String::from),
+        }
+    }
+
+    /// The uri and feed context of every item
+    pub fn items(output: &OutputData) -> Vec<(&str, &str)> {
+        output
+            .feed
+            .iter()
+            .map(|item| {
+                (
+                    item.post.as_str(),
+                    item.feed_context.as_deref().unwrap_or_default(),
+                )
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_diversify_limits_posts_per_author() {
+        let posts = vec![
+            post("1", "spammer"),
+            post("2", "spammer"),
+            post("3", "spammer"),
+            post("4", "someone"),
+            post("5", "spammer"),
+            post("6", "else"),
+        ];
+
+        let (posts, covered) = diversify(posts, 10, 2);
+
+        let uris = posts.into_iter().map(|post| post.uri).collect::<Vec<_>>();
+        assert_eq!(vec!["1", "2", "4", "6"], uris);
+        assert_eq!(6, covered);
+    }
+
+    #[test]
+    fn test_diversify_fills_the_page_after_an_over_quota_post() {
+        let posts = vec![
+            post("1", "spammer"),
+            post("2", "spammer"),
+            post("3", "someone"),
+            post("4", "else"),
+            post("5", "spammer"),
+        ];
+
+        let (posts, covered) = diversify(posts, 2, 1);
+
+        let uris = posts.into_iter().map(|post| post.uri).collect::<Vec<_>>();
+        assert_eq!(vec!["1", "3"], uris);
+        // 4 and 5 weren't looked at, so the next page starts at 4
+        assert_eq!(3, covered);
+
+        let posts = vec![
+            post("1", "spammer"),
+            post("2", "someone"),
+            post("3", "spammer"),
+        ];
+        let (_, covered) = diversify(posts, 10, 1);
+        // the over quota post at the end wasn't shown, so the next page starts at it
+        assert_eq!(2, covered);
+    }
+
+    fn output(uris: &[&str]) -> OutputData {
+        OutputData {
+            cursor: None,
+            feed: uris
+                .iter()
+                .map(|uri| {
+                    Object::from(SkeletonFeedPostData {
+                        post: uri.to_string(),
+                        feed_context: None,
+                        reason: None,
+                    })
+                })
+                .collect(),
+        }
+    }
+
+    fn uris(output: &OutputData) -> Vec<&str> {
+        output.feed.iter().map(|item| item.post.as_str()).collect()
+    }
+
+    #[test]
+    fn test_pins_go_on_top_of_first_page() {
+        let mut output = output(&["1", "pinned", "2"]);
+
+        let pinned = ["pinned".to_string()];
+        pin(&mut output, "music", &pinned, &pinned);
+
+        assert_eq!(vec!["pinned", "1", "2"], uris(&output));
+        assert!(matches!(
+            output.feed[0].reason,
+            Some(Union::Refs(SkeletonFeedPostReasonRefs::SkeletonReasonPin(
+                _
+            )))
+        ));
+        assert_eq!(
+            Some("music:-:-:pin"),
+            output.feed[0].feed_context.as_deref()
+        );
+        assert!(output.feed[1].reason.is_none());
+    }
+
+    #[test]
+    fn test_pins_leave_room_for_the_feed() {
+        let pinned = ["1".to_string(), "2".to_string(), "3".to_string()];
+
+        assert_eq!(&pinned, pins(&pinned, &params(None)));
+        assert!(pins(&pinned, &params(Some("123"))).is_empty());
+
+        let params = ParametersData {
+            limit: Some(3.try_into().unwrap()),
+            ..params(None)
+        };
+        assert_eq!(&pinned[..2], pins(&pinned, &params));
+    }
+
+    #[test]
+    fn test_pins_are_not_repeated_on_later_pages() {
+        let mut output = output(&["1", "pinned", "2"]);
+
+        pin(&mut output, "music", &["pinned".to_string()], &[]);
+
+        assert_eq!(vec!["1", "2"], uris(&output));
+    }
+
+    #[test]
+    fn test_diversify_respects_limit() {
+        let posts = vec![post("1", "a"), post("2", "b"), post("3", "c")];
+
+        let (posts, covered) = diversify(posts, 2, 1);
+
+        assert_eq!(2, posts.len());
+        assert_eq!(2, covered);
+    }
+
+    #[test]
+    fn test_registry() {
+        let mut registry = Registry::from_feeds(&Feeds::default());
+        assert_eq!(
+            vec!["music", "music-for-you"],
+            registry.iter().map(|algo| algo.name()).collect::<Vec<_>>()
+        );
+
+        registry.register(Trending::new("music".to_string(), None));
+
+        assert_eq!(2, registry.iter().count());
+        assert_eq!(None, registry.get("music").unwrap().description());
+    }
+
+    #[tokio::test]
+    async fn test_unknown_feed() {
+        let state = AppState {
+            config: crate::server::test_config(),
+            db: db(vec![]).await,
+            algos: Registry::default(),
+            metrics: crate::metrics::test_handle(),
+            firehose: Default::default(),
+            auth: Default::default(),
+        };
+
+        let err = feed("music", &state, &params(None), None)
+            .await
+            .unwrap_err();
+
+        assert!(matches!(err, XrpcError::UnknownFeed));
+    }
+}
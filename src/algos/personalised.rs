@@ -0,0 +1,203 @@
+use anyhow::Result;
+use atrium_api::app::bsky::feed::get_feed_skeleton::{OutputData, ParametersData};
+use chrono::{DateTime, Utc};
+use futures::future::BoxFuture;
+
+use super::{ranked, Context, FeedAlgorithm, Window};
+use crate::{
+    feed_context::Reason, feedback::ViewerPreferences, models::posts::Post, xrpc::XrpcError,
+};
+
+/// How many hours a single point of preference moves a post up or down
+const PREFERENCE_WEIGHT_HOURS: f64 = 6.0;
+
+/// Recent posts, ranked by the viewer's "show more" and "show less" feedback
+pub struct Personalised {
+    name: String,
+    description: Option<String>,
+}
+
+impl Personalised {
+    pub fn new(name: String, description: Option<String>) -> Self {
+        Self { name, description }
+    }
+
+    async fn page(
+        &self,
+        ctx: &Context<'_>,
+        params: &ParametersData,
+    ) -> Result<OutputData, XrpcError> {
+        let window = Window::from_cursor(params.cursor.as_deref())?;
+        let preferences = match ctx.viewer {
+            Some(viewer) => {
+                ViewerPreferences::load(ctx.db, viewer, window.start, ctx.preference_half_life)
+                    .await?
+            }
+            None => ViewerPreferences::default(),
+        };
+
+        ranked(
+            ctx,
+            params,
+            &self.name,
+            window,
+            Reason::Personalised,
+            |posts, now| rank(posts, &preferences, now),
+        )
+        .await
+    }
+}
+
+impl FeedAlgorithm for Personalised {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn description(&self) -> Option<&str> {
+        self.description.as_deref()
+    }
+
+    fn skeleton<'a>(
+        &'a self,
+        ctx: &'a Context<'a>,
+        params: &'a ParametersData,
+    ) -> BoxFuture<'a, Result<OutputData, XrpcError>> {
+        Box::pin(self.page(ctx, params))
+    }
+}
+
+/// Sorts posts by how recent they are, moved up or down by the viewer's preferences.
+///
+/// `now` is passed in so that the ranking is stable across pages of the same window
+fn rank(posts: Vec<Post>, preferences: &ViewerPreferences, now: DateTime<Utc>) -> Vec<Post> {
+    let mut scored = posts
+        .into_iter()
+        .map(|post| {
+            let age = (now - post.indexed_at).num_seconds() as f64 / 3600.0;
+            let score = preferences.score(&post) * PREFERENCE_WEIGHT_HOURS - age;
+            (score, post)
+        })
+        .collect::<Vec<_>>();
+
+    scored.sort_by(|(a_score, a), (b_score, b)| {
+        b_score
+            .total_cmp(a_score)
+            .then_with(|| b.indexed_at.cmp(&a.indexed_at))
+            .then_with(|| b.uri.cmp(&a.uri))
+    });
+
+    scored.into_iter().map(|(_, post)| post).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::time::Duration;
+
+    use atrium_api::app::bsky::feed::defs::{REQUEST_LESS, REQUEST_MORE};
+
+    use crate::{
+        algos::tests::post,
+        feedback,
+        link_finder::{Kind, Site},
+        storage::{SqliteStorage, Storage},
+    };
+
+    const DAY: Duration = Duration::from_secs(24 * 60 * 60);
+
+    fn now() -> DateTime<Utc> {
+        DateTime::from_timestamp(1_700_000_000, 0).unwrap()
+    }
+
+    /// A spotify track posted an hour ago, and a post without links by someone else two hours ago
+    fn candidates() -> Vec<Post> {
+        let mut newer = post("at://did:plc:newer/app.bsky.feed.post/a", "did:plc:newer");
+        newer.indexed_at = now() - chrono::Duration::hours(1);
+        newer.site = Some(Site::Spotify);
+        newer.kind = Some(Kind::Track);
+
+        let mut older = post("at://did:plc:older/app.bsky.feed.post/b", "did:plc:older");
+        older.indexed_at = now() - chrono::Duration::hours(2);
+
+        vec![older, newer]
+    }
+
+    async fn ranked(storage: &SqliteStorage) -> Vec<String> {
+        let preferences = ViewerPreferences::load(storage, "did:plc:viewer", now(), DAY)
+            .await
+            .unwrap();
+
+        rank(candidates(), &preferences, now())
+            .into_iter()
+            .map(|post| post.author)
+            .collect()
+    }
+
+    async fn feedback(storage: &SqliteStorage, event: &str) {
+        let adjustments = feedback::adjustments(
+            "did:plc:viewer",
+            "at://did:plc:newer/app.bsky.feed.post/a",
+            event,
+            Some("music-for-you:spotify:track:personal"),
+        );
+        storage
+            .store_interactions(&[], &adjustments, now(), DAY)
+            .await
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_without_feedback_newest_is_first() {
+        let storage = SqliteStorage::memory().await;
+
+        assert_eq!(
+            vec!["did:plc:newer", "did:plc:older"],
+            ranked(&storage).await
+        );
+    }
+
+    #[tokio::test]
+    async fn test_show_less_moves_post_down() {
+        let storage = SqliteStorage::memory().await;
+
+        feedback(&storage, REQUEST_LESS).await;
+
+        assert_eq!(
+            vec!["did:plc:older", "did:plc:newer"],
+            ranked(&storage).await
+        );
+    }
+
+    #[tokio::test]
+    async fn test_show_more_undoes_show_less() {
+        let storage = SqliteStorage::memory().await;
+
+        feedback(&storage, REQUEST_LESS).await;
+        feedback(&storage, REQUEST_MORE).await;
+
+        assert_eq!(
+            vec!["did:plc:newer", "did:plc:older"],
+            ranked(&storage).await
+        );
+    }
+
+    #[tokio::test]
+    async fn test_show_less_wears_off() {
+        let storage = SqliteStorage::memory().await;
+
+        feedback(&storage, REQUEST_LESS).await;
+
+        // a month later, the feedback has decayed enough for recency to win again
+        let later = now() + chrono::Duration::days(30);
+        let preferences = ViewerPreferences::load(&storage, "did:plc:viewer", later, DAY)
+            .await
+            .unwrap();
+        let authors = rank(candidates(), &preferences, later)
+            .into_iter()
+            .map(|post| post.author)
+            .collect::<Vec<_>>();
+
+        assert_eq!(vec!["did:plc:newer", "did:plc:older"], authors);
+    }
+}
@@ -0,0 +1,130 @@
+use anyhow::Result;
+use atrium_api::app::bsky::feed::get_feed_skeleton::{OutputData, ParametersData};
+use chrono::{DateTime, Utc};
+use futures::future::BoxFuture;
+
+use super::{ranked, Context, FeedAlgorithm, Window};
+use crate::{feed_context::Reason, models::posts::Post, xrpc::XrpcError};
+
+/// How quickly posts sink as they get older
+const GRAVITY: f64 = 1.5;
+
+/// Recent posts, ranked by how many likes and reposts they got for their age
+pub struct Trending {
+    name: String,
+    description: Option<String>,
+}
+
+impl Trending {
+    pub fn new(name: String, description: Option<String>) -> Self {
+        Self { name, description }
+    }
+}
+
+impl FeedAlgorithm for Trending {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn description(&self) -> Option<&str> {
+        self.description.as_deref()
+    }
+
+    fn skeleton<'a>(
+        &'a self,
+        ctx: &'a Context<'a>,
+        params: &'a ParametersData,
+    ) -> BoxFuture<'a, Result<OutputData, XrpcError>> {
+        Box::pin(async move {
+            let window = Window::from_cursor(params.cursor.as_deref())?;
+            ranked(ctx, params, &self.name, window, Reason::Trending, rank).await
+        })
+    }
+}
+
+/// Sorts posts by their likes and reposts, sinking as they get older.
+///
+/// `now` is passed in so that the ranking is stable across pages of the same window
+fn rank(posts: Vec<Post>, now: DateTime<Utc>) -> Vec<Post> {
+    let mut scored = posts
+        .into_iter()
+        .map(|post| {
+            let age = (now - post.indexed_at).num_seconds().max(0) as f64 / 3600.0;
+            let score = post.engagement as f64 / (age + 2.0).powf(GRAVITY);
+            (score, post)
+        })
+        .collect::<Vec<_>>();
+
+    scored.sort_by(|(a_score, a), (b_score, b)| {
+        b_score
+            .total_cmp(a_score)
+            .then_with(|| b.indexed_at.cmp(&a.indexed_at))
+            .then_with(|| b.uri.cmp(&a.uri))
+    });
+
+    scored.into_iter().map(|(_, post)| post).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::{
+        algos::tests::{context, db, items, params, pending, post},
+        batch::Write,
+        storage::Storage,
+    };
+
+    #[test]
+    fn test_weighs_engagement_against_age() {
+        let now = Utc::now();
+        let mut fresh = post("fresh", "a");
+        fresh.engagement = 2;
+        let mut popular = post("popular", "b");
+        popular.engagement = 40;
+        popular.indexed_at = now - chrono::Duration::hours(10);
+        let mut stale = post("stale", "c");
+        stale.engagement = 40;
+        stale.indexed_at = now - chrono::Duration::days(7);
+        let ignored = post("ignored", "d");
+
+        let uris = rank(vec![ignored, stale, fresh, popular], now)
+            .into_iter()
+            .map(|post| post.uri)
+            .collect::<Vec<_>>();
+
+        assert_eq!(vec!["popular", "fresh", "stale", "ignored"], uris);
+    }
+
+    #[tokio::test]
+    async fn test_most_engaged_first() {
+        let db = db(vec![pending("liked"), pending("ignored")]).await;
+        db.write_batch(&[
+            Write::Engage {
+                uri: "like".to_string(),
+                subject: "liked".to_string(),
+            },
+            Write::Engage {
+                uri: "repost".to_string(),
+                subject: "liked".to_string(),
+            },
+        ])
+        .await
+        .unwrap();
+        let algorithm = Trending::new("hot".to_string(), None);
+
+        let output = algorithm
+            .skeleton(&context(&db), &params(None))
+            .await
+            .unwrap();
+
+        assert_eq!(
+            vec![
+                ("liked", "hot:spotify:track:trending"),
+                ("ignored", "hot:spotify:track:trending")
+            ],
+            items(&output)
+        );
+        assert_eq!(None, output.cursor);
+    }
+}
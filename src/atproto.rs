@@ -1,32 +1,456 @@
-#[derive(Debug, Clone)]
+/// The longest an at-uri can be
+const MAX_URI_LENGTH: usize = 8 * 1024;
+
+/// A repo, collection, or record uri, like
+/// `at://did:plc:z72i7hdynmk6r22z27h6tvur/app.bsky.feed.post/3k2la`.
+///
+/// Only the restricted syntax lexicons use is accepted: `at://`, an authority, and optionally a
+/// collection and then a record key, without a query or fragment
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub struct AtUri<'a> {
-    pub did: &'a str,
-    pub collection: &'a str,
-    pub rkey: &'a str,
+    /// Either a DID or a handle
+    pub authority: &'a str,
+    /// The NSID of the collection. Eg: `app.bsky.feed.post`
+    pub collection: Option<&'a str>,
+    /// Only set if `collection` is
+    pub rkey: Option<&'a str>,
 }
 
 impl<'a> AtUri<'a> {
-    pub fn from_str(s: &'a str) -> Result<AtUri<'a>, &'static str> {
-        let parts = s
-            .strip_prefix("at://")
-            .ok_or(r#"record uri must start with "at://""#)?
-            .splitn(3, '/')
-            .collect::<Vec<_>>();
+    pub fn parse(s: &'a str) -> Result<AtUri<'a>, AtUriError> {
+        if s.len() > MAX_URI_LENGTH {
+            return Err(AtUriError::TooLong);
+        }
+        let rest = s.strip_prefix("at://").ok_or(AtUriError::Scheme)?;
+        if rest.contains(['?', '#']) {
+            return Err(AtUriError::QueryOrFragment);
+        }
 
-        if !parts[0].starts_with("did:plc:") {
-            return Err(r#"record uri must start with "at://did:plc:""#);
+        let mut segments = rest.split('/');
+        let authority = segments.next().unwrap_or_default();
+        let collection = segments.next();
+        let rkey = segments.next();
+        if segments.next().is_some() {
+            return Err(AtUriError::TooManySegments);
+        }
+
+        if authority.starts_with("did:") {
+            validate_did(authority).map_err(AtUriError::Did)?;
+        } else if !is_valid_handle(authority) {
+            return Err(AtUriError::Authority);
+        }
+        if collection.is_some_and(|collection| !is_valid_nsid(collection)) {
+            return Err(AtUriError::Collection);
+        }
+        if rkey.is_some_and(|rkey| !is_valid_rkey(rkey)) {
+            return Err(AtUriError::RecordKey);
         }
 
         Ok(Self {
-            did: parts[0],
-            collection: parts[1],
-            rkey: parts[2],
+            authority,
+            collection,
+            rkey,
         })
     }
 }
 
 impl std::fmt::Display for AtUri<'_> {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(f, "at://{}/{}/{}", self.did, self.collection, self.rkey)
+        write!(f, "at://{}", self.authority)?;
+        if let Some(collection) = self.collection {
+            write!(f, "/{collection}")?;
+            if let Some(rkey) = self.rkey {
+                write!(f, "/{rkey}")?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// An [`AtUri`] that owns its parts
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AtUriBuf {
+    pub authority: String,
+    pub collection: Option<String>,
+    pub rkey: Option<String>,
+}
+
+impl AtUriBuf {
+    pub fn as_uri(&self) -> AtUri<'_> {
+        AtUri {
+            authority: &self.authority,
+            collection: self.collection.as_deref(),
+            rkey: self.rkey.as_deref(),
+        }
+    }
+}
+
+impl std::str::FromStr for AtUriBuf {
+    type Err = AtUriError;
+
+    fn from_str(s: &str) -> Result<Self, AtUriError> {
+        AtUri::parse(s).map(AtUriBuf::from)
+    }
+}
+
+impl From<AtUri<'_>> for AtUriBuf {
+    fn from(uri: AtUri<'_>) -> Self {
+        Self {
+            authority: uri.authority.to_string(),
+            collection: uri.collection.map(String::from),
+            rkey: uri.rkey.map(String::from),
+        }
+    }
+}
+
+impl std::fmt::Display for AtUriBuf {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        self.as_uri().fmt(f)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AtUriError {
+    TooLong,
+    Scheme,
+    QueryOrFragment,
+    TooManySegments,
+    Did(DidError),
+    Authority,
+    Collection,
+    RecordKey,
+}
+
+impl std::fmt::Display for AtUriError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            AtUriError::TooLong => write!(f, "at-uri is longer than {MAX_URI_LENGTH} bytes"),
+            AtUriError::Scheme => write!(f, r#"at-uri must start with "at://""#),
+            AtUriError::QueryOrFragment => write!(f, "at-uri can't have a query or fragment"),
+            AtUriError::TooManySegments => {
+                write!(
+                    f,
+                    "at-uri can only have a collection and record key after the authority"
+                )
+            }
+            AtUriError::Did(err) => err.fmt(f),
+            AtUriError::Authority => write!(f, "at-uri authority is neither a DID nor a handle"),
+            AtUriError::Collection => write!(f, "at-uri collection is not a valid NSID"),
+            AtUriError::RecordKey => write!(f, "at-uri record key is not valid"),
+        }
+    }
+}
+
+impl std::error::Error for AtUriError {}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DidError {
+    Syntax,
+    /// Only did:plc and did:web are supported by atproto
+    Method,
+    Plc,
+    Web,
+}
+
+impl std::fmt::Display for DidError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            DidError::Syntax => write!(f, "not a DID"),
+            DidError::Method => write!(f, "only did:plc and did:web DIDs are supported"),
+            DidError::Plc => write!(f, "did:plc identifiers are 24 characters of base32"),
+            DidError::Web => write!(f, "did:web identifiers are a hostname, without a path"),
+        }
+    }
+}
+
+impl std::error::Error for DidError {}
+
+/// Checks that `did` is a valid did:plc or did:web DID
+pub fn validate_did(did: &str) -> Result<(), DidError> {
+    let valid_syntax = did.len() <= 2048
+        && did
+            .bytes()
+            .all(|b| b.is_ascii_alphanumeric() || b".:_%-".contains(&b))
+        && !did.ends_with([':', '%']);
+    let Some((method, identifier)) = did
+        .strip_prefix("did:")
+        .and_then(|did| did.split_once(':'))
+        .filter(|_| valid_syntax)
+    else {
+        return Err(DidError::Syntax);
+    };
+
+    match method {
+        "plc" => {
+            let base32 = |b: u8| b.is_ascii_lowercase() || (b'2'..=b'7').contains(&b);
+            if identifier.len() == 24 && identifier.bytes().all(base32) {
+                Ok(())
+            } else {
+                Err(DidError::Plc)
+            }
+        }
+        "web" => {
+            // a port is only allowed for local development
+            let host = match identifier.split_once("%3A") {
+                Some(("localhost", port)) if !port.is_empty() => {
+                    return match port.bytes().all(|b| b.is_ascii_digit()) {
+                        true => Ok(()),
+                        false => Err(DidError::Web),
+                    }
+                }
+                Some(_) => return Err(DidError::Web),
+                None => identifier,
+            };
+            if host == "localhost" || is_valid_handle(host) {
+                Ok(())
+            } else {
+                Err(DidError::Web)
+            }
+        }
+        _ => Err(DidError::Method),
+    }
+}
+
+/// Handles are domain names, like `alice.bsky.social`
+fn is_valid_handle(handle: &str) -> bool {
+    let labels = handle.split('.').collect::<Vec<_>>();
+
+    handle.len() <= 253
+        && labels.len() >= 2
+        && labels.iter().all(|label| {
+            (1..=63).contains(&label.len())
+                && label
+                    .bytes()
+                    .all(|b| b.is_ascii_alphanumeric() || b == b'-')
+                && !label.starts_with('-')
+                && !label.ends_with('-')
+        })
+        && labels
+            .last()
+            .is_some_and(|tld| tld.starts_with(|c: char| c.is_ascii_alphabetic()))
+}
+
+/// NSIDs are a reversed domain name followed by a name, like `app.bsky.feed.post`
+fn is_valid_nsid(nsid: &str) -> bool {
+    let segments = nsid.split('.').collect::<Vec<_>>();
+    let Some((name, domain)) = segments.split_last() else {
+        return false;
+    };
+
+    nsid.len() <= 317
+        && domain.len() >= 2
+        && domain.iter().all(|segment| {
+            (1..=63).contains(&segment.len())
+                && segment
+                    .bytes()
+                    .all(|b| b.is_ascii_alphanumeric() || b == b'-')
+                && !segment.starts_with('-')
+                && !segment.ends_with('-')
+        })
+        && domain[0].starts_with(|c: char| c.is_ascii_alphabetic())
+        && (1..=63).contains(&name.len())
+        && name.bytes().all(|b| b.is_ascii_alphanumeric())
+        && name.starts_with(|c: char| c.is_ascii_alphabetic())
+}
+
+fn is_valid_rkey(rkey: &str) -> bool {
+    (1..=512).contains(&rkey.len())
+        && rkey
+            .bytes()
+            .all(|b| b.is_ascii_alphanumeric() || b"._:~-".contains(&b))
+        && rkey != "."
+        && rkey != ".."
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use proptest::prelude::*;
+
+    const DID: &str = "did:plc:z72i7hdynmk6r22z27h6tvur";
+
+    #[test]
+    fn test_parse() {
+        let uri = AtUri::parse("at://did:plc:z72i7hdynmk6r22z27h6tvur/app.bsky.feed.post/3k2la");
+        assert_eq!(
+            Ok(AtUri {
+                authority: DID,
+                collection: Some("app.bsky.feed.post"),
+                rkey: Some("3k2la"),
+            }),
+            uri
+        );
+
+        assert_eq!(
+            Ok(AtUri {
+                authority: DID,
+                collection: None,
+                rkey: None,
+            }),
+            AtUri::parse(&format!("at://{DID}"))
+        );
+        assert_eq!(
+            Ok(AtUri {
+                authority: "did:web:feed.example.com",
+                collection: Some("app.bsky.feed.generator"),
+                rkey: None,
+            }),
+            AtUri::parse("at://did:web:feed.example.com/app.bsky.feed.generator")
+        );
+        assert_eq!(
+            Ok(AtUri {
+                authority: "alice.bsky.social",
+                collection: Some("app.bsky.feed.post"),
+                rkey: Some("self"),
+            }),
+            AtUri::parse("at://alice.bsky.social/app.bsky.feed.post/self")
+        );
+    }
+
+    #[test]
+    fn test_parse_invalid() {
+        let cases = [
+            ("", AtUriError::Scheme),
+            ("https://bsky.app", AtUriError::Scheme),
+            ("at://", AtUriError::Authority),
+            ("at://did:plc:abc", AtUriError::Did(DidError::Plc)),
+            (
+                "at://did:plc:Z72I7HDYNMK6R22Z27H6TVUR",
+                AtUriError::Did(DidError::Plc),
+            ),
+            ("at://did:key:z6Mkabc", AtUriError::Did(DidError::Method)),
+            (
+                "at://did:web:example.com:path",
+                AtUriError::Did(DidError::Web),
+            ),
+            (
+                "at://did:web:example.com%3A8080",
+                AtUriError::Did(DidError::Web),
+            ),
+            ("at://did:", AtUriError::Did(DidError::Syntax)),
+            ("at://localhost/app.bsky.feed.post", AtUriError::Authority),
+            ("at://-alice.bsky.social", AtUriError::Authority),
+            ("at://alice.bsky.123", AtUriError::Authority),
+        ];
+        for (uri, err) in cases {
+            assert_eq!(Err(err), AtUri::parse(uri), "{uri}");
+        }
+
+        let cases = [
+            (format!("at://{DID}/"), AtUriError::Collection),
+            (format!("at://{DID}/post"), AtUriError::Collection),
+            (format!("at://{DID}/bsky.post"), AtUriError::Collection),
+            (
+                format!("at://{DID}/app.bsky.feed.3post"),
+                AtUriError::Collection,
+            ),
+            (
+                format!("at://{DID}/app.bsky.feed.post/"),
+                AtUriError::RecordKey,
+            ),
+            (
+                format!("at://{DID}/app.bsky.feed.post/.."),
+                AtUriError::RecordKey,
+            ),
+            (
+                format!("at://{DID}/app.bsky.feed.post/a b"),
+                AtUriError::RecordKey,
+            ),
+            (
+                format!("at://{DID}/app.bsky.feed.post/a/b"),
+                AtUriError::TooManySegments,
+            ),
+            (
+                format!("at://{DID}/app.bsky.feed.post/a?b"),
+                AtUriError::QueryOrFragment,
+            ),
+            (format!("at://{DID}#fragment"), AtUriError::QueryOrFragment),
+            (
+                format!("at://{DID}/app.bsky.feed.post/{}", "a".repeat(8192)),
+                AtUriError::TooLong,
+            ),
+        ];
+        for (uri, err) in cases {
+            assert_eq!(Err(err), AtUri::parse(&uri), "{uri}");
+        }
+    }
+
+    #[test]
+    fn test_validate_did() {
+        assert_eq!(Ok(()), validate_did(DID));
+        assert_eq!(Ok(()), validate_did("did:web:feed.example.com"));
+        assert_eq!(Ok(()), validate_did("did:web:localhost%3A3000"));
+        assert_eq!(Err(DidError::Web), validate_did("did:web:localhost%3Aport"));
+        assert_eq!(Err(DidError::Syntax), validate_did("did:plc"));
+        assert_eq!(
+            Err(DidError::Syntax),
+            validate_did("did:plc:z72i7hdynmk6r22z27h6tvur:")
+        );
+    }
+
+    #[test]
+    fn test_owned() {
+        let uri = format!("at://{DID}/app.bsky.feed.post/3k2la");
+        let owned = uri.parse::<AtUriBuf>().unwrap();
+
+        assert_eq!(Some("3k2la"), owned.rkey.as_deref());
+        assert_eq!(AtUri::parse(&uri).unwrap(), owned.as_uri());
+        assert_eq!(uri, owned.to_string());
+    }
+
+    fn did() -> impl Strategy<Value = String> {
+        prop_oneof![
+            "[a-z2-7]{24}".prop_map(|id| format!("did:plc:{id}")),
+            "[a-z][a-z0-9-]{0,10}[a-z0-9]\\.[a-z]{2,6}".prop_map(|host| format!("did:web:{host}")),
+        ]
+    }
+
+    fn authority() -> impl Strategy<Value = String> {
+        prop_oneof![did(), "[a-z0-9]{1,20}\\.(bsky\\.social|example\\.com)"]
+    }
+
+    fn nsid() -> impl Strategy<Value = String> {
+        "[a-z][a-z0-9-]{0,8}[a-z0-9]\\.[a-z0-9]{1,10}(\\.[a-z0-9]{1,10}){0,2}\\.[a-zA-Z][a-zA-Z0-9]{0,20}"
+    }
+
+    fn rkey() -> impl Strategy<Value = String> {
+        "[a-zA-Z0-9._:~-]{1,64}".prop_filter("can't be . or ..", |rkey| rkey != "." && rkey != "..")
+    }
+
+    proptest! {
+        #[test]
+        fn test_parse_never_panics(s in "\\PC*") {
+            let _ = AtUri::parse(&s);
+            let _ = AtUri::parse(&format!("at://{s}"));
+            let _ = validate_did(&s);
+        }
+
+        #[test]
+        fn test_parse_never_panics_on_uri_like(s in "at://(did:(plc|web|[a-z]{0,4}):)?[a-zA-Z0-9.:%/_~?#-]{0,80}") {
+            let _ = AtUri::parse(&s);
+        }
+
+        #[test]
+        fn test_round_trip(
+            authority in authority(),
+            collection in proptest::option::of(nsid()),
+            rkey in proptest::option::of(rkey()),
+        ) {
+            let rkey = collection.as_ref().and(rkey);
+            let uri = AtUriBuf { authority, collection, rkey };
+            let s = uri.to_string();
+
+            prop_assert_eq!(Ok(uri.as_uri()), AtUri::parse(&s));
+            prop_assert_eq!(Ok(uri), s.parse::<AtUriBuf>());
+        }
+
+        #[test]
+        fn test_parsed_displays_as_itself(s in "at://[a-z0-9.:]{1,40}(/[a-zA-Z0-9.-]{1,30}(/[a-zA-Z0-9._:~-]{1,20})?)?") {
+            if let Ok(uri) = AtUri::parse(&s) {
+                prop_assert_eq!(&s, &uri.to_string());
+            }
+        }
     }
 }
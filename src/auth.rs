@@ -0,0 +1,576 @@
+//! Service auth: the jwts AppViews send with requests on behalf of an account, signed with the
+//! account's `#atproto` key
+
+use std::{
+    collections::HashMap,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+use anyhow::{anyhow, Context, Result};
+use axum::http::{header::AUTHORIZATION, HeaderMap};
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use chrono::Utc;
+use serde::Deserialize;
+use serde_json::Value;
+
+use crate::{
+    atproto::validate_did,
+    did::{self, PublicKey},
+};
+
+const PLC_DIRECTORY: &str = "https://plc.directory";
+
+/// How long a resolved key is used before the DID document is fetched again
+const KEY_TTL: Duration = Duration::from_secs(60 * 60);
+
+/// How many keys, or failures, are cached before the expired ones are dropped
+const MAX_KEYS: usize = 100_000;
+
+/// How long a DID that couldn't be resolved isn't tried again
+const FAILURE_TTL: Duration = Duration::from_secs(5 * 60);
+
+/// How many DID documents are fetched a minute at most. The issuer is read before the signature
+/// can be checked, so otherwise anyone could make us fetch as many as they like
+const MAX_RESOLVES_PER_MINUTE: u32 = 300;
+
+#[derive(Debug)]
+pub enum AuthError {
+    /// The `Authorization` header isn't a bearer jwt
+    Malformed,
+    /// Only ES256K and ES256 are used by atproto
+    Algorithm,
+    /// The jwt is meant for another service
+    Audience,
+    Expired,
+    Issuer,
+    /// The issuer's DID document couldn't be fetched, or has no `#atproto` key
+    Resolve(anyhow::Error),
+    Signature,
+}
+
+impl std::fmt::Display for AuthError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            AuthError::Malformed => write!(f, "not a bearer jwt"),
+            AuthError::Algorithm => write!(f, "only ES256K and ES256 jwts are supported"),
+            AuthError::Audience => write!(f, "the jwt is for another service"),
+            AuthError::Expired => write!(f, "the jwt has expired"),
+            AuthError::Issuer => write!(f, "the jwt issuer isn't a DID"),
+            AuthError::Resolve(err) => write!(f, "could not resolve the jwt issuer's key: {err:#}"),
+            AuthError::Signature => write!(f, "the jwt signature is invalid"),
+        }
+    }
+}
+
+impl std::error::Error for AuthError {}
+
+#[derive(Deserialize)]
+struct Header {
+    alg: String,
+}
+
+#[derive(Deserialize)]
+struct Claims {
+    iss: String,
+    aud: String,
+    exp: i64,
+}
+
+/// Verifies service auth jwts, caching the keys of the DIDs that issued them, and the DIDs that
+/// couldn't be resolved
+pub struct ServiceAuth {
+    client: reqwest::Client,
+    /// Where did:plc documents are fetched from
+    plc_directory: String,
+    /// Whether `did:web:localhost` issuers are resolved, over http if they have a port. Only for
+    /// local development, since it lets anyone make requests to services on this machine
+    allow_localhost: bool,
+    keys: Mutex<HashMap<String, (PublicKey, Instant)>>,
+    /// Why each DID couldn't be resolved
+    failures: Mutex<HashMap<String, (String, Instant)>>,
+    /// When the current minute of resolves started, and how many there were in it
+    resolves: Mutex<(Instant, u32)>,
+}
+
+impl Default for ServiceAuth {
+    fn default() -> Self {
+        Self {
+            client: reqwest::Client::new(),
+            plc_directory: PLC_DIRECTORY.to_string(),
+            allow_localhost: false,
+            keys: Mutex::default(),
+            failures: Mutex::default(),
+            resolves: Mutex::new((Instant::now(), 0)),
+        }
+    }
+}
+
+impl ServiceAuth {
+    pub fn new(allow_localhost: bool) -> Self {
+        Self {
+            allow_localhost,
+            ..Default::default()
+        }
+    }
+
+    /// Gets the DID of the account making a request, from the service auth jwt in the
+    /// `Authorization` header. The jwt has to be for `service_did`, unexpired, and signed with the
+    /// `#atproto` key of the account's DID document.
+    ///
+    /// Returns `None` if there's no `Authorization` header
+    pub async fn requester_did(
+        &self,
+        headers: &HeaderMap,
+        service_did: &str,
+    ) -> Result<Option<String>, AuthError> {
+        let Some(header) = headers.get(AUTHORIZATION) else {
+            return Ok(None);
+        };
+        let token = header
+            .to_str()
+            .ok()
+            .and_then(|header| header.strip_prefix("Bearer "))
+            .ok_or(AuthError::Malformed)?;
+
+        // the signature is of everything before its `.`
+        let (message, signature) = token.rsplit_once('.').ok_or(AuthError::Malformed)?;
+        let (header, claims) = message.split_once('.').ok_or(AuthError::Malformed)?;
+        let header = decode::<Header>(header)?;
+        let claims = decode::<Claims>(claims)?;
+        let signature = URL_SAFE_NO_PAD
+            .decode(signature)
+            .map_err(|_| AuthError::Malformed)?;
+
+        if !matches!(header.alg.as_str(), "ES256K" | "ES256") {
+            return Err(AuthError::Algorithm);
+        }
+        // `aud` can also include a service fragment, like `did:web:feed.example.com#bsky_fg`
+        if claims.aud.split('#').next() != Some(service_did) {
+            return Err(AuthError::Audience);
+        }
+        if claims.exp <= Utc::now().timestamp() {
+            return Err(AuthError::Expired);
+        }
+        // as can `iss`, like `did:plc:asdfghjkl#atproto_labeler`
+        let did = claims.iss.split('#').next().unwrap_or_default();
+        validate_did(did).map_err(|_| AuthError::Issuer)?;
+        if is_localhost(did) && !self.allow_localhost {
+            return Err(AuthError::Issuer);
+        }
+
+        let verified = |key: &PublicKey| {
+            key.jwt_alg() == header.alg && key.verify(message.as_bytes(), &signature)
+        };
+
+        let (key, cached) = self.key(did, false).await?;
+        // the account may have rotated its key since it was cached
+        if !(verified(&key) || cached && verified(&self.key(did, true).await?.0)) {
+            return Err(AuthError::Signature);
+        }
+
+        Ok(Some(did.to_string()))
+    }
+
+    /// The `#atproto` key of `did`, and whether it came from the cache
+    async fn key(&self, did: &str, refresh: bool) -> Result<(PublicKey, bool), AuthError> {
+        if !refresh {
+            let keys = self.keys.lock().unwrap();
+            if let Some((key, resolved_at)) = keys.get(did) {
+                if resolved_at.elapsed() < KEY_TTL {
+                    return Ok((key.clone(), true));
+                }
+            }
+        }
+
+        if let Some((err, failed_at)) = self.failures.lock().unwrap().get(did) {
+            if failed_at.elapsed() < FAILURE_TTL {
+                return Err(AuthError::Resolve(anyhow!("{err} (cached)")));
+            }
+        }
+        if !self.may_resolve() {
+            return Err(AuthError::Resolve(anyhow!(
+                "too many DID documents were fetched in the last minute to fetch {did}'s"
+            )));
+        }
+
+        let key = match self.resolve(did).await {
+            Ok(key) => key,
+            Err(err) => {
+                let failures = &mut self.failures.lock().unwrap();
+                remember(failures, did, format!("{err:#}"), FAILURE_TTL);
+                return Err(AuthError::Resolve(err));
+            }
+        };
+        remember(&mut self.keys.lock().unwrap(), did, key.clone(), KEY_TTL);
+
+        Ok((key, false))
+    }
+
+    /// Counts a resolve toward [`MAX_RESOLVES_PER_MINUTE`], unless there have been too many
+    fn may_resolve(&self) -> bool {
+        let (started, count) = &mut *self.resolves.lock().unwrap();
+        if started.elapsed() >= Duration::from_secs(60) {
+            (*started, *count) = (Instant::now(), 0);
+        }
+        if *count >= MAX_RESOLVES_PER_MINUTE {
+            return false;
+        }
+        *count += 1;
+        true
+    }
+
+    async fn resolve(&self, did: &str) -> Result<PublicKey> {
+        let url = match did.strip_prefix("did:web:") {
+            // localhost DIDs can have a port, and aren't served over https. they're only let
+            // through this far if `allow_localhost` is set
+            Some(host) if host.starts_with("localhost%3A") => {
+                format!("http://{}/.well-known/did.json", host.replace("%3A", ":"))
+            }
+            Some(host) => format!("https://{host}/.well-known/did.json"),
+            None => format!("{}/{did}", self.plc_directory),
+        };
+
+        let document = self
+            .client
+            .get(&url)
+            .timeout(Duration::from_secs(10))
+            .send()
+            .await
+            .and_then(|response| response.error_for_status())
+            .with_context(|| format!("failed to fetch the DID document of {did}"))?
+            .json::<Value>()
+            .await
+            .with_context(|| format!("the DID document of {did} isn't json"))?;
+
+        did::atproto_key(did, &document)
+            .with_context(|| format!("the DID document of {did} has no #atproto key"))
+    }
+}
+
+fn is_localhost(did: &str) -> bool {
+    did == "did:web:localhost" || did.starts_with("did:web:localhost%3A")
+}
+
+/// Caches `value` for `did`, first dropping the entries older than `ttl` if the cache is full
+fn remember<T>(cache: &mut HashMap<String, (T, Instant)>, did: &str, value: T, ttl: Duration) {
+    if cache.len() >= MAX_KEYS {
+        cache.retain(|_, (_, at)| at.elapsed() < ttl);
+        if cache.len() >= MAX_KEYS {
+            cache.clear();
+        }
+    }
+    cache.insert(did.to_string(), (value, Instant::now()));
+}
+
+fn decode<T: serde::de::DeserializeOwned>(part: &str) -> Result<T, AuthError> {
+    let json = URL_SAFE_NO_PAD
+        .decode(part)
+        .map_err(|_| AuthError::Malformed)?;
+    serde_json::from_slice(&json).map_err(|_| AuthError::Malformed)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    };
+
+    use k256::ecdsa::{signature::Signer, Signature, SigningKey};
+    use serde_json::json;
+
+    const SERVICE_DID: &str = "did:web:feed.example.com";
+    const DID: &str = "did:plc:ragtjsm2j2vknwkz3zp4oxrd";
+
+    fn signing_key(seed: u8) -> SigningKey {
+        SigningKey::from_slice(&[seed; 32]).unwrap()
+    }
+
+    /// The multibase form of the public half of `key`
+    fn multikey(key: &SigningKey) -> PublicKey {
+        let mut bytes = vec![0xe7, 0x01];
+        bytes.extend_from_slice(&key.verifying_key().to_sec1_bytes());
+        PublicKey::parse(&format!("z{}", did::encode_base58(&bytes))).unwrap()
+    }
+
+    /// Can't resolve any DIDs
+    fn offline() -> ServiceAuth {
+        ServiceAuth {
+            plc_directory: "http://127.0.0.1:1".to_string(),
+            ..Default::default()
+        }
+    }
+
+    /// Knows `DID`'s key without resolving it
+    fn auth(key: &SigningKey) -> ServiceAuth {
+        let auth = offline();
+        auth.keys
+            .lock()
+            .unwrap()
+            .insert(DID.to_string(), (multikey(key), Instant::now()));
+        auth
+    }
+
+    fn token(alg: &str, claims: &Value, key: &SigningKey) -> String {
+        let message = format!(
+            "{}.{}",
+            URL_SAFE_NO_PAD.encode(json!({ "alg": alg, "typ": "JWT" }).to_string()),
+            URL_SAFE_NO_PAD.encode(claims.to_string())
+        );
+        let signature: Signature = key.sign(message.as_bytes());
+        format!("{message}.{}", URL_SAFE_NO_PAD.encode(signature.to_bytes()))
+    }
+
+    fn headers(token: &str) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        headers.insert(AUTHORIZATION, format!("Bearer {token}").parse().unwrap());
+        headers
+    }
+
+    fn claims(iss: &str, aud: &str, exp: i64) -> Value {
+        json!({ "iss": iss, "aud": aud, "exp": exp })
+    }
+
+    fn in_a_minute() -> i64 {
+        Utc::now().timestamp() + 60
+    }
+
+    #[tokio::test]
+    async fn test_requester_did() {
+        let key = signing_key(1);
+        let auth = auth(&key);
+
+        for claims in [
+            claims(DID, SERVICE_DID, in_a_minute()),
+            claims(&format!("{DID}#atproto"), SERVICE_DID, in_a_minute()),
+            claims(DID, &format!("{SERVICE_DID}#bsky_fg"), in_a_minute()),
+        ] {
+            let token = token("ES256K", &claims, &key);
+            assert_eq!(
+                Some(DID.to_string()),
+                auth.requester_did(&headers(&token), SERVICE_DID)
+                    .await
+                    .unwrap(),
+                "{claims}"
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn test_no_requester_without_authorization() {
+        assert!(offline()
+            .requester_did(&HeaderMap::new(), SERVICE_DID)
+            .await
+            .unwrap()
+            .is_none());
+    }
+
+    #[tokio::test]
+    async fn test_forged_token_is_rejected() {
+        let auth = auth(&signing_key(1));
+        let forged = token(
+            "ES256K",
+            &claims(DID, SERVICE_DID, in_a_minute()),
+            &signing_key(2),
+        );
+
+        // the cached key doesn't match, and the DID document can't be fetched again
+        assert!(auth
+            .requester_did(&headers(&forged), SERVICE_DID)
+            .await
+            .is_err());
+    }
+
+    #[tokio::test]
+    async fn test_keys_are_resolved_from_the_plc_directory() {
+        let key = signing_key(1);
+        let document = json!({
+            "id": DID,
+            "verificationMethod": [
+                {
+                    "id": "#atproto",
+                    "type": "Multikey",
+                    "controller": DID,
+                    "publicKeyMultibase": multikey(&key).as_str()
+                }
+            ]
+        });
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let plc_directory = format!("http://{}", listener.local_addr().unwrap());
+        // every DID resolves to the document, which is only right for `DID`
+        let app = axum::Router::new().fallback(move || async move { axum::Json(document) });
+        tokio::spawn(async move { axum::serve(listener, app).await });
+
+        // a stale key for the DID is cached, as if it was rotated since
+        let auth = ServiceAuth {
+            plc_directory,
+            ..auth(&signing_key(2))
+        };
+        let token = token("ES256K", &claims(DID, SERVICE_DID, in_a_minute()), &key);
+
+        assert_eq!(
+            Some(DID.to_string()),
+            auth.requester_did(&headers(&token), SERVICE_DID)
+                .await
+                .unwrap()
+        );
+        let forged = self::token(
+            "ES256K",
+            &claims(DID, SERVICE_DID, in_a_minute()),
+            &signing_key(3),
+        );
+        assert!(matches!(
+            auth.requester_did(&headers(&forged), SERVICE_DID).await,
+            Err(AuthError::Signature)
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_localhost_issuers_are_only_resolved_when_allowed() {
+        let key = signing_key(1);
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let did = format!(
+            "did:web:localhost%3A{}",
+            listener.local_addr().unwrap().port()
+        );
+        let document = json!({
+            "id": did,
+            "verificationMethod": [
+                {
+                    "id": "#atproto",
+                    "type": "Multikey",
+                    "controller": did,
+                    "publicKeyMultibase": multikey(&key).as_str()
+                }
+            ]
+        });
+        let app = axum::Router::new().fallback(move || async move { axum::Json(document) });
+        tokio::spawn(async move { axum::serve(listener, app).await });
+        let token = token("ES256K", &claims(&did, SERVICE_DID, in_a_minute()), &key);
+
+        assert!(matches!(
+            offline().requester_did(&headers(&token), SERVICE_DID).await,
+            Err(AuthError::Issuer)
+        ));
+
+        let auth = ServiceAuth {
+            allow_localhost: true,
+            ..offline()
+        };
+        assert_eq!(
+            Some(did),
+            auth.requester_did(&headers(&token), SERVICE_DID)
+                .await
+                .unwrap()
+        );
+    }
+
+    #[tokio::test]
+    async fn test_resolving_is_limited() {
+        let fetches = Arc::new(AtomicUsize::new(0));
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let plc_directory = format!("http://{}", listener.local_addr().unwrap());
+        // no DID has a document
+        let app = axum::Router::new().fallback({
+            let fetches = fetches.clone();
+            move || async move {
+                fetches.fetch_add(1, Ordering::SeqCst);
+                axum::http::StatusCode::NOT_FOUND
+            }
+        });
+        tokio::spawn(async move { axum::serve(listener, app).await });
+        let auth = ServiceAuth {
+            plc_directory,
+            ..Default::default()
+        };
+        let key = signing_key(1);
+        let token = token("ES256K", &claims(DID, SERVICE_DID, in_a_minute()), &key);
+
+        // a DID that couldn't be resolved isn't tried again for a while
+        for _ in 0..2 {
+            assert!(matches!(
+                auth.requester_did(&headers(&token), SERVICE_DID).await,
+                Err(AuthError::Resolve(_))
+            ));
+        }
+        assert_eq!(1, fetches.load(Ordering::SeqCst));
+
+        // and nothing is fetched once too many were this minute
+        *auth.resolves.lock().unwrap() = (Instant::now(), MAX_RESOLVES_PER_MINUTE);
+        let other = "did:plc:z72i7hdynmk6r22z27h6tvur";
+        let token = self::token("ES256K", &claims(other, SERVICE_DID, in_a_minute()), &key);
+        assert!(matches!(
+            auth.requester_did(&headers(&token), SERVICE_DID).await,
+            Err(AuthError::Resolve(_))
+        ));
+        assert_eq!(1, fetches.load(Ordering::SeqCst));
+    }
+
+    #[tokio::test]
+    async fn test_unsigned_token_is_rejected() {
+        let auth = auth(&signing_key(1));
+        let claims = URL_SAFE_NO_PAD.encode(claims(DID, SERVICE_DID, in_a_minute()).to_string());
+
+        for token in [
+            format!("{}.{claims}.", URL_SAFE_NO_PAD.encode(r#"{"alg":"none"}"#)),
+            format!(
+                "{}.{claims}.",
+                URL_SAFE_NO_PAD.encode(r#"{"alg":"ES256K"}"#)
+            ),
+            format!("{}.{claims}", URL_SAFE_NO_PAD.encode(r#"{"alg":"ES256K"}"#)),
+            "not a jwt".to_string(),
+        ] {
+            assert!(auth
+                .requester_did(&headers(&token), SERVICE_DID)
+                .await
+                .is_err());
+        }
+    }
+
+    #[tokio::test]
+    async fn test_claims_are_checked() {
+        let key = signing_key(1);
+        let auth = auth(&key);
+
+        let wrong_audience = token(
+            "ES256K",
+            &claims(DID, "did:web:other.example.com", in_a_minute()),
+            &key,
+        );
+        assert!(matches!(
+            auth.requester_did(&headers(&wrong_audience), SERVICE_DID)
+                .await,
+            Err(AuthError::Audience)
+        ));
+
+        let expired = token(
+            "ES256K",
+            &claims(DID, SERVICE_DID, Utc::now().timestamp() - 1),
+            &key,
+        );
+        assert!(matches!(
+            auth.requester_did(&headers(&expired), SERVICE_DID).await,
+            Err(AuthError::Expired)
+        ));
+
+        let not_a_did = token(
+            "ES256K",
+            &claims("someone", SERVICE_DID, in_a_minute()),
+            &key,
+        );
+        assert!(matches!(
+            auth.requester_did(&headers(&not_a_did), SERVICE_DID).await,
+            Err(AuthError::Issuer)
+        ));
+
+        // signed with the right key, but claiming to be p256
+        let wrong_alg = token("ES256", &claims(DID, SERVICE_DID, in_a_minute()), &key);
+        assert!(auth
+            .requester_did(&headers(&wrong_alg), SERVICE_DID)
+            .await
+            .is_err());
+    }
+}
@@ -0,0 +1,442 @@
+use std::time::Duration;
+
+use tokio::{
+    sync::{mpsc, oneshot},
+    task::JoinHandle,
+};
+
+use crate::{
+    link_finder::{Kind, Site},
+    metrics,
+    spam::{self, SpamConfig, Verdict},
+    storage::{self, Storage},
+};
+
+/// How many times a batch is retried after a transient error, before it's written row by row
+const RETRIES: u32 = 3;
+/// How long to wait before the first retry. Every retry waits twice as long as the last
+const RETRY_DELAY: Duration = Duration::from_millis(100);
+
+pub struct BatchConfig {
+    /// Buffered writes are flushed once there are this many
+    pub max_size: usize,
+    /// Buffered writes are flushed at least this often
+    pub max_delay: Duration,
+}
+
+impl Default for BatchConfig {
+    fn default() -> Self {
+        Self {
+            max_size: 100,
+            max_delay: Duration::from_secs(1),
+        }
+    }
+}
+
+/// A post waiting to be written, along with its links
+pub struct PendingPost {
+    pub uri: String,
+    pub cid: String,
+    pub author: String,
+    pub reply_root: Option<String>,
+    pub reply_parent: Option<String>,
+    pub quote: Option<String>,
+    pub fingerprint: Option<String>,
+    /// Lowercased language tags
+    pub langs: Vec<String>,
+    pub links: Vec<PendingLink>,
+    /// Every url in the post, music or not, for blocked links to match
+    pub urls: Vec<String>,
+}
+
+pub struct PendingLink {
+    pub url: String,
+    pub kind: Kind,
+    pub site: Site,
+}
+
+pub enum Write {
+    Create(PendingPost),
+    Delete(String),
+    /// A like or repost, at `uri`, of the post at `subject`
+    Engage {
+        uri: String,
+        subject: String,
+    },
+    /// The like or repost with this uri was deleted
+    Disengage(String),
+}
+
+impl Write {
+    /// The uri of the post, like or repost being written
+    pub fn uri(&self) -> &str {
+        match self {
+            Write::Create(post) => &post.uri,
+            Write::Delete(uri) | Write::Engage { uri, .. } | Write::Disengage(uri) => uri,
+        }
+    }
+}
+
+/// What the writer's task receives
+// nearly every message is a write, so boxing them would only add an allocation each
+#[allow(clippy::large_enum_variant)]
+enum Message {
+    Write(Write),
+    /// Flush the buffer, then reply
+    Flush(oneshot::Sender<()>),
+}
+
+/// Buffers ingest writes, and writes them in a single transaction once enough have built up or
+/// enough time has passed. Batches that fail are retried if the error looks transient, and
+/// otherwise written row by row so only the bad writes are lost.
+///
+/// Creates and deletes go through the same buffer, so they are applied in the order they arrive.
+/// Created posts are checked for spam right before they're written, one at a time, so that posts
+/// in the same batch count toward each other's limits
+#[derive(Clone)]
+pub struct BatchWriter {
+    sender: mpsc::Sender<Message>,
+}
+
+impl BatchWriter {
+    /// Starts the task that flushes the buffer.
+    ///
+    /// Once every clone of the returned writer is dropped, the task flushes whatever is left and
+    /// the returned handle finishes
+    pub fn spawn<S>(storage: S, config: BatchConfig, spam: SpamConfig) -> (Self, JoinHandle<()>)
+    where
+        S: Storage + Send + Sync + 'static,
+    {
+        let (sender, receiver) = mpsc::channel(config.max_size * 4);
+        let handle = tokio::spawn(run(storage, config, spam, receiver));
+
+        (Self { sender }, handle)
+    }
+
+    pub async fn create(&self, post: PendingPost) {
+        self.send(Write::Create(post)).await;
+    }
+
+    pub async fn delete(&self, uri: String) {
+        self.send(Write::Delete(uri)).await;
+    }
+
+    pub async fn engage(&self, uri: String, subject: String) {
+        self.send(Write::Engage { uri, subject }).await;
+    }
+
+    pub async fn disengage(&self, uri: String) {
+        self.send(Write::Disengage(uri)).await;
+    }
+
+    /// Waits until every write sent before this call has been written, or dropped if it failed
+    pub async fn flush(&self) {
+        let (sender, receiver) = oneshot::channel();
+        if self.sender.send(Message::Flush(sender)).await.is_ok() {
+            let _ = receiver.await;
+        }
+    }
+
+    async fn send(&self, write: Write) {
+        if let Err(mpsc::error::SendError(Message::Write(write))) =
+            self.sender.send(Message::Write(write)).await
+        {
+            tracing::error!(uri = write.uri(), "batch writer stopped, dropping write");
+        }
+    }
+}
+
+async fn run(
+    storage: impl Storage,
+    config: BatchConfig,
+    spam: SpamConfig,
+    mut receiver: mpsc::Receiver<Message>,
+) {
+    let mut buffer = Vec::with_capacity(config.max_size);
+    let mut interval = tokio::time::interval(config.max_delay);
+    interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+
+    loop {
+        tokio::select! {
+            message = receiver.recv() => match message {
+                Some(Message::Write(write)) => {
+                    buffer.push(write);
+                    if buffer.len() >= config.max_size {
+                        flush(&storage, &spam, &mut buffer).await;
+                    }
+                }
+                Some(Message::Flush(done)) => {
+                    flush(&storage, &spam, &mut buffer).await;
+                    let _ = done.send(());
+                }
+                None => break,
+            },
+            _ = interval.tick() => {
+                flush(&storage, &spam, &mut buffer).await;
+            }
+        }
+    }
+
+    // every writer was dropped, so this is the last chance to write anything
+    flush(&storage, &spam, &mut buffer).await;
+}
+
+async fn flush(storage: &impl Storage, spam: &SpamConfig, buffer: &mut Vec<Write>) {
+    screen(storage, spam, buffer).await;
+    if buffer.is_empty() {
+        return;
+    }
+
+    let mut delay = RETRY_DELAY;
+    for retry in 0..=RETRIES {
+        match storage.write_batch(buffer).await {
+            Ok(()) => {
+                record_indexed(buffer);
+                buffer.clear();
+                return;
+            }
+            Err(err) if retry < RETRIES && storage::is_transient(&err) => {
+                tracing::warn!(size = buffer.len(), "retrying batch in {delay:?}: {err:?}");
+                tokio::time::sleep(delay).await;
+                delay *= 2;
+            }
+            Err(err) => {
+                tracing::error!(
+                    size = buffer.len(),
+                    "could not write batch, writing it row by row: {err:?}"
+                );
+                break;
+            }
+        }
+    }
+
+    // so that one bad write doesn't take the rest of the batch down with it
+    for write in buffer.drain(..) {
+        let write = std::slice::from_ref(&write);
+        match storage.write_batch(write).await {
+            Ok(()) => record_indexed(write),
+            Err(err) => tracing::error!(uri = write[0].uri(), "could not write: {err:?}"),
+        }
+    }
+}
+
+/// Drops the created posts that are spam, counting the ones before them in the buffer
+async fn screen(storage: &impl Storage, config: &SpamConfig, buffer: &mut Vec<Write>) {
+    let mut pending = spam::Pending::default();
+    let mut screened = Vec::with_capacity(buffer.len());
+
+    for write in buffer.drain(..) {
+        if let Write::Create(PendingPost {
+            uri,
+            author,
+            fingerprint: Some(fingerprint),
+            ..
+        }) = &write
+        {
+            match spam::check(storage, config, &pending, author, fingerprint).await {
+                Ok(Verdict::Allowed) => pending.add(author, fingerprint),
+                Ok(Verdict::RateLimited | Verdict::Duplicate) => continue,
+                Err(err) => {
+                    tracing::error!(uri, "could not check for spam: {err:?}");
+                    continue;
+                }
+            }
+        }
+        screened.push(write);
+    }
+
+    *buffer = screened;
+}
+
+fn record_indexed(writes: &[Write]) {
+    for write in writes {
+        let Write::Create(post) = write else {
+            continue;
+        };
+
+        let mut seen = Vec::with_capacity(post.links.len());
+        for link in &post.links {
+            metrics::link_indexed(&link.site, &link.kind);
+            if !seen.contains(&(&link.site, &link.kind)) {
+                seen.push((&link.site, &link.kind));
+                metrics::post_indexed(&link.site, &link.kind);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::storage::SqliteStorage;
+
+    fn post(uri: &str) -> PendingPost {
+        PendingPost {
+            uri: uri.to_string(),
+            cid: "cid".to_string(),
+            author: "did:plc:author".to_string(),
+            reply_root: None,
+            reply_parent: None,
+            quote: None,
+            fingerprint: Some(uri.to_string()),
+            langs: vec![],
+            links: vec![PendingLink {
+                url: format!("https://open.spotify.com/track/{uri}"),
+                kind: Kind::Track,
+                site: Site::Spotify,
+            }],
+            urls: vec![format!("https://open.spotify.com/track/{uri}")],
+        }
+    }
+
+    async fn count(storage: &SqliteStorage) -> (i64, i64) {
+        let posts = sqlx::query_scalar!("select count(*) from posts")
+            .fetch_one(storage.pool())
+            .await
+            .unwrap();
+        let links = sqlx::query_scalar!("select count(*) from post_links")
+            .fetch_one(storage.pool())
+            .await
+            .unwrap();
+        (posts, links)
+    }
+
+    #[tokio::test]
+    async fn test_flushes_when_full() {
+        let storage = SqliteStorage::memory().await;
+        let (writer, _handle) = BatchWriter::spawn(
+            storage.clone(),
+            BatchConfig {
+                max_size: 2,
+                max_delay: Duration::from_secs(60 * 60),
+            },
+            SpamConfig::default(),
+        );
+
+        writer.create(post("a")).await;
+        writer.create(post("b")).await;
+        // give the writer a moment to flush
+        tokio::time::sleep(Duration::from_millis(100)).await;
+
+        assert_eq!((2, 2), count(&storage).await);
+    }
+
+    #[tokio::test]
+    async fn test_flushes_remaining_writes_when_dropped() {
+        let storage = SqliteStorage::memory().await;
+        let (writer, handle) = BatchWriter::spawn(
+            storage.clone(),
+            BatchConfig {
+                max_size: 100,
+                max_delay: Duration::from_secs(60 * 60),
+            },
+            SpamConfig::default(),
+        );
+
+        writer.create(post("a")).await;
+        drop(writer);
+        handle.await.unwrap();
+
+        assert_eq!((1, 1), count(&storage).await);
+    }
+
+    #[tokio::test]
+    async fn test_flush_waits_for_earlier_writes() {
+        let storage = SqliteStorage::memory().await;
+        let (writer, _handle) = BatchWriter::spawn(
+            storage.clone(),
+            BatchConfig {
+                max_size: 100,
+                max_delay: Duration::from_secs(60 * 60),
+            },
+            SpamConfig::default(),
+        );
+
+        writer.create(post("a")).await;
+        writer.flush().await;
+
+        assert_eq!((1, 1), count(&storage).await);
+    }
+
+    #[tokio::test]
+    async fn test_deletes_are_applied_in_order() {
+        let storage = SqliteStorage::memory().await;
+        let (writer, handle) = BatchWriter::spawn(
+            storage.clone(),
+            BatchConfig::default(),
+            SpamConfig::default(),
+        );
+
+        writer.create(post("a")).await;
+        writer.delete("a".to_string()).await;
+        writer.create(post("b")).await;
+        drop(writer);
+        handle.await.unwrap();
+
+        assert_eq!((1, 1), count(&storage).await);
+    }
+
+    #[tokio::test]
+    async fn test_spam_within_a_batch_is_dropped() {
+        let storage = SqliteStorage::memory().await;
+        let (writer, handle) = BatchWriter::spawn(
+            storage.clone(),
+            BatchConfig::default(),
+            SpamConfig {
+                max_posts_per_window: 3,
+                ..Default::default()
+            },
+        );
+
+        // one more than the limit, and a duplicate, all written in the same batch
+        for uri in ["a", "b", "c", "d"] {
+            writer.create(post(uri)).await;
+        }
+        writer
+            .create(PendingPost {
+                fingerprint: Some("a".to_string()),
+                ..post("e")
+            })
+            .await;
+        drop(writer);
+        handle.await.unwrap();
+
+        let uris = sqlx::query_scalar!("select uri from posts order by uri")
+            .fetch_all(storage.pool())
+            .await
+            .unwrap();
+        assert_eq!(
+            vec![
+                Some("a".to_string()),
+                Some("b".to_string()),
+                Some("c".to_string())
+            ],
+            uris
+        );
+    }
+
+    #[tokio::test]
+    async fn test_failed_batch_is_written_row_by_row() {
+        let storage = SqliteStorage::memory().await;
+        sqlx::query(
+            "create trigger reject_bad before insert on posts when new.uri = 'bad' begin select raise(abort, 'bad post'); end",
+        )
+        .execute(storage.pool())
+        .await
+        .unwrap();
+        let (writer, handle) = BatchWriter::spawn(
+            storage.clone(),
+            BatchConfig::default(),
+            SpamConfig::default(),
+        );
+
+        writer.create(post("a")).await;
+        writer.create(post("bad")).await;
+        writer.create(post("b")).await;
+        drop(writer);
+        handle.await.unwrap();
+
+        assert_eq!((2, 2), count(&storage).await);
+    }
+}
@@ -0,0 +1,71 @@
+//! One-off commands, for running by hand or from cron. They expect the database to be migrated
+
+use anyhow::Result;
+
+use crate::{
+    config::Config,
+    maintenance,
+    storage::{Database, Storage},
+};
+
+pub async fn migrate(config: &Config) -> Result<()> {
+    let db = Database::connect(&config.database_url).await?;
+    db.migrate().await?;
+    db.close().await;
+
+    println!("migrated");
+    Ok(())
+}
+
+pub async fn prune(config: &Config, optimize: bool) -> Result<()> {
+    let db = Database::connect(&config.database_url).await?;
+
+    let report = maintenance::prune(&db, config.retention.post_max_age).await?;
+    println!("pruned {} posts and {} links", report.posts, report.links);
+
+    if optimize {
+        db.vacuum().await?;
+        db.optimize().await?;
+        println!("vacuumed and optimized");
+    }
+
+    db.close().await;
+    Ok(())
+}
+
+pub async fn stats(config: &Config) -> Result<()> {
+    let db = Database::connect(&config.database_url).await?;
+
+    let stats = db.stats().await?;
+    let cursor = db.cursor(&config.relay).await?;
+    let time = |time: Option<chrono::DateTime<chrono::Utc>>| {
+        time.map(|time| time.to_rfc3339())
+            .unwrap_or_else(|| "-".to_string())
+    };
+
+    println!("posts: {}", stats.posts);
+    println!("authors: {}", stats.authors);
+    println!("links: {}", stats.links);
+    println!("oldest post: {}", time(stats.oldest_post));
+    println!("newest post: {}", time(stats.newest_post));
+    match cursor {
+        Some(cursor) => println!("cursor: {cursor} ({})", config.relay),
+        None => println!("cursor: - ({})", config.relay),
+    }
+
+    db.close().await;
+    Ok(())
+}
+
+pub async fn reindex(config: &Config) -> Result<()> {
+    let db = Database::connect(&config.database_url).await?;
+
+    let report = maintenance::reindex(&db).await?;
+    println!(
+        "reclassified {} links, and removed {} links and {} posts",
+        report.reclassified, report.links, report.posts
+    );
+
+    db.close().await;
+    Ok(())
+}
@@ -0,0 +1,873 @@
+//! Loading and validating the configuration.
+//!
+//! Settings are read from a TOML file, and every one of them can be overridden by an env var.
+//! See `config.example.toml` for all of them
+
+use std::{
+    collections::HashSet,
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use anyhow::{bail, Context, Result};
+use serde::Deserialize;
+
+use crate::{
+    algos::ReplyPolicy,
+    atproto::{validate_did, AtUri},
+    batch::BatchConfig,
+    did::{self, PublicKey},
+    feeds::{Algorithm, FeedDefinition, Feeds, Filters},
+    maintenance::RetentionConfig,
+    server,
+    spam::SpamConfig,
+};
+
+/// Read if `FEEDGEN_CONFIG` isn't set. It's fine for it not to exist
+const DEFAULT_PATH: &str = "config.toml";
+
+pub struct Config {
+    pub port: u16,
+    pub database_url: String,
+    /// The relay to get the firehose from. Either a bare hostname, or a full url
+    pub relay: String,
+    /// Labelers to subscribe to, in the same format as `relay`
+    pub labelers: Vec<String>,
+    /// On SIGTERM, how long to wait for in-flight requests and ingest writes before exiting anyway
+    pub shutdown_timeout: Duration,
+    /// The feeds to serve
+    pub feeds: Feeds,
+    pub server: server::Config,
+    pub spam: SpamConfig,
+    pub batch: BatchConfig,
+    pub retention: RetentionConfig,
+    /// Whether requests can be authenticated by `did:web:localhost` accounts. Only for local
+    /// development
+    pub allow_localhost_dids: bool,
+}
+
+impl Config {
+    /// Loads the file at `FEEDGEN_CONFIG`, or `config.toml` if there is one, applies env
+    /// overrides, and validates the result
+    pub fn load() -> Result<Self> {
+        let file = match std::env::var("FEEDGEN_CONFIG") {
+            Ok(path) => File::read(Path::new(&path))?,
+            Err(_) if Path::new(DEFAULT_PATH).exists() => File::read(Path::new(DEFAULT_PATH))?,
+            Err(_) => File::default(),
+        };
+
+        file.with_overrides(|key| std::env::var(key).ok())?
+            .validate()
+    }
+}
+
+/// The layout of the config file
+#[derive(Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct File {
+    port: u16,
+    shutdown_timeout_secs: u64,
+    database: DatabaseSection,
+    relay: RelaySection,
+    service: ServiceSection,
+    feeds: FeedsSection,
+    ingest: IngestSection,
+    retention: RetentionSection,
+    admin: AdminSection,
+    /// The `[[feed]]` tables. The default feeds are served if there are none
+    feed: Vec<FeedTable>,
+}
+
+#[derive(Deserialize, Default)]
+#[serde(default, deny_unknown_fields)]
+struct DatabaseSection {
+    url: String,
+}
+
+#[derive(Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct RelaySection {
+    host: String,
+    labelers: Vec<String>,
+    stall_threshold_secs: u64,
+}
+
+#[derive(Deserialize, Default)]
+#[serde(default, deny_unknown_fields)]
+struct ServiceSection {
+    did: String,
+    publisher_did: String,
+    hostname: String,
+    /// Published in the DID document. Empty if there's none
+    public_key: String,
+    allow_localhost_dids: bool,
+}
+
+#[derive(Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct FeedsSection {
+    reply_policy: String,
+    excluded_labels: Vec<String>,
+    max_posts_per_author_per_page: usize,
+    preference_half_life_days: u64,
+    pinned_posts: Vec<String>,
+}
+
+#[derive(Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct IngestSection {
+    batch_size: usize,
+    batch_delay_ms: u64,
+    max_posts_per_author: u32,
+    rate_limit_window_secs: u64,
+    duplicate_window_secs: u64,
+}
+
+#[derive(Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct RetentionSection {
+    post_max_age_days: u64,
+    prune_interval_secs: u64,
+    vacuum_interval_secs: u64,
+}
+
+#[derive(Deserialize, Default)]
+#[serde(default, deny_unknown_fields)]
+struct AdminSection {
+    token: Option<String>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct FeedTable {
+    rkey: String,
+    name: String,
+    description: Option<String>,
+    avatar: Option<PathBuf>,
+    #[serde(default = "default_algorithm")]
+    algorithm: String,
+    reply_policy: Option<String>,
+    #[serde(default)]
+    sites: Vec<String>,
+    #[serde(default)]
+    kinds: Vec<String>,
+    #[serde(default)]
+    languages: Vec<String>,
+    #[serde(default)]
+    min_engagement: i64,
+    #[serde(default)]
+    excluded_authors: Vec<String>,
+    #[serde(default)]
+    excluded_labels: Vec<String>,
+}
+
+fn default_algorithm() -> String {
+    Algorithm::Chronological.as_str().to_string()
+}
+
+const DAY: u64 = 24 * 60 * 60;
+/// The longest display name the app accepts for a feed generator
+const MAX_DISPLAY_NAME_LEN: usize = 24;
+
+impl Default for File {
+    fn default() -> Self {
+        Self {
+            port: 3000,
+            shutdown_timeout_secs: 30,
+            database: Default::default(),
+            relay: Default::default(),
+            service: Default::default(),
+            feeds: Default::default(),
+            ingest: Default::default(),
+            retention: Default::default(),
+            admin: Default::default(),
+            feed: vec![],
+        }
+    }
+}
+
+impl Default for RelaySection {
+    fn default() -> Self {
+        Self {
+            host: "bsky.network".to_string(),
+            labelers: vec![],
+            stall_threshold_secs: 5 * 60,
+        }
+    }
+}
+
+impl Default for FeedsSection {
+    fn default() -> Self {
+        Self {
+            reply_policy: ReplyPolicy::default().as_str().to_string(),
+            excluded_labels: ["spam", "porn", "!hide"].map(String::from).to_vec(),
+            max_posts_per_author_per_page: 3,
+            preference_half_life_days: 14,
+            pinned_posts: vec![],
+        }
+    }
+}
+
+impl Default for IngestSection {
+    fn default() -> Self {
+        let batch = BatchConfig::default();
+        let spam = SpamConfig::default();
+        Self {
+            batch_size: batch.max_size,
+            batch_delay_ms: batch.max_delay.as_millis() as u64,
+            max_posts_per_author: spam.max_posts_per_window,
+            rate_limit_window_secs: spam.rate_limit_window.as_secs(),
+            duplicate_window_secs: spam.duplicate_window.as_secs(),
+        }
+    }
+}
+
+impl Default for RetentionSection {
+    fn default() -> Self {
+        let retention = RetentionConfig::default();
+        Self {
+            post_max_age_days: retention.post_max_age.as_secs() / DAY,
+            prune_interval_secs: retention.prune_interval.as_secs(),
+            vacuum_interval_secs: retention.vacuum_interval.as_secs(),
+        }
+    }
+}
+
+impl File {
+    fn read(path: &Path) -> Result<Self> {
+        let contents = std::fs::read_to_string(path)
+            .with_context(|| format!("failed to read config file {}", path.display()))?;
+        toml::from_str(&contents)
+            .with_context(|| format!("failed to parse config file {}", path.display()))
+    }
+
+    /// Overrides settings with the env vars `env` returns. Lists are comma separated
+    fn with_overrides(mut self, env: impl Fn(&str) -> Option<String>) -> Result<Self> {
+        let env = Env(env);
+
+        env.parse("PORT", &mut self.port)?;
+        env.parse(
+            "FEEDGEN_SHUTDOWN_TIMEOUT_SECS",
+            &mut self.shutdown_timeout_secs,
+        )?;
+
+        env.parse("DATABASE_URL", &mut self.database.url)?;
+
+        env.parse("FEEDGEN_RELAY", &mut self.relay.host)?;
+        env.list("FEEDGEN_LABELERS", &mut self.relay.labelers);
+        env.parse(
+            "FEEDGEN_STALL_THRESHOLD_SECS",
+            &mut self.relay.stall_threshold_secs,
+        )?;
+
+        env.parse("FEEDGEN_SERVICE_DID", &mut self.service.did)?;
+        env.parse("FEEDGEN_PUBLISHER_DID", &mut self.service.publisher_did)?;
+        env.parse("FEEDGEN_HOSTNAME", &mut self.service.hostname)?;
+        env.parse("FEEDGEN_SERVICE_PUBLIC_KEY", &mut self.service.public_key)?;
+        env.parse(
+            "FEEDGEN_ALLOW_LOCALHOST_DIDS",
+            &mut self.service.allow_localhost_dids,
+        )?;
+
+        env.parse("FEEDGEN_REPLY_POLICY", &mut self.feeds.reply_policy)?;
+        env.list("FEEDGEN_EXCLUDED_LABELS", &mut self.feeds.excluded_labels);
+        env.parse(
+            "FEEDGEN_MAX_POSTS_PER_AUTHOR_PER_PAGE",
+            &mut self.feeds.max_posts_per_author_per_page,
+        )?;
+        env.parse(
+            "FEEDGEN_PREFERENCE_HALF_LIFE_DAYS",
+            &mut self.feeds.preference_half_life_days,
+        )?;
+        env.list("FEEDGEN_PINNED_POSTS", &mut self.feeds.pinned_posts);
+
+        env.parse("FEEDGEN_BATCH_SIZE", &mut self.ingest.batch_size)?;
+        env.parse("FEEDGEN_BATCH_DELAY_MS", &mut self.ingest.batch_delay_ms)?;
+        env.parse(
+            "FEEDGEN_MAX_POSTS_PER_AUTHOR",
+            &mut self.ingest.max_posts_per_author,
+        )?;
+        env.parse(
+            "FEEDGEN_RATE_LIMIT_WINDOW_SECS",
+            &mut self.ingest.rate_limit_window_secs,
+        )?;
+        env.parse(
+            "FEEDGEN_DUPLICATE_WINDOW_SECS",
+            &mut self.ingest.duplicate_window_secs,
+        )?;
+
+        env.parse(
+            "FEEDGEN_POST_MAX_AGE_DAYS",
+            &mut self.retention.post_max_age_days,
+        )?;
+        env.parse(
+            "FEEDGEN_PRUNE_INTERVAL_SECS",
+            &mut self.retention.prune_interval_secs,
+        )?;
+        env.parse(
+            "FEEDGEN_VACUUM_INTERVAL_SECS",
+            &mut self.retention.vacuum_interval_secs,
+        )?;
+
+        if let Some(token) = env.get("FEEDGEN_ADMIN_TOKEN") {
+            self.admin.token = Some(token);
+        }
+
+        Ok(self)
+    }
+
+    /// Checks every setting, and reports all the invalid ones at once
+    fn validate(self) -> Result<Config> {
+        let mut errors = vec![];
+
+        if self.database.url.is_empty() {
+            errors.push("database.url must be set".to_string());
+        }
+
+        if self.relay.host.is_empty() {
+            errors.push("relay.host must be set".to_string());
+        }
+        if self.relay.labelers.iter().any(|labeler| labeler.is_empty()) {
+            errors.push("relay.labelers can't contain empty hosts".to_string());
+        }
+
+        for (key, did) in [
+            ("service.did", &self.service.did),
+            ("service.publisher_did", &self.service.publisher_did),
+        ] {
+            if did.is_empty() {
+                errors.push(format!("{key} must be set"));
+            } else if let Err(err) = validate_did(did) {
+                errors.push(format!("{key} {did:?} is not a valid DID: {err}"));
+            }
+        }
+
+        let hostname = &self.service.hostname;
+        if hostname.is_empty() {
+            errors.push("service.hostname must be set".to_string());
+        } else if !is_bare_hostname(hostname) {
+            errors.push(format!(
+                "service.hostname {hostname:?} must be a bare hostname like feed.example.com, without a scheme or path, and without a port unless it's localhost"
+            ));
+        } else if let Err(err) = did::check_service_did(&self.service.did, hostname) {
+            errors.push(format!("service.did doesn't match service.hostname: {err}"));
+        }
+
+        let public_key = match self.service.public_key.as_str() {
+            "" => None,
+            _ if !self.service.did.starts_with("did:web:") => {
+                errors.push(
+                    "service.public_key can only be published for a did:web service.did"
+                        .to_string(),
+                );
+                None
+            }
+            key => PublicKey::parse(key)
+                .map_err(|err| errors.push(format!("service.public_key is invalid: {err}")))
+                .ok(),
+        };
+
+        let reply_policy = match self.feeds.reply_policy.parse::<ReplyPolicy>() {
+            Ok(policy) => policy,
+            Err(err) => {
+                errors.push(format!("feeds.reply_policy: {err}"));
+                Default::default()
+            }
+        };
+        if self.feeds.max_posts_per_author_per_page == 0 {
+            errors.push("feeds.max_posts_per_author_per_page must be at least 1".to_string());
+        }
+        for pin in &self.feeds.pinned_posts {
+            match AtUri::parse(pin) {
+                Ok(uri) if uri.rkey.is_none() => {
+                    errors.push(format!("feeds.pinned_posts: {pin:?} is not a record uri"))
+                }
+                Ok(_) => {}
+                Err(err) => errors.push(format!("feeds.pinned_posts: {pin:?} is invalid, {err}")),
+            }
+        }
+
+        let feeds = if self.feed.is_empty() {
+            Feeds::default()
+        } else {
+            let mut rkeys = HashSet::new();
+            let definitions = self
+                .feed
+                .into_iter()
+                .filter_map(|feed| {
+                    if !rkeys.insert(feed.rkey.clone()) {
+                        errors.push(format!("feed {:?} is defined more than once", feed.rkey));
+                    }
+                    feed.validate().map_err(|err| errors.extend(err)).ok()
+                })
+                .collect();
+            Feeds::new(definitions)
+        };
+
+        for (key, value) in [
+            ("shutdown_timeout_secs", self.shutdown_timeout_secs),
+            ("ingest.batch_size", self.ingest.batch_size as u64),
+            ("ingest.batch_delay_ms", self.ingest.batch_delay_ms),
+            (
+                "retention.prune_interval_secs",
+                self.retention.prune_interval_secs,
+            ),
+            (
+                "retention.vacuum_interval_secs",
+                self.retention.vacuum_interval_secs,
+            ),
+        ] {
+            if value == 0 {
+                errors.push(format!("{key} must be more than 0"));
+            }
+        }
+
+        if !errors.is_empty() {
+            bail!("invalid config:\n  {}", errors.join("\n  "));
+        }
+
+        Ok(Config {
+            port: self.port,
+            database_url: self.database.url,
+            relay: self.relay.host.clone(),
+            labelers: self.relay.labelers,
+            shutdown_timeout: Duration::from_secs(self.shutdown_timeout_secs),
+            feeds,
+            server: server::Config {
+                service_did: self.service.did,
+                publisher_did: self.service.publisher_did,
+                hostname: self.service.hostname,
+                public_key,
+                reply_policy,
+                excluded_labels: self.feeds.excluded_labels,
+                max_posts_per_author_per_page: self.feeds.max_posts_per_author_per_page,
+                preference_half_life: Duration::from_secs(
+                    self.feeds.preference_half_life_days * DAY,
+                ),
+                pinned_posts: self.feeds.pinned_posts,
+                admin_token: self.admin.token.filter(|token| !token.is_empty()),
+                stall_threshold: Duration::from_secs(self.relay.stall_threshold_secs),
+                relay: self.relay.host,
+            },
+            spam: SpamConfig {
+                max_posts_per_window: self.ingest.max_posts_per_author,
+                rate_limit_window: Duration::from_secs(self.ingest.rate_limit_window_secs),
+                duplicate_window: Duration::from_secs(self.ingest.duplicate_window_secs),
+            },
+            batch: BatchConfig {
+                max_size: self.ingest.batch_size,
+                max_delay: Duration::from_millis(self.ingest.batch_delay_ms),
+            },
+            retention: RetentionConfig {
+                post_max_age: Duration::from_secs(self.retention.post_max_age_days * DAY),
+                prune_interval: Duration::from_secs(self.retention.prune_interval_secs),
+                vacuum_interval: Duration::from_secs(self.retention.vacuum_interval_secs),
+            },
+            allow_localhost_dids: self.service.allow_localhost_dids,
+        })
+    }
+}
+
+impl FeedTable {
+    /// Returns every problem with the table, prefixed by the feed's rkey
+    fn validate(self) -> Result<FeedDefinition, Vec<String>> {
+        let mut errors = vec![];
+        let rkey = &self.rkey;
+
+        if !is_valid_rkey(rkey) {
+            errors.push(format!(
+                "feed rkey {rkey:?} must be 1 to 512 letters, digits, or any of . _ ~ -"
+            ));
+        }
+        let name = self.name.trim();
+        if name.is_empty() || name.chars().count() > MAX_DISPLAY_NAME_LEN {
+            errors.push(format!(
+                "feed {rkey:?}: name must be 1 to {MAX_DISPLAY_NAME_LEN} characters"
+            ));
+        }
+        if let Some(avatar) = &self.avatar {
+            let extension = avatar.extension().and_then(|ext| ext.to_str());
+            if !matches!(extension, Some("png" | "jpg" | "jpeg")) {
+                errors.push(format!(
+                    "feed {rkey:?}: avatar {} must be a png or jpeg",
+                    avatar.display()
+                ));
+            }
+        }
+        if self.min_engagement < 0 {
+            errors.push(format!("feed {rkey:?}: min_engagement can't be negative"));
+        }
+
+        let algorithm = self.algorithm.parse::<Algorithm>().unwrap_or_else(|err| {
+            errors.push(format!("feed {rkey:?}: {err}"));
+            Algorithm::Chronological
+        });
+        let reply_policy = self.reply_policy.as_deref().map(|policy| {
+            policy.parse::<ReplyPolicy>().unwrap_or_else(|err| {
+                errors.push(format!("feed {rkey:?}: {err}"));
+                ReplyPolicy::default()
+            })
+        });
+        let sites = parse_all(rkey, &self.sites, &mut errors);
+        let kinds = parse_all(rkey, &self.kinds, &mut errors);
+
+        if !errors.is_empty() {
+            return Err(errors);
+        }
+
+        Ok(FeedDefinition {
+            display_name: name.to_string(),
+            rkey: self.rkey,
+            description: self
+                .description
+                .filter(|description| !description.is_empty()),
+            avatar: self.avatar,
+            algorithm,
+            filters: Filters {
+                reply_policy,
+                sites,
+                kinds,
+                languages: self
+                    .languages
+                    .iter()
+                    .map(|lang| lang.to_lowercase())
+                    .collect(),
+                min_engagement: self.min_engagement,
+                excluded_authors: self.excluded_authors,
+                excluded_labels: self.excluded_labels,
+            },
+        })
+    }
+}
+
+struct Env<F>(F);
+
+impl<F: Fn(&str) -> Option<String>> Env<F> {
+    fn get(&self, key: &str) -> Option<String> {
+        (self.0)(key)
+    }
+
+    fn parse<T>(&self, key: &str, field: &mut T) -> Result<()>
+    where
+        T: std::str::FromStr,
+        T::Err: std::error::Error + Send + Sync + 'static,
+    {
+        if let Some(value) = self.get(key) {
+            *field = value
+                .parse()
+                .with_context(|| format!("failed to parse {key}"))?;
+        }
+
+        Ok(())
+    }
+
+    fn list(&self, key: &str, field: &mut Vec<String>) {
+        if let Some(value) = self.get(key) {
+            *field = value
+                .split(',')
+                .map(str::trim)
+                .filter(|item| !item.is_empty())
+                .map(String::from)
+                .collect();
+        }
+    }
+}
+
+/// Parses every value in `values`, adding an error for each one that fails
+fn parse_all<T>(rkey: &str, values: &[String], errors: &mut Vec<String>) -> Vec<T>
+where
+    T: std::str::FromStr<Err = anyhow::Error>,
+{
+    values
+        .iter()
+        .filter_map(|value| {
+            value
+                .parse()
+                .map_err(|err| errors.push(format!("feed {rkey:?}: {err}")))
+                .ok()
+        })
+        .collect()
+}
+
+/// Whether `rkey` can be the record key of a feed generator record
+fn is_valid_rkey(rkey: &str) -> bool {
+    (1..=512).contains(&rkey.len())
+        && rkey != "."
+        && rkey != ".."
+        && rkey
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '.' | '_' | '~' | '-'))
+}
+
+/// Whether `hostname` is only a hostname, like `feed.example.com`. Only `localhost` can have a
+/// port, for local development
+fn is_bare_hostname(hostname: &str) -> bool {
+    if let Some(port) = hostname.strip_prefix("localhost:") {
+        return !port.is_empty() && port.bytes().all(|b| b.is_ascii_digit());
+    }
+
+    hostname.split('.').all(|label| {
+        !label.is_empty() && label.chars().all(|c| c.is_ascii_alphanumeric() || c == '-')
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::collections::HashMap;
+
+    use crate::link_finder::{Kind, Site};
+
+    const VALID: &str = r#"
+        [database]
+        url = "sqlite://db/database.sqlite"
+
+        [service]
+        did = "did:web:feed.example.com"
+        publisher_did = "did:plc:pub6kzfhyqdrwv3lm2xe5a7t"
+        hostname = "feed.example.com"
+    "#;
+
+    fn load(toml: &str, env: &[(&str, &str)]) -> Result<Config> {
+        let env = env
+            .iter()
+            .map(|(key, value)| (key.to_string(), value.to_string()))
+            .collect::<HashMap<_, _>>();
+
+        toml::from_str::<File>(toml)?
+            .with_overrides(|key| env.get(key).cloned())?
+            .validate()
+    }
+
+    #[test]
+    fn test_defaults() {
+        let config = load(VALID, &[]).unwrap();
+
+        assert_eq!(3000, config.port);
+        assert_eq!("bsky.network", config.relay);
+        assert_eq!(ReplyPolicy::All, config.server.reply_policy);
+        assert_eq!(None, config.server.admin_token);
+        assert_eq!(100, config.batch.max_size);
+        assert_eq!(Duration::from_secs(30 * DAY), config.retention.post_max_age);
+    }
+
+    #[test]
+    fn test_file() {
+        let config = load(
+            &format!(
+                r#"
+                port = 8080
+                {VALID}
+
+                [relay]
+                host = "ws://localhost:9000"
+                labelers = ["mod.bsky.app"]
+
+                [feeds]
+                reply_policy = "top_level"
+                pinned_posts = ["at://did:plc:pub6kzfhyqdrwv3lm2xe5a7t/app.bsky.feed.post/pinned"]
+
+                [admin]
+                token = "secret"
+                "#
+            ),
+            &[],
+        )
+        .unwrap();
+
+        assert_eq!(8080, config.port);
+        assert_eq!("ws://localhost:9000", config.relay);
+        assert_eq!(vec!["mod.bsky.app"], config.labelers);
+        assert_eq!(ReplyPolicy::TopLevel, config.server.reply_policy);
+        assert_eq!(1, config.server.pinned_posts.len());
+        assert_eq!(Some("secret"), config.server.admin_token.as_deref());
+    }
+
+    #[test]
+    fn test_env_overrides_file() {
+        let config = load(
+            VALID,
+            &[
+                ("PORT", "4000"),
+                ("DATABASE_URL", "postgres://localhost/feed"),
+                ("FEEDGEN_LABELERS", "a.example, b.example,"),
+                ("FEEDGEN_BATCH_DELAY_MS", "250"),
+                ("FEEDGEN_ALLOW_LOCALHOST_DIDS", "true"),
+            ],
+        )
+        .unwrap();
+
+        assert_eq!(4000, config.port);
+        assert_eq!("postgres://localhost/feed", config.database_url);
+        assert_eq!(vec!["a.example", "b.example"], config.labelers);
+        assert_eq!(Duration::from_millis(250), config.batch.max_delay);
+        assert!(config.allow_localhost_dids);
+
+        assert!(load(VALID, &[("FEEDGEN_BATCH_SIZE", "lots")]).is_err());
+    }
+
+    #[test]
+    fn test_validation() {
+        let err = load(
+            r#"
+            [service]
+            did = "feed.example.com"
+            publisher_did = "did:plc:pub6kzfhyqdrwv3lm2xe5a7t"
+            hostname = "https://feed.example.com/"
+
+            [ingest]
+            batch_size = 0
+            "#,
+            &[],
+        )
+        .err()
+        .unwrap()
+        .to_string();
+
+        assert!(err.contains("database.url must be set"), "{err}");
+        assert!(err.contains("service.did"), "{err}");
+        assert!(!err.contains("service.publisher_did"), "{err}");
+        assert!(err.contains("service.hostname"), "{err}");
+        assert!(err.contains("ingest.batch_size"), "{err}");
+    }
+
+    #[test]
+    fn test_feeds() {
+        let config = load(VALID, &[]).unwrap();
+        assert!(config.feeds.get("music").is_some());
+        assert!(config.feeds.get("music-for-you").is_some());
+
+        let config = load(
+            &format!(
+                r#"
+                {VALID}
+
+                [[feed]]
+                rkey = "bandcamp-albums"
+                name = "Bandcamp albums"
+                sites = ["bandcamp"]
+                kinds = ["album"]
+                languages = ["EN"]
+                reply_policy = "top_level"
+
+                [[feed]]
+                rkey = "hot"
+                name = "Hot tracks"
+                algorithm = "trending"
+                min_engagement = 5
+                excluded_labels = ["nudity"]
+                "#
+            ),
+            &[],
+        )
+        .unwrap();
+        let feeds = &config.feeds;
+
+        assert_eq!(2, feeds.iter().count());
+        assert!(feeds.get("music").is_none());
+
+        let albums = feeds.get("bandcamp-albums").unwrap();
+        assert_eq!(Algorithm::Chronological, albums.algorithm);
+        assert_eq!(vec![Site::Bandcamp], albums.filters.sites);
+        assert_eq!(vec![Kind::Album], albums.filters.kinds);
+        assert_eq!(vec!["en"], albums.filters.languages);
+        assert_eq!(Some(ReplyPolicy::TopLevel), albums.filters.reply_policy);
+
+        let hot = feeds.get("hot").unwrap();
+        assert_eq!(Algorithm::Trending, hot.algorithm);
+        assert_eq!(5, hot.filters.min_engagement);
+        assert_eq!(vec!["nudity"], hot.filters.excluded_labels);
+    }
+
+    #[test]
+    fn test_service_identity() {
+        let key = "zQ3shXjHeiBuRCKmM36cuYnm7YEMzhGnCmCyW92sRJ9pribSF";
+        let config = load(VALID, &[("FEEDGEN_SERVICE_PUBLIC_KEY", key)]).unwrap();
+        assert_eq!(
+            Some(key),
+            config.server.public_key.as_ref().map(PublicKey::as_str)
+        );
+
+        let err = load(VALID, &[("FEEDGEN_HOSTNAME", "other.example.com")])
+            .err()
+            .unwrap()
+            .to_string();
+        assert!(err.contains("service.did doesn't match"), "{err}");
+
+        let err = load(
+            VALID,
+            &[
+                ("FEEDGEN_SERVICE_DID", "did:plc:z72i7hdynmk6r22z27h6tvur"),
+                ("FEEDGEN_SERVICE_PUBLIC_KEY", key),
+            ],
+        )
+        .err()
+        .unwrap()
+        .to_string();
+        assert!(err.contains("did:web service.did"), "{err}");
+
+        let err = load(VALID, &[("FEEDGEN_SERVICE_PUBLIC_KEY", "not a key")])
+            .err()
+            .unwrap()
+            .to_string();
+        assert!(err.contains("service.public_key is invalid"), "{err}");
+    }
+
+    #[test]
+    fn test_feed_validation() {
+        let err = load(
+            &format!(
+                r#"
+                {VALID}
+
+                [[feed]]
+                rkey = "music"
+                name = "Music"
+
+                [[feed]]
+                rkey = "music"
+                name = "A name that is far too long to show"
+                algorithm = "random"
+                sites = ["myspace"]
+
+                [[feed]]
+                rkey = "no spaces"
+                name = "Spaces"
+                "#
+            ),
+            &[],
+        )
+        .err()
+        .unwrap()
+        .to_string();
+
+        assert!(err.contains("\"music\" is defined more than once"), "{err}");
+        assert!(err.contains("name must be"), "{err}");
+        assert!(err.contains("unknown algorithm random"), "{err}");
+        assert!(err.contains("unknown site myspace"), "{err}");
+        assert!(err.contains("\"no spaces\""), "{err}");
+    }
+
+    #[test]
+    fn test_example_parses() {
+        toml::from_str::<File>(include_str!("../config.example.toml")).unwrap();
+    }
+
+    #[test]
+    fn test_unknown_settings() {
+        assert!(toml::from_str::<File>("[feeds]\nreply_polcy = \"all\"").is_err());
+    }
+
+    #[test]
+    fn test_is_bare_hostname() {
+        assert!(is_bare_hostname("feed.example.com"));
+        assert!(is_bare_hostname("localhost"));
+        assert!(!is_bare_hostname("https://feed.example.com"));
+        assert!(!is_bare_hostname("feed.example.com:3000"));
+        assert!(!is_bare_hostname("feed.example.com/"));
+        assert!(is_bare_hostname("localhost:3000"));
+        assert!(!is_bare_hostname("localhost:"));
+        assert!(!is_bare_hostname("localhost:port"));
+    }
+}
@@ -0,0 +1,336 @@
+//! The DID document served at `/.well-known/did.json`, for services with a did:web identity, and
+//! the `#atproto` keys of other DID documents
+
+use k256::ecdsa::signature::Verifier;
+use serde_json::{json, Value};
+
+/// The multicodecs of the key types atproto supports, as varints
+const SECP256K1_PUB: [u8; 2] = [0xe7, 0x01];
+const P256_PUB: [u8; 2] = [0x80, 0x24];
+
+const BASE58_ALPHABET: &[u8] = b"123456789ABCDEFGHJKLMNPQRSTUVWXYZabcdefghijkmnopqrstuvwxyz";
+
+/// A compressed secp256k1 or p256 public key, multibase encoded like atproto's `Multikey`
+/// verification methods
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PublicKey(String);
+
+impl PublicKey {
+    /// Takes either the multibase key, like `zQ3sh...`, or the `did:key` form of it
+    pub fn parse(key: &str) -> Result<Self, String> {
+        let multibase = key.strip_prefix("did:key:").unwrap_or(key);
+        let Some(bytes) = multibase.strip_prefix('z').and_then(decode_base58) else {
+            return Err("expected a base58btc multibase key, starting with z".to_string());
+        };
+
+        let key = match bytes.split_at_checked(2) {
+            Some((codec, key)) if codec == SECP256K1_PUB || codec == P256_PUB => key,
+            _ => return Err("expected a secp256k1 or p256 public key".to_string()),
+        };
+        if key.len() != 33 || !matches!(key[0], 0x02 | 0x03) {
+            return Err("expected a compressed public key".to_string());
+        }
+
+        Ok(Self(multibase.to_string()))
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+
+    /// The jwt `alg` of signatures made with this key
+    pub fn jwt_alg(&self) -> &'static str {
+        match self.decode().0 {
+            SECP256K1_PUB => "ES256K",
+            _ => "ES256",
+        }
+    }
+
+    /// Checks a signature of `message`, in the 64 byte `r || s` form jwts use. Like atproto
+    /// requires, signatures with a high `s` aren't accepted
+    pub fn verify(&self, message: &[u8], signature: &[u8]) -> bool {
+        let (codec, key) = self.decode();
+        match codec {
+            SECP256K1_PUB => {
+                let (Ok(key), Ok(signature)) = (
+                    k256::ecdsa::VerifyingKey::from_sec1_bytes(&key),
+                    k256::ecdsa::Signature::from_slice(signature),
+                ) else {
+                    return false;
+                };
+                signature.normalize_s().is_none() && key.verify(message, &signature).is_ok()
+            }
+            _ => {
+                let (Ok(key), Ok(signature)) = (
+                    p256::ecdsa::VerifyingKey::from_sec1_bytes(&key),
+                    p256::ecdsa::Signature::from_slice(signature),
+                ) else {
+                    return false;
+                };
+                signature.normalize_s().is_none() && key.verify(message, &signature).is_ok()
+            }
+        }
+    }
+
+    /// The multicodec and the compressed key, which `parse` already checked
+    fn decode(&self) -> ([u8; 2], Vec<u8>) {
+        let bytes = decode_base58(&self.0[1..]).expect("parsed keys are base58");
+        ([bytes[0], bytes[1]], bytes[2..].to_vec())
+    }
+}
+
+/// The `#atproto` key of `did`, from its DID `document`
+pub fn atproto_key(did: &str, document: &Value) -> Option<PublicKey> {
+    if document.get("id")?.as_str()? != did {
+        return None;
+    }
+
+    document
+        .get("verificationMethod")?
+        .as_array()?
+        .iter()
+        .find(|method| {
+            method
+                .get("id")
+                .and_then(Value::as_str)
+                .is_some_and(|id| id == "#atproto" || id == format!("{did}#atproto"))
+        })
+        .and_then(|method| method.get("publicKeyMultibase")?.as_str())
+        .and_then(|key| PublicKey::parse(key).ok())
+}
+
+/// The DID document of `did`, served from `hostname`. If there's a `key`, it's published as the
+/// `#atproto` verification method
+pub fn document(did: &str, hostname: &str, key: Option<&PublicKey>) -> Value {
+    let mut document = json!({
+        "@context": ["https://www.w3.org/ns/did/v1"],
+        "id": did,
+        "service": [
+            {
+                "id": "#bsky_fg",
+                "type": "BskyFeedGenerator",
+                "serviceEndpoint": format!("https://{hostname}")
+            }
+        ]
+    });
+
+    if let Some(key) = key {
+        document["@context"] = json!([
+            "https://www.w3.org/ns/did/v1",
+            "https://w3id.org/security/multikey/v1"
+        ]);
+        document["verificationMethod"] = json!([
+            {
+                "id": format!("{did}#atproto"),
+                "type": "Multikey",
+                "controller": did,
+                "publicKeyMultibase": key.as_str()
+            }
+        ]);
+    }
+
+    document
+}
+
+/// Checks that a did:web `did` is the one for `hostname`. Other DIDs can't be checked, since
+/// their documents aren't served by us.
+///
+/// The port of a `localhost:3000` hostname is percent-encoded in its DID, as
+/// `did:web:localhost%3A3000`
+pub fn check_service_did(did: &str, hostname: &str) -> Result<(), String> {
+    let Some(identifier) = did.strip_prefix("did:web:") else {
+        return Ok(());
+    };
+
+    let host = identifier.replace("%3A", ":");
+    if host.eq_ignore_ascii_case(hostname) {
+        Ok(())
+    } else {
+        Err(format!(
+            "{did} would have to be served from {host}, not {hostname}. Use did:web:{}",
+            hostname.replace(':', "%3A")
+        ))
+    }
+}
+
+/// Encodes `bytes` as base58btc, the inverse of `decode_base58`
+#[cfg(test)]
+pub fn encode_base58(bytes: &[u8]) -> String {
+    // little endian digits, without the leading zeros
+    let mut digits: Vec<u8> = vec![];
+    for &byte in bytes {
+        let mut carry = u32::from(byte);
+        for digit in digits.iter_mut() {
+            carry += u32::from(*digit) << 8;
+            *digit = (carry % 58) as u8;
+            carry /= 58;
+        }
+        while carry > 0 {
+            digits.push((carry % 58) as u8);
+            carry /= 58;
+        }
+    }
+
+    let zeros = bytes.iter().take_while(|&&b| b == 0).count();
+    std::iter::repeat_n(b'1', zeros)
+        .chain(
+            digits
+                .iter()
+                .rev()
+                .map(|&digit| BASE58_ALPHABET[usize::from(digit)]),
+        )
+        .map(char::from)
+        .collect()
+}
+
+fn decode_base58(s: &str) -> Option<Vec<u8>> {
+    // big endian, without the leading zeros
+    let mut bytes: Vec<u8> = vec![];
+    for c in s.bytes() {
+        let mut carry = BASE58_ALPHABET.iter().position(|&b| b == c)? as u32;
+        for byte in bytes.iter_mut().rev() {
+            carry += u32::from(*byte) * 58;
+            *byte = carry as u8;
+            carry >>= 8;
+        }
+        while carry > 0 {
+            bytes.insert(0, carry as u8);
+            carry >>= 8;
+        }
+    }
+
+    // each leading 1 is a zero byte
+    let zeros = s.bytes().take_while(|&b| b == b'1').count();
+    let mut decoded = vec![0; zeros];
+    decoded.extend(bytes);
+
+    Some(decoded)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SECP256K1: &str = "zQ3shXjHeiBuRCKmM36cuYnm7YEMzhGnCmCyW92sRJ9pribSF";
+    const P256: &str = "zDnaembgSGUhZULN2Caob4HLJPaxBh92N7rtH21TErzqf8HQo";
+
+    #[test]
+    fn test_parse_key() {
+        assert_eq!(SECP256K1, PublicKey::parse(SECP256K1).unwrap().as_str());
+        assert_eq!(P256, PublicKey::parse(P256).unwrap().as_str());
+        assert_eq!(
+            SECP256K1,
+            PublicKey::parse(&format!("did:key:{SECP256K1}"))
+                .unwrap()
+                .as_str()
+        );
+    }
+
+    #[test]
+    fn test_parse_invalid_key() {
+        // not base58btc
+        assert!(PublicKey::parse("").is_err());
+        assert!(PublicKey::parse(&SECP256K1[1..]).is_err());
+        assert!(PublicKey::parse("z0OIl").is_err());
+        // an ed25519 key
+        assert!(PublicKey::parse("z6MkhaXgBZDvotDkL5257faiztiGiC2QtKLGpbnnEGta2doK").is_err());
+        // too short
+        assert!(PublicKey::parse(&SECP256K1[..SECP256K1.len() - 4]).is_err());
+    }
+
+    #[test]
+    fn test_decode_base58() {
+        assert_eq!(Some(vec![]), decode_base58(""));
+        assert_eq!(Some(vec![0, 0, 1]), decode_base58("112"));
+        assert_eq!(
+            Some(b"hello world".to_vec()),
+            decode_base58("StV1DL6CwTryKyV")
+        );
+        assert_eq!(None, decode_base58("0"));
+    }
+
+    #[test]
+    fn test_encode_base58() {
+        assert_eq!("", encode_base58(&[]));
+        assert_eq!("112", encode_base58(&[0, 0, 1]));
+        assert_eq!("StV1DL6CwTryKyV", encode_base58(b"hello world"));
+    }
+
+    #[test]
+    fn test_atproto_key() {
+        let did = "did:plc:ragtjsm2j2vknwkz3zp4oxrd";
+        let document = json!({
+            "id": did,
+            "verificationMethod": [
+                {
+                    "id": format!("{did}#atproto"),
+                    "type": "Multikey",
+                    "controller": did,
+                    "publicKeyMultibase": SECP256K1
+                }
+            ]
+        });
+
+        let key = atproto_key(did, &document).unwrap();
+        assert_eq!(SECP256K1, key.as_str());
+        assert_eq!("ES256K", key.jwt_alg());
+        assert_eq!("ES256", PublicKey::parse(P256).unwrap().jwt_alg());
+
+        // a document for someone else
+        assert!(atproto_key("did:plc:else5xkq2vmz7nwr3ly4dpt6", &document).is_none());
+    }
+
+    #[test]
+    fn test_document() {
+        let document = document("did:web:feed.example.com", "feed.example.com", None);
+
+        assert_eq!(
+            json!({
+                "@context": ["https://www.w3.org/ns/did/v1"],
+                "id": "did:web:feed.example.com",
+                "service": [
+                    {
+                        "id": "#bsky_fg",
+                        "type": "BskyFeedGenerator",
+                        "serviceEndpoint": "https://feed.example.com"
+                    }
+                ]
+            }),
+            document
+        );
+    }
+
+    #[test]
+    fn test_document_with_key() {
+        let key = PublicKey::parse(SECP256K1).unwrap();
+        let document = document("did:web:feed.example.com", "feed.example.com", Some(&key));
+
+        assert_eq!(
+            json!([
+                {
+                    "id": "did:web:feed.example.com#atproto",
+                    "type": "Multikey",
+                    "controller": "did:web:feed.example.com",
+                    "publicKeyMultibase": SECP256K1
+                }
+            ]),
+            document["verificationMethod"]
+        );
+        assert_eq!(
+            json!("https://w3id.org/security/multikey/v1"),
+            document["@context"][1]
+        );
+    }
+
+    #[test]
+    fn test_check_service_did() {
+        assert!(check_service_did("did:web:feed.example.com", "feed.example.com").is_ok());
+        assert!(check_service_did("did:web:Feed.Example.com", "feed.example.com").is_ok());
+        assert!(check_service_did("did:web:other.example.com", "feed.example.com").is_err());
+        assert!(check_service_did("did:plc:z72i7hdynmk6r22z27h6tvur", "feed.example.com").is_ok());
+
+        assert!(check_service_did("did:web:localhost%3A3000", "localhost:3000").is_ok());
+        assert!(check_service_did("did:web:localhost%3A3000", "localhost:8080").is_err());
+        assert!(check_service_did("did:web:localhost", "localhost:3000").is_err());
+    }
+}
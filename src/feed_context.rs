@@ -0,0 +1,117 @@
+use anyhow::{anyhow, Result};
+
+use crate::link_finder::{Kind, Site};
+
+/// Attached to every item of a feed skeleton as its `feedContext`. The app sends it back to us with
+/// any interactions on that item, so we know what produced it.
+///
+/// It's encoded as `algo:site:kind:reason`, with `-` for missing values. Eg: `music:spotify:track:recent`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FeedContext {
+    /// The algorithm that produced the item. Eg: `music`
+    pub algo: String,
+    /// The site of the item's music link
+    pub site: Option<Site>,
+    /// The kind of the item's music link
+    pub kind: Option<Kind>,
+    pub reason: Reason,
+}
+
+/// Why an item was put where it is in the feed
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Reason {
+    /// Ordered chronologically
+    Recent,
+    /// Pinned to the top of the feed
+    Pinned,
+    /// Ranked using the viewer's preferences
+    Personalised,
+    /// Ranked by likes and reposts
+    Trending,
+}
+
+impl Reason {
+    fn as_str(&self) -> &'static str {
+        match self {
+            Reason::Recent => "recent",
+            Reason::Pinned => "pin",
+            Reason::Personalised => "personal",
+            Reason::Trending => "trending",
+        }
+    }
+}
+
+impl std::fmt::Display for FeedContext {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{}:{}:{}:{}",
+            self.algo,
+            self.site.as_ref().map(Site::as_str).unwrap_or("-"),
+            self.kind.as_ref().map(Kind::as_str).unwrap_or("-"),
+            self.reason.as_str()
+        )
+    }
+}
+
+impl std::str::FromStr for FeedContext {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let [algo, site, kind, reason] = s
+            .split(':')
+            .collect::<Vec<_>>()
+            .try_into()
+            .map_err(|_| anyhow!("invalid feed context {s}"))?;
+
+        Ok(FeedContext {
+            algo: algo.to_string(),
+            site: (site != "-").then(|| site.parse()).transpose()?,
+            kind: (kind != "-").then(|| kind.parse()).transpose()?,
+            reason: match reason {
+                "recent" => Reason::Recent,
+                "pin" => Reason::Pinned,
+                "personal" => Reason::Personalised,
+                "trending" => Reason::Trending,
+                _ => return Err(anyhow!("unknown reason {reason}")),
+            },
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_round_trip() {
+        let context = FeedContext {
+            algo: "music".to_string(),
+            site: Some(Site::Spotify),
+            kind: Some(Kind::Album),
+            reason: Reason::Recent,
+        };
+
+        assert_eq!("music:spotify:album:recent", context.to_string());
+        assert_eq!(context, context.to_string().parse().unwrap());
+    }
+
+    #[test]
+    fn test_missing_values() {
+        let context = FeedContext {
+            algo: "music".to_string(),
+            site: None,
+            kind: None,
+            reason: Reason::Pinned,
+        };
+
+        assert_eq!("music:-:-:pin", context.to_string());
+        assert_eq!(context, context.to_string().parse().unwrap());
+    }
+
+    #[test]
+    fn test_invalid() {
+        assert!("music:spotify:album".parse::<FeedContext>().is_err());
+        assert!("music:myspace:album:recent".parse::<FeedContext>().is_err());
+    }
+}
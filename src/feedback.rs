@@ -0,0 +1,204 @@
+use std::{collections::HashMap, time::Duration};
+
+use anyhow::Result;
+use atrium_api::app::bsky::feed::defs::{REQUEST_LESS, REQUEST_MORE};
+use chrono::{DateTime, Utc};
+
+use crate::{
+    atproto::AtUri,
+    feed_context::FeedContext,
+    models::{posts::Post, preferences::Dimension},
+    storage::Storage,
+};
+
+/// A change to one of a viewer's preferences
+pub struct Adjustment<'a> {
+    pub viewer: &'a str,
+    pub dimension: Dimension,
+    pub value: &'a str,
+    pub delta: f64,
+}
+
+/// How the viewer's preferences change after they asked to see more or less of a post.
+///
+/// The post's author is taken from its uri, and the site and kind from the feed context we
+/// attached to it. Any other event changes nothing
+pub fn adjustments<'a>(
+    viewer: &'a str,
+    item: &'a str,
+    event: &str,
+    feed_context: Option<&str>,
+) -> Vec<Adjustment<'a>> {
+    let delta = match event {
+        REQUEST_MORE => 1.0,
+        REQUEST_LESS => -1.0,
+        _ => return vec![],
+    };
+
+    let mut adjustments = vec![];
+    if let Ok(uri) = AtUri::parse(item) {
+        adjustments.push((Dimension::Author, uri.authority));
+    }
+    let context = feed_context.and_then(|context| context.parse::<FeedContext>().ok());
+    if let Some(site) = context.as_ref().and_then(|context| context.site.as_ref()) {
+        adjustments.push((Dimension::Site, site.as_str()));
+    }
+    if let Some(kind) = context.as_ref().and_then(|context| context.kind.as_ref()) {
+        adjustments.push((Dimension::Kind, kind.as_str()));
+    }
+
+    adjustments
+        .into_iter()
+        .map(|(dimension, value)| Adjustment {
+            viewer,
+            dimension,
+            value,
+            delta,
+        })
+        .collect()
+}
+
+/// A viewer's preferences, decayed to a point in time
+#[derive(Default)]
+pub struct ViewerPreferences {
+    scores: HashMap<(Dimension, String), f64>,
+}
+
+impl ViewerPreferences {
+    pub async fn load(
+        storage: &impl Storage,
+        viewer: &str,
+        now: DateTime<Utc>,
+        half_life: Duration,
+    ) -> Result<Self> {
+        let scores = storage
+            .preferences(viewer)
+            .await?
+            .into_iter()
+            .map(|preference| {
+                let score = preference.decayed_score(now, half_life);
+                ((preference.dimension, preference.value), score)
+            })
+            .collect();
+
+        Ok(Self { scores })
+    }
+
+    /// The sum of the viewer's preferences for the post's author, site, and kind
+    pub fn score(&self, post: &Post) -> f64 {
+        let get = |dimension: Dimension, value: &str| {
+            self.scores
+                .get(&(dimension, value.to_string()))
+                .copied()
+                .unwrap_or(0.0)
+        };
+
+        get(Dimension::Author, &post.author)
+            + post
+                .site
+                .as_ref()
+                .map_or(0.0, |site| get(Dimension::Site, site.as_str()))
+            + post
+                .kind
+                .as_ref()
+                .map_or(0.0, |kind| get(Dimension::Kind, kind.as_str()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::{
+        link_finder::{Kind, Site},
+        storage::SqliteStorage,
+    };
+
+    const DAY: Duration = Duration::from_secs(24 * 60 * 60);
+
+    fn post(author: &str) -> Post {
+        Post {
+            uri: format!("at://{author}/app.bsky.feed.post/a"),
+            cid: "cid".to_string(),
+            author: author.to_string(),
+            indexed_at: Utc::now(),
+            reply_root: None,
+            reply_parent: None,
+            quote: None,
+            site: Some(Site::Spotify),
+            kind: Some(Kind::Track),
+            engagement: 0,
+        }
+    }
+
+    async fn record(
+        storage: &SqliteStorage,
+        event: &str,
+        feed_context: Option<&str>,
+        now: DateTime<Utc>,
+    ) {
+        let adjustments = adjustments(
+            "did:plc:viewer",
+            "at://did:plc:auth3r7xq2lzm5kvwe4ny6sd/app.bsky.feed.post/a",
+            event,
+            feed_context,
+        );
+        storage
+            .store_interactions(&[], &adjustments, now, DAY)
+            .await
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_show_less_lowers_author_site_and_kind() {
+        let storage = SqliteStorage::memory().await;
+        let now = Utc::now();
+
+        record(
+            &storage,
+            REQUEST_LESS,
+            Some("music:spotify:track:recent"),
+            now,
+        )
+        .await;
+
+        let preferences = ViewerPreferences::load(&storage, "did:plc:viewer", now, DAY)
+            .await
+            .unwrap();
+
+        assert_eq!(
+            -3.0,
+            preferences.score(&post("did:plc:auth3r7xq2lzm5kvwe4ny6sd"))
+        );
+        // a different author still shares the site and kind
+        assert_eq!(-2.0, preferences.score(&post("did:plc:other")));
+    }
+
+    #[test]
+    fn test_other_events_are_ignored() {
+        assert!(adjustments(
+            "did:plc:viewer",
+            "at://did:plc:auth3r7xq2lzm5kvwe4ny6sd/app.bsky.feed.post/a",
+            "app.bsky.feed.defs#interactionSeen",
+            Some("music:spotify:track:recent"),
+        )
+        .is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_preferences_are_per_viewer() {
+        let storage = SqliteStorage::memory().await;
+        let now = Utc::now();
+
+        record(&storage, REQUEST_MORE, None, now).await;
+
+        let preferences = ViewerPreferences::load(&storage, "did:plc:someone", now, DAY)
+            .await
+            .unwrap();
+
+        assert_eq!(
+            0.0,
+            preferences.score(&post("did:plc:auth3r7xq2lzm5kvwe4ny6sd"))
+        );
+    }
+}
@@ -0,0 +1,117 @@
+//! The feeds we serve, as defined in the config
+
+use std::path::PathBuf;
+
+use anyhow::{anyhow, Result};
+
+use crate::{
+    algos::ReplyPolicy,
+    link_finder::{Kind, Site},
+};
+
+/// How a feed orders its posts
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Algorithm {
+    /// Newest first
+    Chronological,
+    /// Newest first, moved up or down by the viewer's "show more" and "show less" feedback
+    Personalised,
+    /// Recent posts with the most likes and reposts
+    Trending,
+}
+
+impl Algorithm {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Algorithm::Chronological => "chronological",
+            Algorithm::Personalised => "personalised",
+            Algorithm::Trending => "trending",
+        }
+    }
+}
+
+impl std::str::FromStr for Algorithm {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "chronological" => Ok(Algorithm::Chronological),
+            "personalised" => Ok(Algorithm::Personalised),
+            "trending" => Ok(Algorithm::Trending),
+            _ => Err(anyhow!("unknown algorithm {s}")),
+        }
+    }
+}
+
+/// Narrows down which posts a feed shows. The empty lists don't filter anything
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Filters {
+    /// Overrides the global reply policy
+    pub reply_policy: Option<ReplyPolicy>,
+    pub sites: Vec<Site>,
+    pub kinds: Vec<Kind>,
+    /// Lowercased language tags. `en` also matches `en-us`
+    pub languages: Vec<String>,
+    /// Posts need at least this many likes and reposts
+    pub min_engagement: i64,
+    pub excluded_authors: Vec<String>,
+    /// Excluded on top of the globally excluded labels
+    pub excluded_labels: Vec<String>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct FeedDefinition {
+    /// The record key of the feed generator record. Eg: `music`
+    pub rkey: String,
+    pub display_name: String,
+    pub description: Option<String>,
+    /// A png or jpeg to publish as the feed's avatar
+    pub avatar: Option<PathBuf>,
+    pub algorithm: Algorithm,
+    pub filters: Filters,
+}
+
+/// Every feed we serve
+#[derive(Debug, Clone)]
+pub struct Feeds(Vec<FeedDefinition>);
+
+impl Feeds {
+    pub fn new(feeds: Vec<FeedDefinition>) -> Self {
+        Self(feeds)
+    }
+
+    pub fn get(&self, rkey: &str) -> Option<&FeedDefinition> {
+        self.0.iter().find(|feed| feed.rkey == rkey)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &FeedDefinition> {
+        self.0.iter()
+    }
+}
+
+/// The feeds served when none are configured
+impl Default for Feeds {
+    fn default() -> Self {
+        Self(vec![
+            FeedDefinition {
+                rkey: "music".to_string(),
+                display_name: "Music".to_string(),
+                description: Some("Posts with links to music, newest first".to_string()),
+                avatar: None,
+                algorithm: Algorithm::Chronological,
+                filters: Filters::default(),
+            },
+            FeedDefinition {
+                rkey: "music-for-you".to_string(),
+                display_name: "Music for you".to_string(),
+                description: Some(
+                    "Posts with links to music, tuned by your \"show more\" and \"show less\""
+                        .to_string(),
+                ),
+                avatar: None,
+                algorithm: Algorithm::Personalised,
+                filters: Filters::default(),
+            },
+        ])
+    }
+}
@@ -1,15 +1,18 @@
 use std::{future::Future, pin::Pin, sync::Arc};
 
-use anyhow::{anyhow, Result};
+use anyhow::{anyhow, Context, Result};
 use atrium_api::{
     app::bsky::feed::{
+        like::Record as LikeRecord,
         post::{Record, RecordData as PostRecordData},
-        Post as AtriumPost,
+        Like, Post as AtriumPost, Repost,
     },
     com::atproto::sync::subscribe_repos::Commit,
     types::{CidLink, Collection, Object},
 };
 
+use serde::de::DeserializeOwned;
+
 use super::subscription::CommitHandler;
 
 pub type Post = Object<PostRecordData>;
@@ -39,6 +42,24 @@ pub struct OnPostDeleteParams<'a> {
     pub author: &'a str,
 }
 
+/// A like or a repost
+#[allow(dead_code)]
+pub struct OnEngagementParams<'a> {
+    pub commit: &'a Commit,
+    /// The uri of the like or repost. Eg: `at://did:plc:asdfghjkl/app.bsky.feed.like/qwertyuiop`
+    pub uri: String,
+    /// The uri of the liked or reposted record
+    pub subject: &'a str,
+}
+
+/// A like or a repost was deleted. The record is gone by then, so only its uri is known
+#[allow(dead_code)]
+pub struct OnEngagementDeleteParams<'a> {
+    pub commit: &'a Commit,
+    /// The uri of the like or repost. Eg: `at://did:plc:asdfghjkl/app.bsky.feed.like/qwertyuiop`
+    pub uri: String,
+}
+
 pub type OnPostCreate<DATA> = Arc<
     dyn for<'a> Fn(
             OnPostCreateParams<'a>,
@@ -56,9 +77,29 @@ pub type OnPostDelete<DATA> = Arc<
         + Sync,
 >;
 
+pub type OnEngagement<DATA> = Arc<
+    dyn for<'a> Fn(
+            OnEngagementParams<'a>,
+            Arc<DATA>,
+        ) -> Pin<Box<dyn Future<Output = ()> + Send + 'a>>
+        + Send
+        + Sync,
+>;
+
+pub type OnEngagementDelete<DATA> = Arc<
+    dyn for<'a> Fn(
+            OnEngagementDeleteParams<'a>,
+            Arc<DATA>,
+        ) -> Pin<Box<dyn Future<Output = ()> + Send + 'a>>
+        + Send
+        + Sync,
+>;
+
 pub struct Handler<DATA> {
     pub on_post_create: OnPostCreate<DATA>,
     pub on_post_delete: OnPostDelete<DATA>,
+    pub on_engagement: OnEngagement<DATA>,
+    pub on_engagement_delete: OnEngagementDelete<DATA>,
     pub data: Arc<DATA>,
 }
 
@@ -66,64 +107,85 @@ impl<DATA: Send + Sync> CommitHandler for Handler<DATA> {
     async fn handle_commit(&self, commit: &Commit) -> Result<()> {
         for op in &commit.ops {
             // path is something like `app.bsky.feed.post/3lb3tt5kwha2w`
-            // we only care about posts where the path starts with `app.bsky.feed.post`
-            let Some((AtriumPost::NSID, post_id)) = op.path.split_once('/') else {
+            let Some((collection, rkey)) = op.path.split_once('/') else {
                 continue;
             };
-
-            // skip things that aren't creates
-            if op.action == "create" {
-                // cid exists on create and update, but not on delete
-                let Some(cid) = &op.cid else {
-                    continue;
-                };
-
-                let uri = format!("at://{}/{}", commit.repo.as_str(), &op.path);
-
-                let (items, _header) =
-                    rs_car::car_read_all(&mut commit.blocks.as_slice(), true).await?;
-
-                // get the referenced item out of the list
-                let Some((_, item)) = items.iter().find(|(cid, _)| {
-                    op.cid
-                        .as_ref()
-                        // TODO figure out how to do this equality without to_bytes
-                        .map(|c| c.0.to_bytes() == cid.to_bytes())
-                        .unwrap_or(false)
-                }) else {
-                    return Err(anyhow!(
-                        "FAILED: could not find item with operation cid {:?} out of {} items",
-                        op.cid,
-                        items.len()
-                    ));
-                };
-
-                let record = serde_ipld_dagcbor::from_reader::<Record, _>(&mut item.as_slice())?;
-
-                let params = OnPostCreateParams {
-                    post: &record,
-                    commit,
-                    uri,
-                    post_id,
-                    cid,
-                    author: commit.repo.as_str(),
-                };
-
-                (self.on_post_create)(params, self.data.clone()).await;
-            } else if op.action == "delete" {
-                let uri = format!("at://{}/{}", commit.repo.as_str(), &op.path);
-
-                let params = OnPostDeleteParams {
-                    commit,
-                    uri,
-                    post_id,
-                    author: commit.repo.as_str(),
-                };
-
-                (self.on_post_delete)(params, self.data.clone()).await;
+            let uri = format!("at://{}/{}", commit.repo.as_str(), &op.path);
+
+            match (collection, op.action.as_str()) {
+                (AtriumPost::NSID, "create") => {
+                    // cid exists on create and update, but not on delete
+                    let Some(cid) = &op.cid else {
+                        continue;
+                    };
+                    let record = find_record::<Record>(commit, cid, &uri).await?;
+
+                    let params = OnPostCreateParams {
+                        post: &record,
+                        commit,
+                        uri,
+                        post_id: rkey,
+                        cid,
+                        author: commit.repo.as_str(),
+                    };
+
+                    (self.on_post_create)(params, self.data.clone()).await;
+                }
+                (AtriumPost::NSID, "delete") => {
+                    let params = OnPostDeleteParams {
+                        commit,
+                        uri,
+                        post_id: rkey,
+                        author: commit.repo.as_str(),
+                    };
+
+                    (self.on_post_delete)(params, self.data.clone()).await;
+                }
+                (Like::NSID | Repost::NSID, "create") => {
+                    let Some(cid) = &op.cid else {
+                        continue;
+                    };
+                    // likes and reposts both only point at their subject
+                    let record = find_record::<LikeRecord>(commit, cid, &uri).await?;
+
+                    let params = OnEngagementParams {
+                        commit,
+                        uri,
+                        subject: &record.subject.uri,
+                    };
+
+                    (self.on_engagement)(params, self.data.clone()).await;
+                }
+                (Like::NSID | Repost::NSID, "delete") => {
+                    let params = OnEngagementDeleteParams { commit, uri };
+
+                    (self.on_engagement_delete)(params, self.data.clone()).await;
+                }
+                _ => {}
             }
         }
 
         Ok(())
     }
 }
+
+/// Decodes the record with `cid` out of the commit's blocks
+async fn find_record<T: DeserializeOwned>(commit: &Commit, cid: &CidLink, uri: &str) -> Result<T> {
+    let (items, _header) = rs_car::car_read_all(&mut commit.blocks.as_slice(), true).await?;
+
+    // get the referenced item out of the list
+    // TODO figure out how to do this equality without to_bytes
+    let Some((_, item)) = items
+        .iter()
+        .find(|(item_cid, _)| cid.0.to_bytes() == item_cid.to_bytes())
+    else {
+        return Err(anyhow!(
+            "could not find item for {uri} with operation cid {:?} out of {} items",
+            cid,
+            items.len()
+        ));
+    };
+
+    serde_ipld_dagcbor::from_reader::<T, _>(&mut item.as_slice())
+        .with_context(|| format!("could not decode {uri}"))
+}
@@ -1,16 +1,51 @@
+use std::sync::Arc;
+
 use anyhow::Result;
+use tokio_util::sync::CancellationToken;
 
-pub use self::handler::{Handler, OnPostCreateParams, OnPostDeleteParams};
+pub use self::backoff::Backoff;
+pub use self::handler::{
+    Handler, OnEngagementDeleteParams, OnEngagementParams, OnPostCreateParams, OnPostDeleteParams,
+    Post,
+};
+pub use self::status::{FirehoseStatus, Snapshot};
+pub use self::subscription::LabelsHandler;
 
+mod backoff;
 mod handler;
+mod status;
 mod stream;
 mod subscription;
 
-pub async fn listen<DATA: Send + Sync + 'static>(handler: Handler<DATA>) -> Result<()> {
-    subscription::RepoSubscription::new("bsky.network")
+/// Handles commits from `relay`, starting after `cursor`, until the connection ends or
+/// `shutdown` is cancelled.
+///
+/// Returns the sequence number of the last handled commit, to resume from next time
+pub async fn listen<DATA: Send + Sync + 'static>(
+    relay: &str,
+    handler: Handler<DATA>,
+    cursor: Option<i64>,
+    status: Arc<FirehoseStatus>,
+    shutdown: CancellationToken,
+) -> Result<Option<i64>> {
+    subscription::RepoSubscription::new(relay, cursor)
         .await?
-        .run(handler)
-        .await?;
+        .run(handler, status, shutdown)
+        .await
+}
 
-    Ok(())
+/// Handles labels from `labeler`, starting after `cursor`, until the connection ends or
+/// `shutdown` is cancelled.
+///
+/// Returns the sequence number of the last handled labels
+pub async fn listen_labels(
+    labeler: &str,
+    handler: impl LabelsHandler,
+    cursor: Option<i64>,
+    shutdown: CancellationToken,
+) -> Result<Option<i64>> {
+    subscription::LabelSubscription::new(labeler, cursor)
+        .await?
+        .run(handler, shutdown)
+        .await
 }
@@ -0,0 +1,231 @@
+use std::{
+    collections::BTreeSet,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+use chrono::{DateTime, Utc};
+
+use crate::models::heartbeats::Heartbeat;
+
+/// What the firehose subscription is up to, for `/ready` to report
+pub struct FirehoseStatus {
+    inner: Mutex<Inner>,
+}
+
+struct Inner {
+    connected: bool,
+    cursor: Option<i64>,
+    /// The seq of the latest commit that was received
+    received: Option<i64>,
+    /// Commits that were received but are still being handled
+    in_flight: BTreeSet<i64>,
+    last_commit: Option<Instant>,
+    created: Instant,
+}
+
+/// The status at a point in time
+#[derive(Debug, Clone, PartialEq)]
+pub struct Snapshot {
+    pub connected: bool,
+    /// The seq of the latest commit that was handled, or the one ingest resumed from
+    pub cursor: Option<i64>,
+    /// How long ago the last commit was handled, if any were
+    pub since_last_commit: Option<Duration>,
+    /// How long nothing has been handled for. Counts from startup until the first commit
+    pub idle: Duration,
+}
+
+impl FirehoseStatus {
+    /// Sets the cursor ingest is starting from, before any commits have been handled
+    pub fn resume_from(&self, cursor: Option<i64>) {
+        let mut inner = self.inner.lock().unwrap();
+        inner.cursor = cursor;
+        inner.received = cursor;
+    }
+
+    pub fn set_connected(&self, connected: bool) {
+        self.inner.lock().unwrap().connected = connected;
+    }
+
+    /// Called before a commit is handed off to be handled
+    pub fn commit_received(&self, seq: i64) {
+        let mut inner = self.inner.lock().unwrap();
+        inner.received = inner.received.max(Some(seq));
+        inner.in_flight.insert(seq);
+    }
+
+    /// Commits are handled concurrently, so they can finish slightly out of order. The cursor
+    /// only ever moves forward
+    pub fn commit_handled(&self, seq: i64) {
+        let mut inner = self.inner.lock().unwrap();
+        inner.cursor = inner.cursor.max(Some(seq));
+        inner.in_flight.remove(&seq);
+        inner.last_commit = Some(Instant::now());
+    }
+
+    /// A commit that could not be handled. It isn't retried, so it doesn't hold the cursor back
+    pub fn commit_failed(&self, seq: i64) {
+        self.inner.lock().unwrap().in_flight.remove(&seq);
+    }
+
+    /// The latest seq up to which every received commit is done being handled, so it's safe to
+    /// resume from once their writes are flushed. Unlike the cursor in the snapshot, it never
+    /// skips past commits that are still in flight
+    pub fn settled(&self) -> Option<i64> {
+        let inner = self.inner.lock().unwrap();
+        match inner.in_flight.first() {
+            Some(seq) => Some(seq - 1),
+            None => inner.received,
+        }
+    }
+
+    pub fn snapshot(&self, now: Instant) -> Snapshot {
+        let inner = self.inner.lock().unwrap();
+        let since_last_commit = inner
+            .last_commit
+            .map(|at| now.saturating_duration_since(at));
+
+        Snapshot {
+            connected: inner.connected,
+            cursor: inner.cursor,
+            since_last_commit,
+            idle: since_last_commit.unwrap_or(now.saturating_duration_since(inner.created)),
+        }
+    }
+}
+
+impl Snapshot {
+    /// The heartbeat for other processes to read, for ingest that started at `started_at`
+    pub fn to_heartbeat(
+        &self,
+        relay: &str,
+        started_at: DateTime<Utc>,
+        now: DateTime<Utc>,
+    ) -> Heartbeat {
+        let before =
+            |duration: Duration| now - chrono::Duration::from_std(duration).unwrap_or_default();
+
+        Heartbeat {
+            relay: relay.to_string(),
+            connected: self.connected,
+            cursor: self.cursor,
+            started_at,
+            last_commit_at: self.since_last_commit.map(before),
+            updated_at: now,
+        }
+    }
+
+    /// What the process that wrote `heartbeat` was up to, as far as we can tell at `now`. If it has
+    /// stopped, it looks idle since its last commit
+    pub fn from_heartbeat(heartbeat: &Heartbeat, now: DateTime<Utc>) -> Self {
+        let since = |at: DateTime<Utc>| (now - at).to_std().unwrap_or_default();
+        let since_last_commit = heartbeat.last_commit_at.map(since);
+
+        Self {
+            connected: heartbeat.connected,
+            cursor: heartbeat.cursor,
+            since_last_commit,
+            idle: since_last_commit.unwrap_or(since(heartbeat.started_at)),
+        }
+    }
+}
+
+impl Default for FirehoseStatus {
+    fn default() -> Self {
+        Self {
+            inner: Mutex::new(Inner {
+                connected: false,
+                cursor: None,
+                received: None,
+                in_flight: BTreeSet::new(),
+                last_commit: None,
+                created: Instant::now(),
+            }),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_snapshot() {
+        let status = FirehoseStatus::default();
+        status.resume_from(Some(5));
+        let later = Instant::now() + Duration::from_secs(60);
+
+        let snapshot = status.snapshot(later);
+        assert!(!snapshot.connected);
+        assert_eq!(Some(5), snapshot.cursor);
+        assert_eq!(None, snapshot.since_last_commit);
+        assert!(snapshot.idle >= Duration::from_secs(60));
+
+        status.set_connected(true);
+        status.commit_handled(8);
+        status.commit_handled(7);
+
+        let snapshot = status.snapshot(Instant::now() + Duration::from_secs(10));
+        assert!(snapshot.connected);
+        assert_eq!(Some(8), snapshot.cursor);
+        assert!(snapshot.since_last_commit.unwrap() >= Duration::from_secs(10));
+        assert!(snapshot.idle < Duration::from_secs(60));
+    }
+
+    #[test]
+    fn test_settled() {
+        let status = FirehoseStatus::default();
+        status.resume_from(Some(5));
+        assert_eq!(Some(5), status.settled());
+
+        status.commit_received(7);
+        status.commit_received(8);
+        status.commit_received(9);
+        status.commit_handled(8);
+        // 7 is still being handled
+        assert_eq!(Some(6), status.settled());
+
+        status.commit_failed(7);
+        assert_eq!(Some(8), status.settled());
+
+        status.commit_handled(9);
+        assert_eq!(Some(9), status.settled());
+    }
+
+    #[test]
+    fn test_heartbeat() {
+        let now = Utc::now();
+        let started_at = now - chrono::Duration::minutes(10);
+        let snapshot = Snapshot {
+            connected: true,
+            cursor: Some(8),
+            since_last_commit: Some(Duration::from_secs(30)),
+            idle: Duration::from_secs(30),
+        };
+
+        let heartbeat = snapshot.to_heartbeat("bsky.network", started_at, now);
+        assert_eq!(
+            Some(now - chrono::Duration::seconds(30)),
+            heartbeat.last_commit_at
+        );
+        assert_eq!(now, heartbeat.updated_at);
+
+        // the process writing it stopped a minute ago
+        let later = now + chrono::Duration::minutes(1);
+        let read = Snapshot::from_heartbeat(&heartbeat, later);
+        assert!(read.connected);
+        assert_eq!(Some(8), read.cursor);
+        assert_eq!(Some(Duration::from_secs(90)), read.since_last_commit);
+        assert_eq!(Duration::from_secs(90), read.idle);
+
+        // nothing was handled since starting
+        let heartbeat = Heartbeat {
+            last_commit_at: None,
+            ..heartbeat
+        };
+        let read = Snapshot::from_heartbeat(&heartbeat, later);
+        assert_eq!(None, read.since_last_commit);
+        assert_eq!(Duration::from_secs(11 * 60), read.idle);
+    }
+}
@@ -98,7 +98,7 @@ pub mod frames {
         use super::*;
 
         fn serialized_data(s: &str) -> Vec<u8> {
-            assert!(s.len() % 2 == 0);
+            assert!(s.len().is_multiple_of(2));
             let b2u = |b: u8| match b {
                 b'0'..=b'9' => b - b'0',
                 b'a'..=b'f' => b - b'a' + 10,
@@ -1,55 +1,462 @@
 use anyhow::Result;
-use atrium_api::com::atproto::sync::subscribe_repos::{Commit, NSID};
+use atrium_api::com::atproto::{
+    label::subscribe_labels::{self, Labels},
+    sync::subscribe_repos::{self, Commit},
+};
+use chrono::Utc;
 use futures::StreamExt;
 use std::future::Future;
 use std::sync::Arc;
 use tokio::net::TcpStream;
 use tokio_tungstenite::tungstenite::Message;
 use tokio_tungstenite::{connect_async, MaybeTlsStream, WebSocketStream};
+use tokio_util::{sync::CancellationToken, task::TaskTracker};
+use tracing::Instrument;
 
-use super::stream::frames::Frame;
+use super::{status::FirehoseStatus, stream::frames::Frame};
+use crate::metrics;
+
+type Stream = WebSocketStream<MaybeTlsStream<TcpStream>>;
 
 pub trait CommitHandler {
     fn handle_commit(&self, commit: &Commit) -> impl Future<Output = Result<()>> + Send;
 }
 
+pub trait LabelsHandler {
+    fn handle_labels(&self, labels: &Labels) -> impl Future<Output = Result<()>> + Send;
+}
+
 pub struct RepoSubscription {
-    stream: WebSocketStream<MaybeTlsStream<TcpStream>>,
+    stream: Stream,
 }
 
 impl RepoSubscription {
-    pub async fn new(bgs: &str) -> Result<Self> {
-        let (stream, _) = connect_async(format!("wss://{bgs}/xrpc/{NSID}")).await?;
+    /// Connects to `bgs`. If `cursor` is set, the stream starts right after that event
+    pub async fn new(bgs: &str, cursor: Option<i64>) -> Result<Self> {
+        let mut url = endpoint(bgs, subscribe_repos::NSID);
+        if let Some(cursor) = cursor {
+            url = format!("{url}?cursor={cursor}");
+        }
+        let (stream, _) = connect_async(url).await?;
         Ok(RepoSubscription { stream })
     }
 
-    pub async fn run(&mut self, handler: impl CommitHandler + Send + Sync + 'static) -> Result<()> {
+    /// Handles commits until the stream ends or `shutdown` is cancelled. Commits that are still
+    /// being handled are waited for before returning.
+    ///
+    /// Returns the sequence number of the last commit that was handled
+    pub async fn run(
+        &mut self,
+        handler: impl CommitHandler + Send + Sync + 'static,
+        status: Arc<FirehoseStatus>,
+        shutdown: CancellationToken,
+    ) -> Result<Option<i64>> {
         let handler = Arc::new(handler);
+        let tasks = TaskTracker::new();
+        let mut last_seq = None;
+        status.set_connected(true);
+
+        loop {
+            let result = tokio::select! {
+                result = next(&mut self.stream) => result,
+                _ = shutdown.cancelled() => break,
+            };
+            let Some(result) = result else {
+                break;
+            };
+
+            metrics::frame_received(match &result {
+                Ok(Frame::Message(t, _)) => t.as_deref().unwrap_or("unknown"),
+                Ok(Frame::Error(_)) => "error",
+                Err(_) => "invalid",
+            });
 
-        while let Some(result) = self.next().await {
             if let Ok(Frame::Message(Some(t), message)) = result {
                 if t.as_str() == "#commit" {
+                    let Ok(commit) =
+                        serde_ipld_dagcbor::from_reader::<Commit, _>(message.body.as_slice())
+                    else {
+                        continue;
+                    };
+                    last_seq = Some(commit.seq);
+                    let lag = Utc::now().signed_duration_since(commit.time.as_ref());
+                    metrics::commit_received(lag.to_std().unwrap_or_default());
+
+                    let span = tracing::info_span!(
+                        "commit",
+                        repo = commit.repo.as_str(),
+                        seq = commit.seq
+                    );
+                    status.commit_received(commit.seq);
                     let handler = handler.clone();
-                    tokio::spawn(async move {
-                        let Ok(commit) = serde_ipld_dagcbor::from_reader(message.body.as_slice())
-                        else {
-                            return;
-                        };
-                        if let Err(err) = handler.handle_commit(&commit).await {
-                            eprintln!("FAILED: {err:?}");
+                    let status = status.clone();
+                    tasks.spawn(
+                        async move {
+                            match handler.handle_commit(&commit).await {
+                                Ok(()) => {
+                                    metrics::commit_processed();
+                                    status.commit_handled(commit.seq);
+                                }
+                                Err(err) => {
+                                    metrics::commit_failed();
+                                    status.commit_failed(commit.seq);
+                                    tracing::error!("could not handle commit: {err:?}");
+                                }
+                            }
                         }
-                    });
+                        .instrument(span),
+                    );
+                }
+            }
+        }
+
+        status.set_connected(false);
+        tasks.close();
+        tasks.wait().await;
+
+        Ok(last_seq)
+    }
+}
+
+pub struct LabelSubscription {
+    stream: Stream,
+}
+
+impl LabelSubscription {
+    /// Connects to `labeler`. If `cursor` is set, the stream starts right after those labels
+    pub async fn new(labeler: &str, cursor: Option<i64>) -> Result<Self> {
+        let mut url = endpoint(labeler, subscribe_labels::NSID);
+        if let Some(cursor) = cursor {
+            url = format!("{url}?cursor={cursor}");
+        }
+        let (stream, _) = connect_async(url).await?;
+        Ok(LabelSubscription { stream })
+    }
+
+    /// Handles labels until the stream ends or `shutdown` is cancelled. Labels that are being
+    /// handled are finished first.
+    ///
+    /// Returns the sequence number of the last labels that were handled
+    pub async fn run(
+        &mut self,
+        handler: impl LabelsHandler,
+        shutdown: CancellationToken,
+    ) -> Result<Option<i64>> {
+        let mut last_seq = None;
+        loop {
+            let result = tokio::select! {
+                result = next(&mut self.stream) => result,
+                _ = shutdown.cancelled() => break,
+            };
+            let Some(result) = result else {
+                break;
+            };
+            if let Ok(Frame::Message(Some(t), message)) = result {
+                if t.as_str() == "#labels" {
+                    let Ok(labels) =
+                        serde_ipld_dagcbor::from_reader::<Labels, _>(message.body.as_slice())
+                    else {
+                        continue;
+                    };
+                    let span = tracing::info_span!("labels", seq = labels.seq);
+                    // labels are handled in order, since a later label can negate an earlier one
+                    if let Err(err) = handler.handle_labels(&labels).instrument(span).await {
+                        tracing::error!(seq = labels.seq, "could not handle labels: {err:?}");
+                    }
+                    last_seq = Some(labels.seq);
                 }
             }
         }
-        Ok(())
+        Ok(last_seq)
+    }
+}
+
+/// Builds the websocket url for `nsid` on `host`.
+///
+/// `host` is usually a bare hostname like `bsky.network`, which is connected to over `wss`,
+/// but it can also be a full url like `ws://localhost:8080` for local testing
+fn endpoint(host: &str, nsid: &str) -> String {
+    if host.contains("://") {
+        format!("{}/xrpc/{nsid}", host.trim_end_matches('/'))
+    } else {
+        format!("wss://{host}/xrpc/{nsid}")
+    }
+}
+
+async fn next(stream: &mut Stream) -> Option<Result<Frame, <Frame as TryFrom<&[u8]>>::Error>> {
+    if let Some(Ok(Message::Binary(data))) = stream.next().await {
+        Some(Frame::try_from(data.as_slice()))
+    } else {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use atrium_api::com::atproto::{
+        label::{defs::LabelData, subscribe_labels::LabelsData},
+        sync::subscribe_repos::CommitData,
+    };
+    use futures::SinkExt;
+    use ipld_core::ipld::Ipld;
+    use std::{collections::BTreeMap, sync::Mutex};
+    use tokio::net::TcpListener;
+    use tokio_tungstenite::tungstenite::handshake::server::{Request, Response};
+
+    #[test]
+    fn test_endpoint() {
+        assert_eq!(
+            "wss://bsky.network/xrpc/com.atproto.sync.subscribeRepos",
+            endpoint("bsky.network", subscribe_repos::NSID)
+        );
+        assert_eq!(
+            "ws://localhost:8080/xrpc/com.atproto.label.subscribeLabels",
+            endpoint("ws://localhost:8080/", subscribe_labels::NSID)
+        );
     }
 
-    async fn next(&mut self) -> Option<Result<Frame, <Frame as TryFrom<&[u8]>>::Error>> {
-        if let Some(Ok(Message::Binary(data))) = self.stream.next().await {
-            Some(Frame::try_from(data.as_slice()))
-        } else {
-            None
+    struct Collect(Arc<Mutex<Vec<String>>>);
+
+    impl LabelsHandler for Collect {
+        async fn handle_labels(&self, labels: &Labels) -> Result<()> {
+            let mut vals = self.0.lock().unwrap();
+            vals.extend(labels.labels.iter().map(|label| label.val.clone()));
+            Ok(())
         }
     }
+
+    fn labels_frame(seq: i64, val: &str) -> Vec<u8> {
+        let header = Ipld::Map(BTreeMap::from([
+            ("op".to_string(), Ipld::Integer(1)),
+            ("t".to_string(), Ipld::String("#labels".to_string())),
+        ]));
+        let body = Labels::from(LabelsData {
+            seq,
+            labels: vec![LabelData {
+                cid: None,
+                cts: "2024-11-21T10:00:00.000Z".parse().unwrap(),
+                exp: None,
+                neg: None,
+                sig: None,
+                src: "did:plc:labeler".parse().unwrap(),
+                uri: "did:plc:author".to_string(),
+                val: val.to_string(),
+                ver: Some(1),
+            }
+            .into()],
+        });
+
+        let mut frame = serde_ipld_dagcbor::to_vec(&header).unwrap();
+        frame.extend(serde_ipld_dagcbor::to_vec(&body).unwrap());
+        frame
+    }
+
+    #[tokio::test]
+    async fn test_label_subscription_against_local_labeler() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+
+        tokio::spawn(async move {
+            let (socket, _) = listener.accept().await.unwrap();
+            let mut ws = tokio_tungstenite::accept_async(socket).await.unwrap();
+            ws.send(Message::Binary(labels_frame(1, "spam")))
+                .await
+                .unwrap();
+            ws.send(Message::Binary(labels_frame(2, "porn")))
+                .await
+                .unwrap();
+            ws.close(None).await.unwrap();
+        });
+
+        let vals = Arc::new(Mutex::new(vec![]));
+        let last_seq = LabelSubscription::new(&format!("ws://{addr}"), None)
+            .await
+            .unwrap()
+            .run(Collect(vals.clone()), CancellationToken::new())
+            .await
+            .unwrap();
+
+        assert_eq!(vec!["spam", "porn"], *vals.lock().unwrap());
+        assert_eq!(Some(2), last_seq);
+    }
+
+    #[tokio::test]
+    async fn test_label_subscription_stops_on_shutdown() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            let (socket, _) = listener.accept().await.unwrap();
+            let mut ws = tokio_tungstenite::accept_async(socket).await.unwrap();
+            ws.send(Message::Binary(labels_frame(1, "spam")))
+                .await
+                .unwrap();
+            // wait for the client to go away
+            while ws.next().await.is_some() {}
+        });
+
+        let vals = Arc::new(Mutex::new(vec![]));
+        let shutdown = CancellationToken::new();
+        let mut subscription = LabelSubscription::new(&format!("ws://{addr}"), None)
+            .await
+            .unwrap();
+        let run = tokio::spawn({
+            let vals = vals.clone();
+            let shutdown = shutdown.clone();
+            async move { subscription.run(Collect(vals), shutdown).await }
+        });
+        while vals.lock().unwrap().is_empty() {
+            tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+        }
+        shutdown.cancel();
+
+        let last_seq = tokio::time::timeout(std::time::Duration::from_secs(5), run)
+            .await
+            .expect("the subscription should stop on shutdown")
+            .unwrap()
+            .unwrap();
+        assert_eq!(Some(1), last_seq);
+    }
+
+    /// Collects the seq of every commit, and cancels `shutdown` once it has seen `stop_after`
+    struct Seqs {
+        seqs: Arc<Mutex<Vec<i64>>>,
+        shutdown: CancellationToken,
+        stop_after: i64,
+    }
+
+    impl CommitHandler for Seqs {
+        async fn handle_commit(&self, commit: &Commit) -> Result<()> {
+            self.seqs.lock().unwrap().push(commit.seq);
+            if commit.seq == self.stop_after {
+                self.shutdown.cancel();
+            }
+            Ok(())
+        }
+    }
+
+    fn commit_frame(seq: i64) -> Vec<u8> {
+        let header = Ipld::Map(BTreeMap::from([
+            ("op".to_string(), Ipld::Integer(1)),
+            ("t".to_string(), Ipld::String("#commit".to_string())),
+        ]));
+        let cid = "bafyreib2rxk3rybk3aobmv5cjuql3bm2twh4jo5uxgf5kpqcsgz7soqzle"
+            .parse()
+            .unwrap();
+        let body = Commit::from(CommitData {
+            blobs: vec![],
+            blocks: vec![],
+            commit: atrium_api::types::CidLink(cid),
+            ops: vec![],
+            prev: None,
+            rebase: false,
+            repo: "did:plc:author".parse().unwrap(),
+            rev: "rev".to_string(),
+            seq,
+            since: None,
+            time: "2024-11-29T10:00:00.000Z".parse().unwrap(),
+            too_big: false,
+        });
+
+        let mut frame = serde_ipld_dagcbor::to_vec(&header).unwrap();
+        frame.extend(serde_ipld_dagcbor::to_vec(&body).unwrap());
+        frame
+    }
+
+    /// Accepts one connection, sends a commit for each of `seqs`, and then either closes the
+    /// connection or leaves it open. Returns the requested url
+    // the handshake callback's error type is tungstenite's
+    #[allow(clippy::result_large_err)]
+    async fn relay(seqs: Vec<i64>, close: bool) -> (String, tokio::task::JoinHandle<String>) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+
+        let handle = tokio::spawn(async move {
+            let (socket, _) = listener.accept().await.unwrap();
+            let mut uri = String::new();
+            let mut ws = tokio_tungstenite::accept_hdr_async(
+                socket,
+                |request: &Request, response: Response| {
+                    uri = request.uri().to_string();
+                    Ok(response)
+                },
+            )
+            .await
+            .unwrap();
+            for seq in seqs {
+                ws.send(Message::Binary(commit_frame(seq))).await.unwrap();
+            }
+            if close {
+                ws.close(None).await.unwrap();
+            } else {
+                // wait for the client to go away
+                while ws.next().await.is_some() {}
+            }
+            uri
+        });
+
+        (format!("ws://{addr}"), handle)
+    }
+
+    #[tokio::test]
+    async fn test_repo_subscription_resumes_from_cursor() {
+        let (url, relay) = relay(vec![6, 7], true).await;
+        let seqs = Arc::new(Mutex::new(vec![]));
+        let shutdown = CancellationToken::new();
+
+        let status = Arc::new(FirehoseStatus::default());
+
+        let last_seq = RepoSubscription::new(&url, Some(5))
+            .await
+            .unwrap()
+            .run(
+                Seqs {
+                    seqs: seqs.clone(),
+                    shutdown: shutdown.clone(),
+                    stop_after: -1,
+                },
+                status.clone(),
+                shutdown,
+            )
+            .await
+            .unwrap();
+
+        assert_eq!(Some(7), last_seq);
+        let snapshot = status.snapshot(std::time::Instant::now());
+        assert_eq!(Some(7), snapshot.cursor);
+        assert!(!snapshot.connected);
+        let mut seqs = seqs.lock().unwrap().clone();
+        seqs.sort();
+        assert_eq!(vec![6, 7], seqs);
+        assert_eq!(
+            "/xrpc/com.atproto.sync.subscribeRepos?cursor=5",
+            relay.await.unwrap()
+        );
+    }
+
+    #[tokio::test]
+    async fn test_repo_subscription_stops_on_shutdown() {
+        let (url, _relay) = relay(vec![1], false).await;
+        let seqs = Arc::new(Mutex::new(vec![]));
+        let shutdown = CancellationToken::new();
+
+        let mut subscription = RepoSubscription::new(&url, None).await.unwrap();
+        let run = subscription.run(
+            Seqs {
+                seqs: seqs.clone(),
+                shutdown: shutdown.clone(),
+                stop_after: 1,
+            },
+            Default::default(),
+            shutdown,
+        );
+        let last_seq = tokio::time::timeout(std::time::Duration::from_secs(5), run)
+            .await
+            .expect("subscription didn't stop")
+            .unwrap();
+
+        assert_eq!(Some(1), last_seq);
+        assert_eq!(vec![1], *seqs.lock().unwrap());
+    }
 }
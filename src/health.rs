@@ -0,0 +1,210 @@
+use std::{sync::Arc, time::Instant};
+
+use axum::{extract::State, http::StatusCode, routing::get, Json, Router};
+use chrono::Utc;
+use serde_json::{json, Value};
+
+use crate::{firehose::Snapshot, storage::Storage, AppState};
+
+/// Routes for whatever is keeping the service running.
+///
+/// `/health` only says the process is up. `/ready` also checks the db, and responds with 503 if
+/// no commit has been handled for longer than `FEEDGEN_STALL_THRESHOLD_SECS`. Processes that don't
+/// ingest report the heartbeat the ingesting process writes to the db, but can still serve
+/// without it, so a missing or stale heartbeat is only a warning
+pub fn router() -> Router<Arc<AppState>> {
+    Router::new()
+        .route("/health", get(health))
+        .route("/ready", get(ready))
+}
+
+async fn health() -> Json<Value> {
+    Json(json!({ "status": "ok" }))
+}
+
+async fn ready(State(state): State<Arc<AppState>>) -> (StatusCode, Json<Value>) {
+    let mut db = match state.db.ping().await {
+        Ok(()) => true,
+        Err(err) => {
+            tracing::warn!("db is unreachable: {err:?}");
+            false
+        }
+    };
+
+    let (firehose, since_heartbeat) = match &state.firehose {
+        Some(firehose) => (Some(firehose.snapshot(Instant::now())), None),
+        None => match state.db.heartbeat(&state.config.relay).await {
+            Ok(Some(heartbeat)) => {
+                let now = Utc::now();
+                let since_heartbeat = (now - heartbeat.updated_at).to_std().unwrap_or_default();
+                (
+                    Some(Snapshot::from_heartbeat(&heartbeat, now)),
+                    Some(since_heartbeat),
+                )
+            }
+            Ok(None) => (None, None),
+            Err(err) => {
+                tracing::warn!("could not get heartbeat: {err:?}");
+                db = false;
+                (None, None)
+            }
+        },
+    };
+    let stalled = firehose
+        .as_ref()
+        .is_none_or(|firehose| firehose.idle > state.config.stall_threshold);
+
+    let warning = match (&state.firehose, &firehose) {
+        (None, None) => Some("no heartbeat from ingest"),
+        (None, Some(_)) if stalled => Some("ingest has stalled"),
+        _ => None,
+    };
+
+    let status = if db && (state.firehose.is_none() || !stalled) {
+        StatusCode::OK
+    } else {
+        StatusCode::SERVICE_UNAVAILABLE
+    };
+
+    let body = json!({
+        "db": db,
+        "ingesting": state.firehose.is_some(),
+        "firehose_connected": firehose.as_ref().map(|firehose| firehose.connected),
+        "stalled": stalled,
+        "secs_since_last_commit": firehose
+            .as_ref()
+            .and_then(|firehose| firehose.since_last_commit)
+            .map(|since| since.as_secs_f64()),
+        "secs_since_heartbeat": since_heartbeat.map(|since| since.as_secs_f64()),
+        "cursor": firehose.and_then(|firehose| firehose.cursor),
+        "warning": warning,
+    });
+
+    (status, Json(body))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::time::Duration;
+
+    use crate::{
+        algos::Registry,
+        auth::ServiceAuth,
+        firehose::FirehoseStatus,
+        metrics,
+        models::heartbeats::Heartbeat,
+        server,
+        storage::{Database, SqliteStorage},
+    };
+
+    async fn state(stall_threshold: Duration, ingesting: bool) -> Arc<AppState> {
+        Arc::new(AppState {
+            config: server::Config {
+                stall_threshold,
+                ..server::test_config()
+            },
+            db: Database::Sqlite(SqliteStorage::memory().await),
+            algos: Registry::default(),
+            metrics: metrics::test_handle(),
+            firehose: ingesting.then(|| Arc::new(FirehoseStatus::default())),
+            auth: ServiceAuth::default(),
+        })
+    }
+
+    #[tokio::test]
+    async fn test_ready() {
+        let state = state(Duration::from_secs(60), true).await;
+        let firehose = state.firehose.as_ref().unwrap();
+        firehose.resume_from(Some(4));
+        firehose.set_connected(true);
+        firehose.commit_handled(5);
+
+        let (status, Json(body)) = ready(State(state)).await;
+
+        assert_eq!(StatusCode::OK, status);
+        assert_eq!(json!(true), body["db"]);
+        assert_eq!(json!(true), body["firehose_connected"]);
+        assert_eq!(json!(5), body["cursor"]);
+    }
+
+    #[tokio::test]
+    async fn test_not_ready_when_stalled() {
+        let state = state(Duration::ZERO, true).await;
+        tokio::time::sleep(Duration::from_millis(5)).await;
+
+        let (status, Json(body)) = ready(State(state)).await;
+
+        assert_eq!(StatusCode::SERVICE_UNAVAILABLE, status);
+        assert_eq!(json!(true), body["stalled"]);
+        assert_eq!(Value::Null, body["secs_since_last_commit"]);
+        assert_eq!(Value::Null, body["cursor"]);
+    }
+
+    #[tokio::test]
+    async fn test_ready_from_heartbeat() {
+        let state = state(Duration::from_secs(60), false).await;
+        let now = Utc::now();
+        state
+            .db
+            .set_heartbeat(&Heartbeat {
+                relay: "bsky.network".to_string(),
+                connected: true,
+                cursor: Some(5),
+                started_at: now - chrono::Duration::minutes(10),
+                last_commit_at: Some(now - chrono::Duration::seconds(2)),
+                updated_at: now - chrono::Duration::seconds(1),
+            })
+            .await
+            .unwrap();
+
+        let (status, Json(body)) = ready(State(state)).await;
+
+        assert_eq!(StatusCode::OK, status);
+        assert_eq!(json!(false), body["ingesting"]);
+        assert_eq!(json!(true), body["firehose_connected"]);
+        assert_eq!(json!(5), body["cursor"]);
+        assert!(body["secs_since_heartbeat"].as_f64().unwrap() >= 1.0);
+        assert_eq!(Value::Null, body["warning"]);
+    }
+
+    #[tokio::test]
+    async fn test_ready_when_heartbeat_stopped() {
+        let state = state(Duration::from_secs(60), false).await;
+        let now = Utc::now();
+        state
+            .db
+            .set_heartbeat(&Heartbeat {
+                relay: "bsky.network".to_string(),
+                connected: true,
+                cursor: Some(5),
+                started_at: now - chrono::Duration::minutes(10),
+                last_commit_at: Some(now - chrono::Duration::minutes(2)),
+                updated_at: now - chrono::Duration::minutes(2),
+            })
+            .await
+            .unwrap();
+
+        let (status, Json(body)) = ready(State(state)).await;
+
+        assert_eq!(StatusCode::OK, status);
+        assert_eq!(json!(true), body["stalled"]);
+        assert!(body["secs_since_heartbeat"].as_f64().unwrap() >= 120.0);
+        assert_eq!(json!("ingest has stalled"), body["warning"]);
+    }
+
+    #[tokio::test]
+    async fn test_ready_without_heartbeat() {
+        let state = state(Duration::from_secs(60), false).await;
+
+        let (status, Json(body)) = ready(State(state)).await;
+
+        assert_eq!(StatusCode::OK, status);
+        assert_eq!(json!(true), body["db"]);
+        assert_eq!(json!(false), body["ingesting"]);
+        assert_eq!(json!(true), body["stalled"]);
+        assert_eq!(Value::Null, body["firehose_connected"]);
+        assert_eq!(json!("no heartbeat from ingest"), body["warning"]);
+    }
+}
@@ -1,52 +1,604 @@
-use std::sync::Arc;
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
 
 use anyhow::{Context, Result};
-use sqlx::{Pool, Sqlite};
+use atrium_api::{
+    app::bsky::{
+        embed::record_with_media::MainMediaRefs, feed::post::RecordEmbedRefs,
+        richtext::facet::MainFeaturesItem,
+    },
+    types::Union,
+};
+use chrono::Utc;
+use tokio_util::sync::CancellationToken;
 
 use crate::{
-    firehose::{self, Handler, OnPostCreateParams, OnPostDeleteParams},
-    link_finder::get_music_links,
-    models::{links, posts},
+    batch::{BatchConfig, BatchWriter, PendingLink, PendingPost},
+    firehose::{
+        self, Backoff, FirehoseStatus, Handler, OnEngagementDeleteParams, OnEngagementParams,
+        OnPostCreateParams, OnPostDeleteParams, Post,
+    },
+    link_finder::{get_music_links, get_urls},
+    spam::{self, SpamConfig},
+    storage::{Database, Storage},
 };
 
-pub async fn start_ingest(pool: Pool<Sqlite>) -> Result<()> {
-    firehose::listen(Handler::<AppData> {
-        on_post_create: Arc::new(move |params, data| Box::pin(on_post_create(params, data))),
-        on_post_delete: Arc::new(move |params, data| Box::pin(on_post_delete(params, data))),
-        data: Arc::new(AppData { pool }),
-    })
-    .await
-    .context("failed while listening to firehose")?;
+/// How often ingest writes its heartbeat
+const HEARTBEAT_INTERVAL: Duration = Duration::from_secs(10);
+/// How often ingest saves its cursor while it runs
+const CHECKPOINT_INTERVAL: Duration = Duration::from_secs(30);
+/// How often ingest reloads the uris it knows are stored, to forget the ones purged since
+const RELOAD_INTERVAL: Duration = Duration::from_secs(60 * 60);
+
+/// Indexes posts from `relay`'s firehose until `shutdown` is cancelled, resuming from where the
+/// last run left off. Connections that end or fail are retried with a backoff, from the saved
+/// cursor. While it runs, it writes a heartbeat, so processes that only serve can tell whether
+/// it's alive.
+///
+/// The cursor is saved every [`CHECKPOINT_INTERVAL`], once the writes of the commits before it
+/// are flushed, so a crash only replays the commits since. When it stops, it waits for in-flight
+/// commits and buffered writes before saving the cursor one last time
+pub async fn start_ingest(
+    db: Database,
+    relay: String,
+    spam: SpamConfig,
+    batch: BatchConfig,
+    status: Arc<FirehoseStatus>,
+    shutdown: CancellationToken,
+) -> Result<()> {
+    status.resume_from(db.cursor(&relay).await?);
+    let (writer, flushed) = BatchWriter::spawn(db.clone(), batch, spam);
+
+    let stopped = shutdown.child_token();
+    let heartbeat = tokio::spawn(beat(
+        db.clone(),
+        relay.clone(),
+        status.clone(),
+        stopped.clone(),
+    ));
+    let checkpoints = tokio::spawn(checkpoint(
+        db.clone(),
+        relay.clone(),
+        writer.clone(),
+        status.clone(),
+        stopped.clone(),
+    ));
+
+    let data = Arc::new(AppData::load(db.clone(), writer).await?);
+    let reloads = tokio::spawn(reload(data.clone(), stopped.clone()));
+    let mut backoff = Backoff::default();
+    loop {
+        let handler = Handler::<AppData> {
+            on_post_create: Arc::new(move |params, data| Box::pin(on_post_create(params, data))),
+            on_post_delete: Arc::new(move |params, data| Box::pin(on_post_delete(params, data))),
+            on_engagement: Arc::new(move |params, data| Box::pin(on_engagement(params, data))),
+            on_engagement_delete: Arc::new(move |params, data| {
+                Box::pin(on_engagement_delete(params, data))
+            }),
+            data: data.clone(),
+        };
+        let cursor = status.settled();
+
+        match firehose::listen(&relay, handler, cursor, status.clone(), shutdown.clone())
+            .await
+            .context("failed while listening to firehose")
+        {
+            Ok(Some(_)) => backoff.reset(),
+            Ok(None) => {}
+            Err(err) => tracing::warn!(relay, "{err:?}"),
+        }
+        if shutdown.is_cancelled() {
+            break;
+        }
+
+        // every commit this connection handled is settled now, so the next one resumes from
+        // the cursor this saves
+        if let Err(err) = save_cursor(&db, &relay, &data.writer, &status).await {
+            tracing::warn!("could not save cursor: {err:?}");
+        }
+
+        let delay = backoff.next_delay();
+        tracing::info!(relay, "reconnecting to firehose in {delay:?}");
+        tokio::select! {
+            _ = tokio::time::sleep(delay) => {}
+            _ = shutdown.cancelled() => break,
+        }
+    }
+    stopped.cancel();
+    heartbeat.await.context("heartbeat panicked")?;
+    checkpoints.await.context("checkpoints panicked")?;
+    reloads.await.context("reloads panicked")?;
+
+    // once every in-flight commit is done with the writer, it flushes whatever is left
+    drop(data);
+    flushed.await.context("batch writer panicked")?;
+
+    if let Some(seq) = status.settled() {
+        db.set_cursor(&relay, seq).await?;
+    }
 
     Ok(())
 }
 
+/// Saves the cursor for `relay` every [`CHECKPOINT_INTERVAL`] until `stopped` is cancelled
+async fn checkpoint(
+    db: Database,
+    relay: String,
+    writer: BatchWriter,
+    status: Arc<FirehoseStatus>,
+    stopped: CancellationToken,
+) {
+    let mut interval = tokio::time::interval(CHECKPOINT_INTERVAL);
+    interval.tick().await;
+
+    loop {
+        tokio::select! {
+            _ = interval.tick() => {}
+            _ = stopped.cancelled() => break,
+        }
+
+        if let Err(err) = save_cursor(&db, &relay, &writer, &status).await {
+            tracing::warn!("could not save cursor: {err:?}");
+        }
+    }
+}
+
+/// Reloads the uris `data` knows are stored every [`RELOAD_INTERVAL`] until `stopped` is
+/// cancelled
+async fn reload(data: Arc<AppData>, stopped: CancellationToken) {
+    let mut interval = tokio::time::interval(RELOAD_INTERVAL);
+    interval.tick().await;
+
+    loop {
+        tokio::select! {
+            _ = interval.tick() => {}
+            _ = stopped.cancelled() => break,
+        }
+
+        if let Err(err) = data.reload().await {
+            tracing::warn!("could not reload stored uris: {err:?}");
+        }
+    }
+}
+
+/// Saves the seq that every handled commit's writes are flushed up to as the cursor for `relay`
+async fn save_cursor(
+    db: &Database,
+    relay: &str,
+    writer: &BatchWriter,
+    status: &FirehoseStatus,
+) -> Result<()> {
+    // commits are only settled once they're done sending their writes, so flushing after
+    // reading it covers every one of them
+    let Some(seq) = status.settled() else {
+        return Ok(());
+    };
+    writer.flush().await;
+    db.set_cursor(relay, seq).await
+}
+
+/// Writes `status` as the heartbeat for `relay` every [`HEARTBEAT_INTERVAL`], and once more when
+/// `stopped` is cancelled
+async fn beat(
+    db: Database,
+    relay: String,
+    status: Arc<FirehoseStatus>,
+    stopped: CancellationToken,
+) {
+    let started_at = Utc::now();
+    let mut interval = tokio::time::interval(HEARTBEAT_INTERVAL);
+
+    loop {
+        let last = tokio::select! {
+            _ = interval.tick() => false,
+            _ = stopped.cancelled() => true,
+        };
+
+        let now = Utc::now();
+        let mut heartbeat = status
+            .snapshot(std::time::Instant::now())
+            .to_heartbeat(&relay, started_at, now);
+        heartbeat.connected &= !last;
+
+        if let Err(err) = db.set_heartbeat(&heartbeat).await {
+            tracing::warn!("could not write heartbeat: {err:?}");
+        }
+        if last {
+            break;
+        }
+    }
+}
+
 struct AppData {
-    pool: Pool<Sqlite>,
+    /// Only used for reads, writes go through `writer`
+    db: Database,
+    writer: BatchWriter,
+    /// The posts that are stored, or queued to be
+    posts: KnownUris,
+    /// The likes and reposts that are counted in a stored post's engagement, or queued to be
+    engagements: KnownUris,
+}
+
+impl AppData {
+    async fn load(db: Database, writer: BatchWriter) -> Result<Self> {
+        let data = Self {
+            db,
+            writer,
+            posts: KnownUris::default(),
+            engagements: KnownUris::default(),
+        };
+        data.reload().await?;
+        Ok(data)
+    }
+
+    /// Replaces the known uris with the stored ones, which drops the posts and engagement that
+    /// were purged since
+    async fn reload(&self) -> Result<()> {
+        // uris queued before this are written by the flush, so the ones read after it only miss
+        // those queued since, which are kept
+        let since = Instant::now();
+        self.writer.flush().await;
+        let posts = self.db.post_uris().await?;
+        let engagements = self.db.engagement_uris().await?;
+
+        self.posts.reload(posts, since);
+        self.engagements.reload(engagements, since);
+        Ok(())
+    }
+}
+
+/// A set of uris that ingest knows are stored, so it only queues the deletes and engagement that
+/// can change something. The firehose carries every like, repost and delete on the network, and
+/// nearly none of them are for posts we have
+#[derive(Default)]
+struct KnownUris {
+    /// Each uri, with when it was added
+    uris: Mutex<HashMap<String, Instant>>,
+}
+
+impl KnownUris {
+    fn insert(&self, uri: String) {
+        self.uris.lock().unwrap().insert(uri, Instant::now());
+    }
+
+    /// Removes `uri`, and returns whether it was known
+    fn remove(&self, uri: &str) -> bool {
+        self.uris.lock().unwrap().remove(uri).is_some()
+    }
+
+    fn contains(&self, uri: &str) -> bool {
+        self.uris.lock().unwrap().contains_key(uri)
+    }
+
+    /// Replaces the uris with `stored`, keeping the ones added since `since`, which `stored` may
+    /// have been read before they were written
+    fn reload(&self, stored: Vec<String>, since: Instant) {
+        let mut uris = self.uris.lock().unwrap();
+        uris.retain(|_, added| *added >= since);
+        for uri in stored {
+            uris.entry(uri).or_insert(since);
+        }
+    }
 }
 
 async fn on_post_create(params: OnPostCreateParams<'_>, data: Arc<AppData>) {
     let links = get_music_links(&params.post.text);
 
     if !links.is_empty() {
-        // store post in posts table
-        let cid = params.cid.0.to_string();
-        if let Err(err) = posts::Post::create(&data.pool, &params.uri, cid).await {
-            println!("{err}");
+        let urls = post_urls(params.post);
+        match is_blocked(&params, &urls, &data).await {
+            Ok(false) => {}
+            Ok(true) => return,
+            Err(err) => {
+                tracing::error!(uri = params.uri, "could not check blocklist: {err:?}");
+                return;
+            }
         }
 
-        // store links in links table
-        for link in &links {
-            if let Err(err) = links::Link::create(&data.pool, link).await {
-                println!("{err}");
+        // store the post and its links. the writer checks it for spam before it's written
+        let fingerprint = spam::fingerprint(&params.post.text);
+        let reply = params.post.reply.as_ref();
+        let post = PendingPost {
+            uri: params.uri.clone(),
+            cid: params.cid.0.to_string(),
+            author: params.author.to_string(),
+            reply_root: reply.map(|reply| reply.root.uri.clone()),
+            reply_parent: reply.map(|reply| reply.parent.uri.clone()),
+            quote: quoted_uri(params.post).map(String::from),
+            fingerprint: Some(fingerprint),
+            langs: params
+                .post
+                .langs
+                .iter()
+                .flatten()
+                .map(|lang| lang.as_ref().as_str().to_lowercase())
+                .collect(),
+            links: links
+                .into_iter()
+                .map(|link| PendingLink {
+                    url: link.url.to_string(),
+                    kind: link.kind,
+                    site: link.site,
+                })
+                .collect(),
+            urls,
+        };
+        data.writer.create(post).await;
+        data.posts.insert(params.uri);
+    }
+}
+
+/// Checks whether the post's author or any of its urls are on the blocklist
+async fn is_blocked(
+    params: &OnPostCreateParams<'_>,
+    urls: &[String],
+    data: &AppData,
+) -> Result<bool> {
+    if data.db.is_author_blocked(params.author).await? {
+        return Ok(true);
+    }
+
+    let urls = urls.iter().map(String::as_str).collect::<Vec<_>>();
+    data.db.any_link_blocked(&urls).await
+}
+
+/// Every url in the post, music or not: the ones written out in its text, the ones behind its
+/// link facets, which the text often shortens, and the link card it embeds
+fn post_urls(post: &Post) -> Vec<String> {
+    let facets = post
+        .facets
+        .iter()
+        .flatten()
+        .flat_map(|facet| &facet.features)
+        .filter_map(|feature| match feature {
+            Union::Refs(MainFeaturesItem::Link(link)) => Some(link.uri.as_str()),
+            _ => None,
+        });
+    let external = match post.embed.as_ref() {
+        Some(Union::Refs(RecordEmbedRefs::AppBskyEmbedExternalMain(embed))) => {
+            Some(embed.external.uri.as_str())
+        }
+        Some(Union::Refs(RecordEmbedRefs::AppBskyEmbedRecordWithMediaMain(embed))) => {
+            match &embed.media {
+                Union::Refs(MainMediaRefs::AppBskyEmbedExternalMain(media)) => {
+                    Some(media.external.uri.as_str())
+                }
+                _ => None,
             }
         }
+        _ => None,
+    };
+
+    let mut urls: Vec<String> = vec![];
+    for url in get_urls(&post.text)
+        .into_iter()
+        .chain(facets)
+        .chain(external)
+    {
+        if !urls.iter().any(|seen| seen == url) {
+            urls.push(url.to_string());
+        }
+    }
+    urls
+}
+
+/// Returns the uri of the record this post quotes, if any
+fn quoted_uri(post: &Post) -> Option<&str> {
+    match post.embed.as_ref()? {
+        Union::Refs(RecordEmbedRefs::AppBskyEmbedRecordMain(embed)) => Some(&embed.record.uri),
+        Union::Refs(RecordEmbedRefs::AppBskyEmbedRecordWithMediaMain(embed)) => {
+            Some(&embed.record.record.uri)
+        }
+        _ => None,
     }
 }
 
 async fn on_post_delete(params: OnPostDeleteParams<'_>, data: Arc<AppData>) {
-    // delete post by uri from the db
-    if let Err(err) = posts::Post::delete(&data.pool, &params.uri).await {
-        println!("{err}");
+    // delete post by uri from the db, if we have it
+    if data.posts.remove(&params.uri) {
+        data.writer.delete(params.uri).await;
+    }
+}
+
+async fn on_engagement(params: OnEngagementParams<'_>, data: Arc<AppData>) {
+    // likes and reposts of anything but a post we have are skipped
+    if !params.subject.contains("/app.bsky.feed.post/") || !data.posts.contains(params.subject) {
+        return;
+    }
+
+    data.engagements.insert(params.uri.clone());
+    data.writer
+        .engage(params.uri, params.subject.to_string())
+        .await;
+}
+
+async fn on_engagement_delete(params: OnEngagementDeleteParams<'_>, data: Arc<AppData>) {
+    // only engagement with posts we have was recorded, so the rest is skipped too
+    if data.engagements.remove(&params.uri) {
+        data.writer.disengage(params.uri).await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use atrium_api::{
+        com::atproto::sync::subscribe_repos::{Commit, CommitData},
+        types::CidLink,
+    };
+    use serde_json::json;
+    use tokio::task::JoinHandle;
+
+    use crate::{models::posts::PostFilter, storage::SqliteStorage};
+
+    fn commit() -> Commit {
+        Commit::from(CommitData {
+            blobs: vec![],
+            blocks: vec![],
+            commit: CidLink(cid()),
+            ops: vec![],
+            prev: None,
+            rebase: false,
+            repo: "did:plc:author".parse().unwrap(),
+            rev: "rev".to_string(),
+            seq: 1,
+            since: None,
+            time: "2024-12-04T10:00:00.000Z".parse().unwrap(),
+            too_big: false,
+        })
+    }
+
+    fn cid() -> ipld_core::cid::Cid {
+        "bafyreib2rxk3rybk3aobmv5cjuql3bm2twh4jo5uxgf5kpqcsgz7soqzle"
+            .parse()
+            .unwrap()
+    }
+
+    /// A post sharing an album, that also links to `url` behind a shortened link in its text
+    fn post(url: &str) -> Post {
+        serde_json::from_value(json!({
+            "$type": "app.bsky.feed.post",
+            "createdAt": "2024-12-04T10:00:00.000Z",
+            "text": "new album https://open.spotify.com/album/abc tickets at scam.exa...",
+            "facets": [{
+                "index": { "byteStart": 56, "byteEnd": 67 },
+                "features": [{ "$type": "app.bsky.richtext.facet#link", "uri": url }]
+            }]
+        }))
+        .unwrap()
+    }
+
+    /// Loads the data the handlers share, and returns it with the writer's task
+    async fn app_data(db: &Database) -> (Arc<AppData>, JoinHandle<()>) {
+        let (writer, flushed) =
+            BatchWriter::spawn(db.clone(), BatchConfig::default(), SpamConfig::default());
+        let data = AppData::load(db.clone(), writer).await.unwrap();
+        (Arc::new(data), flushed)
+    }
+
+    /// Runs `posts` through `on_post_create`, and returns the uris of the stored posts
+    async fn ingest(db: &Database, posts: &[(&str, Post)]) -> Vec<String> {
+        let (data, flushed) = app_data(db).await;
+        let commit = commit();
+        let cid = CidLink(cid());
+        for (uri, post) in posts {
+            let params = OnPostCreateParams {
+                post,
+                commit: &commit,
+                uri: uri.to_string(),
+                post_id: "post",
+                author: "did:plc:author",
+                cid: &cid,
+            };
+            on_post_create(params, data.clone()).await;
+        }
+        drop(data);
+        flushed.await.unwrap();
+
+        let posts = db
+            .get_posts(100, None, &PostFilter::default())
+            .await
+            .unwrap();
+        posts.into_iter().map(|post| post.uri).collect()
+    }
+
+    #[test]
+    fn test_post_urls() {
+        let mut post = post("https://scam.example/win");
+        post.text.push_str(" https://open.spotify.com/album/abc");
+
+        assert_eq!(
+            vec![
+                "https://open.spotify.com/album/abc",
+                "https://scam.example/win"
+            ],
+            post_urls(&post)
+        );
+    }
+
+    #[tokio::test]
+    async fn test_posts_linking_to_a_blocked_site_are_dropped() {
+        let db = Database::Sqlite(SqliteStorage::memory().await);
+        db.block_link("scam.example", None).await.unwrap();
+
+        let stored = ingest(
+            &db,
+            &[
+                ("blocked", post("https://scam.example/win")),
+                ("fine", post("https://tickets.example/tour")),
+            ],
+        )
+        .await;
+        assert_eq!(vec!["fine"], stored);
+
+        // and posts that were stored before a site was blocked are purged
+        assert_eq!(1, db.block_link("tickets.example", None).await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn test_only_engagement_with_stored_posts_is_queued() {
+        let db = Database::Sqlite(SqliteStorage::memory().await);
+        let stored = "at://did:plc:author/app.bsky.feed.post/stored";
+        ingest(&db, &[(stored, post("https://tickets.example/tour"))]).await;
+
+        // the stored posts are loaded when it starts
+        let (data, flushed) = app_data(&db).await;
+        let commit = commit();
+        for (uri, subject) in [
+            ("like-stored", stored),
+            (
+                "like-unknown",
+                "at://did:plc:author/app.bsky.feed.post/unknown",
+            ),
+            (
+                "like-profile",
+                "at://did:plc:author/app.bsky.actor.profile/self",
+            ),
+        ] {
+            let params = OnEngagementParams {
+                commit: &commit,
+                uri: uri.to_string(),
+                subject,
+            };
+            on_engagement(params, data.clone()).await;
+        }
+        assert!(data.engagements.contains("like-stored"));
+        assert!(!data.engagements.contains("like-unknown"));
+        assert!(!data.engagements.contains("like-profile"));
+
+        data.reload().await.unwrap();
+        assert_eq!(vec!["like-stored"], db.engagement_uris().await.unwrap());
+        assert!(data.engagements.contains("like-stored"));
+
+        for uri in ["like-unknown", "like-stored"] {
+            let params = OnEngagementDeleteParams {
+                commit: &commit,
+                uri: uri.to_string(),
+            };
+            on_engagement_delete(params, data.clone()).await;
+        }
+        assert!(!data.engagements.contains("like-stored"));
+
+        drop(data);
+        flushed.await.unwrap();
+        assert!(db.engagement_uris().await.unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_known_uris_keep_the_ones_added_during_a_reload() {
+        let known = KnownUris::default();
+        known.insert("purged".to_string());
+        std::thread::sleep(Duration::from_millis(1));
+        let since = Instant::now();
+        known.insert("queued".to_string());
+
+        known.reload(vec!["stored".to_string()], since);
+        assert!(!known.contains("purged"));
+        assert!(known.contains("queued"));
+        assert!(known.contains("stored"));
     }
 }
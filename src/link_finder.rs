@@ -1,3 +1,6 @@
+use regex::Regex;
+use std::sync::LazyLock;
+
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub struct FoundLink<'a> {
     pub url: &'a str,
@@ -22,6 +25,52 @@ pub enum Site {
     Bandcamp,
 }
 
+impl Kind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Kind::Track => "track",
+            Kind::Playlist => "playlist",
+            Kind::Album => "album",
+        }
+    }
+}
+
+impl std::str::FromStr for Kind {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "track" => Ok(Kind::Track),
+            "playlist" => Ok(Kind::Playlist),
+            "album" => Ok(Kind::Album),
+            _ => Err(anyhow::anyhow!("unknown kind {s}")),
+        }
+    }
+}
+
+impl Site {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Site::Spotify => "spotify",
+            Site::Soundclound => "soundclound",
+            Site::Bandcamp => "bandcamp",
+        }
+    }
+}
+
+impl std::str::FromStr for Site {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "spotify" => Ok(Site::Spotify),
+            "soundclound" => Ok(Site::Soundclound),
+            "bandcamp" => Ok(Site::Bandcamp),
+            _ => Err(anyhow::anyhow!("unknown site {s}")),
+        }
+    }
+}
+
 mod spotify {
     use regex::Regex;
     use std::sync::LazyLock;
@@ -103,3 +152,39 @@ pub fn get_music_links(text: &str) -> Vec<FoundLink<'_>> {
 
     spotify
 }
+
+static URL_REGEX: LazyLock<Regex> = LazyLock::new(|| Regex::new(r#"https?://[^\s<>"]+"#).unwrap());
+
+/// Every url written out in `text`, music or not. Punctuation right after a url is taken to end
+/// the sentence rather than be part of it
+pub fn get_urls(text: &str) -> Vec<&str> {
+    URL_REGEX
+        .find_iter(text)
+        .map(|url| {
+            url.as_str()
+                .trim_end_matches(['.', ',', ';', ':', '!', '?', ')', '\''])
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_get_urls() {
+        let urls = get_urls(
+            "new album https://open.spotify.com/album/abc! and free tickets at http://scam.example/win?id=1. (see https://x.example/a)",
+        );
+
+        assert_eq!(
+            vec![
+                "https://open.spotify.com/album/abc",
+                "http://scam.example/win?id=1",
+                "https://x.example/a"
+            ],
+            urls
+        );
+        assert!(get_urls("nothing to find here, not even scam.example").is_empty());
+    }
+}
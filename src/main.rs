@@ -1,61 +1,265 @@
+use std::sync::Arc;
+
+use algos::Registry;
 use anyhow::Context;
+use auth::ServiceAuth;
+use clap::{Parser, Subcommand};
+use config::Config;
+use firehose::FirehoseStatus;
 use ingest::start_ingest;
-use server::{start_server, Config};
-use sqlx::{sqlite::SqlitePoolOptions, Pool, Sqlite};
+use metrics_exporter_prometheus::PrometheusHandle;
+use server::{start_server, Routes};
+use storage::{Database, Storage};
+use tokio_util::{sync::CancellationToken, task::TaskTracker};
+use tracing_subscriber::EnvFilter;
 
+mod admin;
 mod algos;
 mod atproto;
+mod auth;
+mod batch;
+mod commands;
+mod config;
+mod did;
+mod feed_context;
+mod feedback;
+mod feeds;
 mod firehose;
+mod health;
 mod ingest;
 mod link_finder;
+mod maintenance;
+mod metrics;
 mod models;
+mod moderation;
+mod publish;
 mod server;
+mod spam;
+mod storage;
+mod xrpc;
 
 pub struct AppState {
-    pub config: Config,
-    pub pool: Pool<Sqlite>,
+    pub config: server::Config,
+    pub db: Database,
+    /// The algorithms of the feeds we serve
+    pub algos: Registry,
+    pub metrics: PrometheusHandle,
+    /// What the firehose subscription is up to. `None` if this process doesn't ingest
+    pub firehose: Option<Arc<FirehoseStatus>>,
+    pub auth: ServiceAuth,
+}
+
+/// A bluesky feed generator for posts with music links
+#[derive(Parser)]
+struct Cli {
+    #[command(subcommand)]
+    command: Option<Command>,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Ingests the firehose and serves the feeds. This is the default
+    Run,
+    /// Only serves the feeds, next to a separate `ingest` process using the same database
+    Serve,
+    /// Only ingests the firehose and labelers, and prunes old posts. `/metrics`, `/health`, and
+    /// `/ready` are still served
+    Ingest,
+    /// Runs the database migrations
+    Migrate,
+    /// Deletes old posts, and links no post contains anymore
+    Prune {
+        /// Also vacuum and optimize the database afterwards. Vacuuming blocks writes until it's
+        /// done, so on SQLite stop ingest first
+        #[arg(long)]
+        optimize: bool,
+    },
+    /// Prints how much is in the database
+    Stats,
+    /// Runs the link finder on every stored link again, after changing it
+    Reindex,
+    /// Creates, updates, and deletes the feed generator records of the configured feeds
+    Publish(publish::Args),
+}
+
+/// What a long running process does
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Role {
+    Both,
+    Serve,
+    Ingest,
 }
 
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn std::error::Error>> {
-    dotenv::dotenv().context("failed to load .env")?;
+    load_dotenv()?;
+    let cli = Cli::parse();
+    init_logging()?;
 
-    let db_connection_str = std::env::var("DATABASE_URL").context("failed to get db url")?;
+    let config = Config::load()?;
 
-    let pool = SqlitePoolOptions::new()
-        .max_connections(5)
-        .connect(&db_connection_str)
-        .await
-        .context("failed to connect to db")?;
+    match cli.command.unwrap_or(Command::Run) {
+        Command::Run => start(config, Role::Both).await?,
+        Command::Serve => start(config, Role::Serve).await?,
+        Command::Ingest => start(config, Role::Ingest).await?,
+        Command::Migrate => commands::migrate(&config).await?,
+        Command::Prune { optimize } => commands::prune(&config, optimize).await?,
+        Command::Stats => commands::stats(&config).await?,
+        Command::Reindex => commands::reindex(&config).await?,
+        Command::Publish(args) => {
+            let changes = publish::publish(
+                &args,
+                &config.feeds,
+                &config.server.service_did,
+                &config.server.publisher_did,
+            )
+            .await?;
 
-    sqlx::migrate!("./migrations")
-        .run(&pool)
-        .await
-        .context("failed to run migrations")?;
+            for change in &changes {
+                println!("{change}");
+            }
+            if changes.is_empty() {
+                println!("every feed is up to date");
+            }
+        }
+    }
 
-    tokio::spawn({
-        let pool = pool.clone();
-        async move {
-            start_ingest(pool).await.unwrap();
+    Ok(())
+}
+
+/// Ingests the firehose, serves the feeds, or both, until we get ctrl-c or SIGTERM
+async fn start(config: Config, role: Role) -> Result<(), Box<dyn std::error::Error>> {
+    let metrics = metrics::install()?;
+
+    let db = Database::connect(&config.database_url).await?;
+    db.migrate().await?;
+
+    let shutdown = CancellationToken::new();
+    tokio::spawn(shutdown_on_signal(shutdown.clone()));
+
+    let firehose = (role != Role::Serve).then(|| Arc::new(FirehoseStatus::default()));
+    let ingest = firehose.clone().map(|firehose| {
+        tokio::spawn(maintenance::start_maintenance(db.clone(), config.retention));
+
+        // label and firehose ingest both finish what they're writing once shutdown starts
+        let tasks = TaskTracker::new();
+        for labeler in config.labelers {
+            let db = db.clone();
+            let shutdown = shutdown.clone();
+            tasks.spawn(async move {
+                if let Err(err) = moderation::start_label_ingest(db, &labeler, shutdown).await {
+                    tracing::error!(labeler, "label ingest stopped: {err:?}");
+                }
+            });
         }
+
+        let db = db.clone();
+        let relay = config.relay.clone();
+        let shutdown = shutdown.clone();
+        tasks.spawn(async move {
+            if let Err(err) =
+                start_ingest(db, relay, config.spam, config.batch, firehose, shutdown).await
+            {
+                tracing::error!("ingest stopped: {err:?}");
+            }
+        });
+        tasks.close();
+        tasks
     });
 
-    let config = server::Config {
-        service_did: std::env::var("FEEDGEN_SERVICE_DID")
-            .context("failed to get FEEDGEN_SERVICE_DID")?,
-        publisher_did: std::env::var("FEEDGEN_PUBLISHER_DID")
-            .context("failed to get FEEDGEN_PUBLISHER_DID")?,
-        hostname: std::env::var("FEEDGEN_HOSTNAME").context("failed to get FEEDGEN_HOSTNAME")?,
+    let app_state = AppState {
+        config: config.server,
+        db: db.clone(),
+        algos: Registry::from_feeds(&config.feeds),
+        metrics,
+        firehose,
+        auth: ServiceAuth::new(config.allow_localhost_dids),
+    };
+    let routes = match role {
+        Role::Ingest => Routes::Monitoring,
+        Role::Both | Role::Serve => Routes::All,
     };
+    // config validation already checked that did:web identities match the hostname
+    let server = &app_state.config;
+    if routes == Routes::All && !server.service_did.starts_with("did:web:") {
+        tracing::warn!(
+            did = server.service_did,
+            "the service DID isn't a did:web, so /.well-known/did.json isn't served. make sure its DID document has a #bsky_fg BskyFeedGenerator service at https://{}",
+            server.hostname
+        );
+    }
 
-    let app_state = AppState { config, pool };
+    let shutdown_timeout = config.shutdown_timeout;
+    let finished = async {
+        // the server only stops once shutdown starts, and then ingest finishes up
+        start_server(app_state, config.port, routes, shutdown.clone()).await;
+        if let Some(ingest) = ingest {
+            ingest.wait().await;
+        }
+    };
+    let deadline = async {
+        shutdown.cancelled().await;
+        tokio::time::sleep(shutdown_timeout).await;
+    };
+    tokio::select! {
+        _ = finished => {}
+        _ = deadline => tracing::warn!(?shutdown_timeout, "could not shut down in time, stopping anyway"),
+    }
+
+    db.close().await;
+
+    Ok(())
+}
+
+/// Loads `.env` into the environment, if there is one
+fn load_dotenv() -> anyhow::Result<()> {
+    match dotenv::dotenv() {
+        Ok(_) => Ok(()),
+        Err(err) if err.not_found() => Ok(()),
+        Err(err) => Err(err).context("failed to load .env"),
+    }
+}
+
+/// Cancels `shutdown` once we get ctrl-c or SIGTERM
+async fn shutdown_on_signal(shutdown: CancellationToken) {
+    let ctrl_c = async {
+        tokio::signal::ctrl_c()
+            .await
+            .expect("failed to listen for ctrl-c");
+    };
+
+    #[cfg(unix)]
+    let terminate = async {
+        tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate())
+            .expect("failed to listen for SIGTERM")
+            .recv()
+            .await;
+    };
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<()>();
+
+    tokio::select! {
+        _ = ctrl_c => {}
+        _ = terminate => {}
+    }
+
+    tracing::info!("shutting down");
+    shutdown.cancel();
+}
 
-    let port: u16 = std::env::var("PORT")
-        .ok()
-        .and_then(|a| a.parse().ok())
-        .unwrap_or(3000);
+/// Logs to stderr, filtered by `RUST_LOG` (`info` by default). Set `FEEDGEN_LOG_FORMAT=json` to
+/// get one json object per line
+fn init_logging() -> anyhow::Result<()> {
+    let filter = EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new("info"));
+    let logs = tracing_subscriber::fmt()
+        .with_env_filter(filter)
+        .with_writer(std::io::stderr);
 
-    start_server(app_state, port).await;
+    match std::env::var("FEEDGEN_LOG_FORMAT").as_deref() {
+        Ok("json") => logs.json().init(),
+        Ok("text") | Err(_) => logs.init(),
+        Ok(format) => anyhow::bail!("unknown FEEDGEN_LOG_FORMAT {format}, expected text or json"),
+    }
 
     Ok(())
 }
@@ -0,0 +1,240 @@
+use std::time::Duration;
+
+use anyhow::Result;
+use chrono::{DateTime, Utc};
+
+use crate::{
+    link_finder::{self, Kind, Site},
+    storage::{Database, Storage},
+};
+
+pub struct RetentionConfig {
+    /// Posts indexed longer ago than this are deleted
+    pub post_max_age: Duration,
+    /// How often old posts and orphaned links are pruned
+    pub prune_interval: Duration,
+    /// How often free space is reclaimed and the database is optimized. The whole database is
+    /// only vacuumed by `prune --optimize`
+    pub vacuum_interval: Duration,
+}
+
+impl Default for RetentionConfig {
+    fn default() -> Self {
+        Self {
+            post_max_age: Duration::from_secs(30 * 24 * 60 * 60),
+            prune_interval: Duration::from_secs(60 * 60),
+            vacuum_interval: Duration::from_secs(24 * 60 * 60),
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub struct PruneReport {
+    pub posts: u64,
+    pub links: u64,
+}
+
+/// How much is in the database
+#[derive(Debug, PartialEq, Eq)]
+pub struct Stats {
+    pub posts: i64,
+    pub authors: i64,
+    pub links: i64,
+    pub oldest_post: Option<DateTime<Utc>>,
+    pub newest_post: Option<DateTime<Utc>>,
+}
+
+/// A link as it's stored
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StoredLink {
+    pub url: String,
+    pub kind: Kind,
+    pub site: Site,
+}
+
+/// What happens to a stored link after running the link finder on it again
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum LinkUpdate {
+    /// The link is now found as a different kind or site
+    Reclassify { url: String, kind: Kind, site: Site },
+    /// The link isn't found anymore. Posts left without any links are removed with it
+    Remove { url: String },
+}
+
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct ReindexReport {
+    pub reclassified: u64,
+    pub links: u64,
+    pub posts: u64,
+}
+
+/// Periodically prunes old rows and optimizes the database. Never returns
+pub async fn start_maintenance(db: Database, config: RetentionConfig) {
+    let mut prune_interval = tokio::time::interval(config.prune_interval);
+    let mut vacuum_interval = tokio::time::interval(config.vacuum_interval);
+    // don't optimize right at startup
+    vacuum_interval.reset();
+
+    loop {
+        tokio::select! {
+            _ = prune_interval.tick() => {
+                match prune(&db, config.post_max_age).await {
+                    Ok(report) => tracing::info!(
+                        posts = report.posts,
+                        links = report.links,
+                        "pruned old rows"
+                    ),
+                    Err(err) => tracing::error!("could not prune: {err:?}"),
+                }
+            }
+            _ = vacuum_interval.tick() => {
+                if let Err(err) = db.optimize().await {
+                    tracing::error!("could not optimize db: {err:?}");
+                }
+            }
+        }
+    }
+}
+
+/// Deletes posts older than `max_age`, and links that were only in posts that no longer exist
+pub async fn prune(storage: &impl Storage, max_age: Duration) -> Result<PruneReport> {
+    storage.prune(Utc::now() - max_age).await
+}
+
+/// Runs the link finder on every stored link again, so links and the posts with them follow
+/// changes to it
+pub async fn reindex(storage: &impl Storage) -> Result<ReindexReport> {
+    let updates = storage
+        .links()
+        .await?
+        .into_iter()
+        .filter_map(|link| {
+            let found = link_finder::get_music_links(&link.url)
+                .into_iter()
+                .find(|found| found.url == link.url)
+                .map(|found| (found.kind, found.site));
+            match found {
+                Some((kind, site)) if kind == link.kind && site == link.site => None,
+                Some((kind, site)) => Some(LinkUpdate::Reclassify {
+                    url: link.url,
+                    kind,
+                    site,
+                }),
+                None => Some(LinkUpdate::Remove { url: link.url }),
+            }
+        })
+        .collect::<Vec<_>>();
+
+    if updates.is_empty() {
+        return Ok(ReindexReport::default());
+    }
+    storage.update_links(&updates).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::storage::SqliteStorage;
+
+    const DAY: Duration = Duration::from_secs(24 * 60 * 60);
+
+    async fn create(storage: &SqliteStorage, uri: &str, url: &str, age: Duration) {
+        let time = Utc::now() - age;
+        sqlx::query!(
+            "insert into posts (uri, cid, author, indexed_at) values (?, 'cid', 'did:plc:author', ?)",
+            uri,
+            time
+        )
+        .execute(storage.pool())
+        .await
+        .unwrap();
+        sqlx::query!(
+            "insert into links (url, kind, site, created_at) values (?, 'track', 'spotify', ?) on conflict do nothing",
+            url,
+            time
+        )
+        .execute(storage.pool())
+        .await
+        .unwrap();
+        sqlx::query!(
+            "insert into post_links (post_uri, url) values (?, ?)",
+            uri,
+            url
+        )
+        .execute(storage.pool())
+        .await
+        .unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_prune_removes_old_posts_and_their_links() {
+        let storage = SqliteStorage::memory().await;
+        create(&storage, "old", "old-link", DAY * 40).await;
+        create(&storage, "new", "new-link", DAY).await;
+
+        let report = prune(&storage, DAY * 30).await.unwrap();
+
+        assert_eq!(PruneReport { posts: 1, links: 1 }, report);
+        let urls = sqlx::query_scalar!("select url from links")
+            .fetch_all(storage.pool())
+            .await
+            .unwrap();
+        assert_eq!(vec![Some("new-link".to_string())], urls);
+    }
+
+    #[tokio::test]
+    async fn test_prune_keeps_links_still_in_recent_posts() {
+        let storage = SqliteStorage::memory().await;
+        create(&storage, "old", "shared", DAY * 40).await;
+        create(&storage, "new", "shared", DAY).await;
+
+        let report = prune(&storage, DAY * 30).await.unwrap();
+
+        assert_eq!(PruneReport { posts: 1, links: 0 }, report);
+    }
+
+    #[tokio::test]
+    async fn test_reindex() {
+        let storage = SqliteStorage::memory().await;
+        // stored as spotify tracks by `create`
+        create(&storage, "album", "https://open.spotify.com/album/a", DAY).await;
+        create(&storage, "track", "https://open.spotify.com/track/b", DAY).await;
+        create(&storage, "gone", "https://example.com/not-music", DAY).await;
+
+        let report = reindex(&storage).await.unwrap();
+
+        assert_eq!(
+            ReindexReport {
+                reclassified: 1,
+                links: 1,
+                posts: 1,
+            },
+            report
+        );
+        let kinds = storage
+            .links()
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|link| (link.url, link.kind))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            vec![
+                ("https://open.spotify.com/album/a".to_string(), Kind::Album),
+                ("https://open.spotify.com/track/b".to_string(), Kind::Track),
+            ],
+            kinds
+        );
+
+        // nothing changes the second time
+        assert_eq!(ReindexReport::default(), reindex(&storage).await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn test_optimize() {
+        let storage = SqliteStorage::memory().await;
+
+        storage.optimize().await.unwrap();
+    }
+}
@@ -0,0 +1,138 @@
+//! Prometheus metrics, served on `/metrics`.
+//!
+//! Every metric is recorded through one of the functions here, so the names and labels all live
+//! in one place
+
+use std::time::Duration;
+
+use ::metrics::{counter, histogram};
+use anyhow::{Context, Result};
+use axum::http::StatusCode;
+use metrics_exporter_prometheus::{Matcher, PrometheusBuilder, PrometheusHandle};
+
+use crate::link_finder::{Kind, Site};
+
+/// Buckets for things that should take milliseconds, like db queries and feed requests
+const LATENCY_BUCKETS: &[f64] = &[
+    0.0005, 0.001, 0.0025, 0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0,
+];
+/// Buckets for how far behind the firehose we are, which is normally a second or two
+const LAG_BUCKETS: &[f64] = &[
+    0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0, 30.0, 60.0, 300.0, 900.0, 3600.0,
+];
+
+/// Installs the global recorder, and returns a handle to render it with
+pub fn install() -> Result<PrometheusHandle> {
+    let handle = builder()?
+        .install_recorder()
+        .context("failed to install metrics recorder")?;
+
+    // keeps the histograms from growing forever
+    tokio::spawn({
+        let handle = handle.clone();
+        async move {
+            let mut interval = tokio::time::interval(Duration::from_secs(5));
+            loop {
+                interval.tick().await;
+                handle.run_upkeep();
+            }
+        }
+    });
+
+    Ok(handle)
+}
+
+/// A handle to a recorder that is never installed, for tests
+#[cfg(test)]
+pub fn test_handle() -> PrometheusHandle {
+    builder().unwrap().build_recorder().handle()
+}
+
+fn builder() -> Result<PrometheusBuilder> {
+    PrometheusBuilder::new()
+        .set_buckets(LATENCY_BUCKETS)?
+        .set_buckets_for_metric(
+            Matcher::Full("feedgen_firehose_lag_seconds".into()),
+            LAG_BUCKETS,
+        )
+        .context("failed to set metric buckets")
+}
+
+/// A frame arrived on the firehose. `kind` is its message type, like `#commit`, or `error`
+pub fn frame_received(kind: &str) {
+    counter!("feedgen_firehose_frames_total", "type" => kind.to_string()).increment(1);
+}
+
+/// A commit was decoded, and is about to be handled. `lag` is how long ago it was made
+pub fn commit_received(lag: Duration) {
+    counter!("feedgen_firehose_commits_received_total").increment(1);
+    histogram!("feedgen_firehose_lag_seconds").record(lag);
+}
+
+pub fn commit_processed() {
+    counter!("feedgen_firehose_commits_processed_total").increment(1);
+}
+
+pub fn commit_failed() {
+    counter!("feedgen_firehose_commits_failed_total").increment(1);
+}
+
+/// A post was written to the db. A post with links to several sites or kinds counts once for each
+pub fn post_indexed(site: &Site, kind: &Kind) {
+    counter!(
+        "feedgen_posts_indexed_total",
+        "site" => site.as_str(),
+        "kind" => kind.as_str()
+    )
+    .increment(1);
+}
+
+/// A post containing a link was written to the db
+pub fn link_indexed(site: &Site, kind: &Kind) {
+    counter!(
+        "feedgen_links_indexed_total",
+        "site" => site.as_str(),
+        "kind" => kind.as_str()
+    )
+    .increment(1);
+}
+
+/// A call to a [`Storage`](crate::storage::Storage) method finished
+pub fn db_query(query: &'static str, elapsed: Duration) {
+    histogram!("feedgen_db_query_duration_seconds", "query" => query).record(elapsed);
+}
+
+/// A `getFeedSkeleton` request finished. `feed` is the rkey of the feed, or `unknown`
+pub fn feed_request(feed: &str, status: StatusCode, elapsed: Duration) {
+    let labels = [
+        ("feed", feed.to_string()),
+        ("status", status.as_u16().to_string()),
+    ];
+    counter!("feedgen_feed_requests_total", &labels).increment(1);
+    histogram!("feedgen_feed_request_duration_seconds", &labels).record(elapsed);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_render() {
+        let recorder = builder().unwrap().build_recorder();
+        ::metrics::with_local_recorder(&recorder, || {
+            feed_request("music", StatusCode::OK, Duration::from_millis(3));
+            post_indexed(&Site::Bandcamp, &Kind::Album);
+            commit_received(Duration::from_secs(2));
+        });
+
+        let rendered = recorder.handle().render();
+
+        assert!(rendered.contains(r#"feedgen_feed_requests_total{feed="music",status="200"} 1"#));
+        assert!(rendered.contains(
+            r#"feedgen_feed_request_duration_seconds_bucket{feed="music",status="200",le="0.005"} 1"#
+        ));
+        assert!(rendered.contains(r#"feedgen_posts_indexed_total{site="bandcamp",kind="album"} 1"#));
+        assert!(rendered.contains(r#"feedgen_firehose_lag_seconds_bucket{le="1"} 0"#));
+        assert!(rendered.contains(r#"feedgen_firehose_lag_seconds_bucket{le="2.5"} 1"#));
+    }
+}
@@ -0,0 +1,167 @@
+use anyhow::{Context, Result};
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use sqlx::{Executor, Sqlite};
+
+#[derive(Serialize)]
+pub struct BlockedAuthor {
+    /// The blocked author's DID. Eg: `did:plc:asdfghjkl`
+    pub did: String,
+    /// Why the author was blocked, for our own reference
+    pub reason: Option<String>,
+    pub created_at: DateTime<Utc>,
+}
+
+impl BlockedAuthor {
+    /// Blocks an author, and removes all of their already indexed posts.
+    ///
+    /// Returns how many posts were removed
+    pub async fn create(
+        conn: &mut sqlx::SqliteConnection,
+        did: &str,
+        reason: Option<&str>,
+    ) -> Result<u64> {
+        let now = Utc::now();
+        sqlx::query!(
+            "insert into blocked_authors (did, reason, created_at) values (?, ?, ?) on conflict(did) do update set reason = excluded.reason",
+            did,
+            reason,
+            now,
+        )
+        .execute(&mut *conn)
+        .await
+        .context("failed to create blocked author")?;
+
+        let purged = sqlx::query!("delete from posts where author = ?", did)
+            .execute(&mut *conn)
+            .await
+            .with_context(|| format!("failed to purge posts by {did}"))?
+            .rows_affected();
+
+        Ok(purged)
+    }
+
+    pub async fn delete<'e, E>(executor: E, did: &str) -> Result<()>
+    where
+        E: Executor<'e, Database = Sqlite>,
+    {
+        sqlx::query!("delete from blocked_authors where did = ?", did)
+            .execute(executor)
+            .await
+            .with_context(|| format!("failed to delete blocked author {did}"))?;
+
+        Ok(())
+    }
+
+    pub async fn get_all<'e, E>(executor: E) -> Result<Vec<BlockedAuthor>>
+    where
+        E: Executor<'e, Database = Sqlite>,
+    {
+        let blocked = sqlx::query!("select * from blocked_authors order by created_at desc")
+            .fetch_all(executor)
+            .await?
+            .into_iter()
+            .map(|blocked| BlockedAuthor {
+                did: blocked.did,
+                reason: blocked.reason,
+                created_at: blocked.created_at.and_utc(),
+            })
+            .collect();
+
+        Ok(blocked)
+    }
+
+    pub async fn is_blocked<'e, E>(executor: E, did: &str) -> Result<bool>
+    where
+        E: Executor<'e, Database = Sqlite>,
+    {
+        let blocked = sqlx::query_scalar!(
+            "select exists(select 1 from blocked_authors where did = ?)",
+            did
+        )
+        .fetch_one(executor)
+        .await?;
+
+        Ok(blocked == 1)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::models::posts::{NewPost, Post};
+    use sqlx::{Connection, SqliteConnection};
+
+    async fn conn() -> SqliteConnection {
+        let mut conn = SqliteConnection::connect("sqlite::memory:").await.unwrap();
+
+        sqlx::migrate!("./migrations/sqlite")
+            .run(&mut conn)
+            .await
+            .unwrap();
+
+        conn
+    }
+
+    async fn create_post(conn: &mut SqliteConnection, uri: &str, author: &str) {
+        Post::create(
+            conn,
+            &NewPost {
+                uri,
+                cid: "cid".to_string(),
+                author,
+                reply_root: None,
+                reply_parent: None,
+                quote: None,
+                fingerprint: None,
+                langs: &[],
+            },
+        )
+        .await
+        .unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_blocking_purges_only_their_posts() {
+        let mut conn = conn().await;
+        create_post(&mut conn, "a", "did:plc:spammer").await;
+        create_post(&mut conn, "b", "did:plc:spammer").await;
+        create_post(&mut conn, "c", "did:plc:fine").await;
+
+        let purged = BlockedAuthor::create(&mut conn, "did:plc:spammer", Some("spam"))
+            .await
+            .unwrap();
+
+        assert_eq!(2, purged);
+        let uris = sqlx::query_scalar!("select uri from posts")
+            .fetch_all(&mut conn)
+            .await
+            .unwrap();
+        assert_eq!(vec![Some("c".to_string())], uris);
+    }
+
+    #[tokio::test]
+    async fn test_is_blocked() {
+        let mut conn = conn().await;
+
+        BlockedAuthor::create(&mut conn, "did:plc:spammer", None)
+            .await
+            .unwrap();
+
+        assert!(BlockedAuthor::is_blocked(&mut conn, "did:plc:spammer")
+            .await
+            .unwrap());
+        assert!(!BlockedAuthor::is_blocked(&mut conn, "did:plc:fine")
+            .await
+            .unwrap());
+
+        BlockedAuthor::delete(&mut conn, "did:plc:spammer")
+            .await
+            .unwrap();
+
+        assert!(!BlockedAuthor::is_blocked(&mut conn, "did:plc:spammer")
+            .await
+            .unwrap());
+    }
+}
@@ -0,0 +1,291 @@
+use anyhow::{Context, Result};
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use sqlx::{Executor, Sqlite};
+
+/// A blocked link is stored as `host[/path]`, see [`BlockedLink::normalise`]. A host blocks links
+/// to it and its subdomains, and a path blocks links to it and anything under it.
+///
+/// Links are matched against every url in a post, through the `blocked_post_links` view. Posts
+/// indexed before post_urls existed only have their music links matched, and posts from before
+/// post_links existed are never matched
+#[derive(Serialize)]
+pub struct BlockedLink {
+    pub url: String,
+    /// Why the link was blocked, for our own reference
+    pub reason: Option<String>,
+    pub created_at: DateTime<Utc>,
+}
+
+impl BlockedLink {
+    /// Turns a url, or a bare domain, into the form links are blocked by: the lowercased host
+    /// without `www.`, followed by the path if there is one. The scheme, port, query, fragment and
+    /// trailing slashes are dropped.
+    ///
+    /// Returns `None` if there's no host that could be a domain
+    pub fn normalise(url: &str) -> Option<String> {
+        let url = url.trim();
+        let rest = url.split_once("://").map_or(url, |(_, rest)| rest);
+        let rest = rest.split(['?', '#']).next().unwrap_or_default();
+        let (host, path) = rest.split_at(rest.find('/').unwrap_or(rest.len()));
+        let host = host.split(':').next().unwrap_or_default().to_lowercase();
+        let host = host.strip_prefix("www.").unwrap_or(&host);
+
+        let valid = host.contains('.')
+            && !host.starts_with('.')
+            && !host.ends_with('.')
+            && host
+                .bytes()
+                .all(|b| b.is_ascii_alphanumeric() || matches!(b, b'-' | b'.'));
+        if !valid || path.contains(char::is_whitespace) {
+            return None;
+        }
+
+        Some(format!("{host}{}", path.trim_end_matches('/')))
+    }
+
+    /// Whether `blocked`, a normalised blocked link, blocks `url`. This is the same match the
+    /// `blocked_post_links` view makes
+    pub fn blocks(blocked: &str, url: &str) -> bool {
+        let Some(url) = Self::normalise(url) else {
+            return false;
+        };
+        let host = url.split('/').next().unwrap_or_default();
+
+        url == blocked
+            || url.starts_with(&format!("{blocked}/"))
+            || (!blocked.contains('/') && host.ends_with(&format!(".{blocked}")))
+    }
+
+    /// Blocks a link, and removes all already indexed posts that contain it.
+    ///
+    /// Returns how many posts were removed
+    pub async fn create(
+        conn: &mut sqlx::SqliteConnection,
+        url: &str,
+        reason: Option<&str>,
+    ) -> Result<u64> {
+        let now = Utc::now();
+        sqlx::query!(
+            "insert into blocked_links (url, reason, created_at) values (?, ?, ?) on conflict(url) do update set reason = excluded.reason",
+            url,
+            reason,
+            now,
+        )
+        .execute(&mut *conn)
+        .await
+        .context("failed to create blocked link")?;
+
+        let purged = sqlx::query!(
+            "delete from posts where uri in (select post_uri from blocked_post_links where blocked_url = ?)",
+            url
+        )
+        .execute(&mut *conn)
+        .await
+        .with_context(|| format!("failed to purge posts linking to {url}"))?
+        .rows_affected();
+
+        Ok(purged)
+    }
+
+    pub async fn delete<'e, E>(executor: E, url: &str) -> Result<()>
+    where
+        E: Executor<'e, Database = Sqlite>,
+    {
+        sqlx::query!("delete from blocked_links where url = ?", url)
+            .execute(executor)
+            .await
+            .with_context(|| format!("failed to delete blocked link {url}"))?;
+
+        Ok(())
+    }
+
+    pub async fn get_all<'e, E>(executor: E) -> Result<Vec<BlockedLink>>
+    where
+        E: Executor<'e, Database = Sqlite>,
+    {
+        let blocked = sqlx::query!("select * from blocked_links order by created_at desc")
+            .fetch_all(executor)
+            .await?
+            .into_iter()
+            .map(|blocked| BlockedLink {
+                url: blocked.url,
+                reason: blocked.reason,
+                created_at: blocked.created_at.and_utc(),
+            })
+            .collect();
+
+        Ok(blocked)
+    }
+
+    /// Checks whether any of `urls` is blocked
+    pub async fn any_blocked<'e, E>(executor: E, urls: &[&str]) -> Result<bool>
+    where
+        E: Executor<'e, Database = Sqlite>,
+    {
+        let blocked = sqlx::query_scalar!("select url from blocked_links")
+            .fetch_all(executor)
+            .await?;
+
+        Ok(blocked
+            .iter()
+            .any(|blocked| urls.iter().any(|url| Self::blocks(blocked, url))))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::{
+        link_finder::{FoundLink, Kind, Site},
+        models::{
+            links::Link,
+            posts::{NewPost, Post},
+        },
+    };
+    use sqlx::{Connection, SqliteConnection};
+
+    async fn conn() -> SqliteConnection {
+        let mut conn = SqliteConnection::connect("sqlite::memory:").await.unwrap();
+
+        sqlx::migrate!("./migrations/sqlite")
+            .run(&mut conn)
+            .await
+            .unwrap();
+
+        conn
+    }
+
+    async fn create_post(conn: &mut SqliteConnection, uri: &str, url: &str) {
+        Post::create(
+            &mut *conn,
+            &NewPost {
+                uri,
+                cid: "cid".to_string(),
+                author: "did:plc:author",
+                reply_root: None,
+                reply_parent: None,
+                quote: None,
+                fingerprint: None,
+                langs: &[],
+            },
+        )
+        .await
+        .unwrap();
+        let link = FoundLink {
+            url,
+            kind: Kind::Playlist,
+            site: Site::Spotify,
+        };
+        Link::create(&mut *conn, &link).await.unwrap();
+        Link::add_to_post(&mut *conn, uri, &link).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_blocking_purges_posts_with_matching_links() {
+        let mut conn = conn().await;
+        create_post(&mut conn, "a", "https://open.spotify.com/playlist/scam").await;
+        create_post(&mut conn, "b", "https://open.spotify.com/playlist/fine").await;
+
+        let purged = BlockedLink::create(&mut conn, "open.spotify.com/playlist/scam", None)
+            .await
+            .unwrap();
+
+        assert_eq!(1, purged);
+        let uris = sqlx::query_scalar!("select uri from posts")
+            .fetch_all(&mut conn)
+            .await
+            .unwrap();
+        assert_eq!(vec![Some("b".to_string())], uris);
+    }
+
+    #[test]
+    fn test_normalise() {
+        assert_eq!(
+            Some("scam.example".to_string()),
+            BlockedLink::normalise("scam.example")
+        );
+        assert_eq!(
+            Some("scam.example/a/b".to_string()),
+            BlockedLink::normalise("https://WWW.Scam.Example:443/a/b/?si=1#top")
+        );
+        assert_eq!(
+            Some("open.spotify.com/playlist/Scam".to_string()),
+            BlockedLink::normalise("open.spotify.com/playlist/Scam")
+        );
+        assert_eq!(None, BlockedLink::normalise(""));
+        assert_eq!(None, BlockedLink::normalise("https://localhost/a"));
+        assert_eq!(None, BlockedLink::normalise("scam example.com"));
+    }
+
+    #[test]
+    fn test_blocks() {
+        assert!(BlockedLink::blocks("scam.example", "https://scam.example"));
+        assert!(BlockedLink::blocks(
+            "scam.example",
+            "https://scam.example/a"
+        ));
+        assert!(BlockedLink::blocks(
+            "scam.example",
+            "https://www.scam.example/a"
+        ));
+        assert!(BlockedLink::blocks(
+            "scam.example",
+            "https://a.scam.example/a"
+        ));
+        assert!(!BlockedLink::blocks(
+            "scam.example",
+            "https://notscam.example/a"
+        ));
+        assert!(!BlockedLink::blocks(
+            "scam.example",
+            "https://scam.example.com/a"
+        ));
+
+        assert!(BlockedLink::blocks("x.example/a", "https://x.example/a/b"));
+        assert!(!BlockedLink::blocks("x.example/a", "https://x.example/ab"));
+        assert!(!BlockedLink::blocks("x.example/a", "https://y.x.example/a"));
+    }
+
+    #[tokio::test]
+    async fn test_blocking_matches_hosts_not_substrings() {
+        let mut conn = conn().await;
+        create_post(&mut conn, "a", "https://scam.example/a").await;
+        create_post(&mut conn, "b", "https://www.scam.example/b").await;
+        create_post(&mut conn, "c", "https://notscam.example/c").await;
+        create_post(&mut conn, "d", "https://sub.scam.example/d").await;
+
+        let purged = BlockedLink::create(&mut conn, "scam.example", None)
+            .await
+            .unwrap();
+
+        assert_eq!(3, purged);
+        let uris = sqlx::query_scalar!("select uri from posts")
+            .fetch_all(&mut conn)
+            .await
+            .unwrap();
+        assert_eq!(vec![Some("c".to_string())], uris);
+    }
+
+    #[tokio::test]
+    async fn test_any_blocked_matches_domains() {
+        let mut conn = conn().await;
+
+        BlockedLink::create(&mut conn, "scam.example", None)
+            .await
+            .unwrap();
+
+        assert!(BlockedLink::any_blocked(
+            &mut conn,
+            &["https://open.spotify.com/track/a", "https://scam.example/b"]
+        )
+        .await
+        .unwrap());
+        assert!(
+            !BlockedLink::any_blocked(&mut conn, &["https://open.spotify.com/track/a"])
+                .await
+                .unwrap()
+        );
+    }
+}
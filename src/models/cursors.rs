@@ -0,0 +1,41 @@
+use anyhow::{Context, Result};
+use chrono::Utc;
+use sqlx::{Executor, Sqlite};
+
+/// The sequence number of the last firehose event we processed, so we can resume from it
+pub struct Cursor;
+
+impl Cursor {
+    pub async fn get<'e, E>(executor: E, service: &str) -> Result<Option<i64>>
+    where
+        E: Executor<'e, Database = Sqlite>,
+    {
+        let seq = sqlx::query_scalar!(
+            "select seq from firehose_cursors where service = ?",
+            service
+        )
+        .fetch_optional(executor)
+        .await
+        .with_context(|| format!("failed to get cursor for {service}"))?;
+
+        Ok(seq)
+    }
+
+    pub async fn set<'e, E>(executor: E, service: &str, seq: i64) -> Result<()>
+    where
+        E: Executor<'e, Database = Sqlite>,
+    {
+        let now = Utc::now();
+        sqlx::query!(
+            "insert into firehose_cursors (service, seq, updated_at) values (?, ?, ?) on conflict(service) do update set seq = excluded.seq, updated_at = excluded.updated_at",
+            service,
+            seq,
+            now,
+        )
+        .execute(executor)
+        .await
+        .with_context(|| format!("failed to set cursor for {service}"))?;
+
+        Ok(())
+    }
+}
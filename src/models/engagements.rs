@@ -0,0 +1,55 @@
+use anyhow::{Context, Result};
+use sqlx::{Executor, Sqlite};
+
+/// A like or repost of an indexed post, kept so that deleting it can be taken back off the
+/// post's engagement
+pub struct Engagement;
+
+impl Engagement {
+    /// Records the like or repost at `uri` of the post at `subject`. Returns whether it's new,
+    /// which it isn't if it was already recorded or we don't have the post
+    pub async fn create<'e, E>(executor: E, uri: &str, subject: &str) -> Result<bool>
+    where
+        E: Executor<'e, Database = Sqlite>,
+    {
+        let result = sqlx::query!(
+            "insert or ignore into engagements (uri, subject) select ?, uri from posts where uri = ?",
+            uri,
+            subject,
+        )
+        .execute(executor)
+        .await
+        .with_context(|| format!("failed to create engagement {uri}"))?;
+
+        Ok(result.rows_affected() == 1)
+    }
+
+    pub async fn uris<'e, E>(executor: E) -> Result<Vec<String>>
+    where
+        E: Executor<'e, Database = Sqlite>,
+    {
+        let uris = sqlx::query_scalar!("select uri from engagements")
+            .fetch_all(executor)
+            .await
+            .context("failed to get engagement uris")?;
+
+        Ok(uris)
+    }
+
+    /// Deletes the like or repost at `uri`, returning the uri of the post it was for, if it was
+    /// recorded
+    pub async fn delete<'e, E>(executor: E, uri: &str) -> Result<Option<String>>
+    where
+        E: Executor<'e, Database = Sqlite>,
+    {
+        let subject = sqlx::query_scalar!(
+            "delete from engagements where uri = ? returning subject",
+            uri
+        )
+        .fetch_optional(executor)
+        .await
+        .with_context(|| format!("failed to delete engagement {uri}"))?;
+
+        Ok(subject)
+    }
+}
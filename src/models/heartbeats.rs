@@ -0,0 +1,61 @@
+use anyhow::{Context, Result};
+use chrono::{DateTime, Utc};
+use sqlx::{Executor, Sqlite};
+
+/// What the process ingesting a relay was up to when it last checked in
+#[derive(Debug, Clone, PartialEq)]
+pub struct Heartbeat {
+    pub relay: String,
+    pub connected: bool,
+    /// The seq of the latest commit that was handled, or the one ingest resumed from
+    pub cursor: Option<i64>,
+    /// When ingest started
+    pub started_at: DateTime<Utc>,
+    pub last_commit_at: Option<DateTime<Utc>>,
+    pub updated_at: DateTime<Utc>,
+}
+
+impl Heartbeat {
+    pub async fn get<'e, E>(executor: E, relay: &str) -> Result<Option<Heartbeat>>
+    where
+        E: Executor<'e, Database = Sqlite>,
+    {
+        let heartbeat = sqlx::query_as!(
+            Heartbeat,
+            r#"select
+                relay,
+                connected,
+                seq as cursor,
+                started_at as "started_at: DateTime<Utc>",
+                last_commit_at as "last_commit_at: DateTime<Utc>",
+                updated_at as "updated_at: DateTime<Utc>"
+            from ingest_heartbeats where relay = ?"#,
+            relay
+        )
+        .fetch_optional(executor)
+        .await
+        .with_context(|| format!("failed to get heartbeat for {relay}"))?;
+
+        Ok(heartbeat)
+    }
+
+    pub async fn set<'e, E>(executor: E, heartbeat: &Heartbeat) -> Result<()>
+    where
+        E: Executor<'e, Database = Sqlite>,
+    {
+        sqlx::query!(
+            "insert into ingest_heartbeats (relay, connected, seq, started_at, last_commit_at, updated_at) values (?, ?, ?, ?, ?, ?) on conflict(relay) do update set connected = excluded.connected, seq = excluded.seq, started_at = excluded.started_at, last_commit_at = excluded.last_commit_at, updated_at = excluded.updated_at",
+            heartbeat.relay,
+            heartbeat.connected,
+            heartbeat.cursor,
+            heartbeat.started_at,
+            heartbeat.last_commit_at,
+            heartbeat.updated_at,
+        )
+        .execute(executor)
+        .await
+        .with_context(|| format!("failed to set heartbeat for {}", heartbeat.relay))?;
+
+        Ok(())
+    }
+}
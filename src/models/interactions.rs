@@ -0,0 +1,86 @@
+use anyhow::{Context, Result};
+use chrono::Utc;
+use sqlx::{Executor, Sqlite};
+
+/// An interaction a viewer had with a feed item, as sent by the app to `sendInteractions`
+pub struct NewInteraction<'a> {
+    /// The DID of the viewer
+    pub requester: &'a str,
+    /// The uri of the post that was interacted with
+    pub item: &'a str,
+    /// The interaction. Eg: `app.bsky.feed.defs#requestLess`
+    pub event: &'a str,
+    /// The feed context we attached to the item
+    pub feed_context: Option<&'a str>,
+}
+
+pub struct Interaction;
+
+impl Interaction {
+    pub async fn create<'e, E>(executor: E, interaction: &NewInteraction<'_>) -> Result<()>
+    where
+        E: Executor<'e, Database = Sqlite>,
+    {
+        let now = Utc::now();
+        sqlx::query!(
+            "insert into interactions (requester, item, event, feed_context, created_at) values (?, ?, ?, ?, ?)",
+            interaction.requester,
+            interaction.item,
+            interaction.event,
+            interaction.feed_context,
+            now,
+        )
+        .execute(executor)
+        .await
+        .context("failed to create interaction")?;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use sqlx::{Connection, SqliteConnection};
+
+    async fn conn() -> SqliteConnection {
+        let mut conn = SqliteConnection::connect("sqlite::memory:").await.unwrap();
+
+        sqlx::migrate!("./migrations/sqlite")
+            .run(&mut conn)
+            .await
+            .unwrap();
+
+        conn
+    }
+
+    #[tokio::test]
+    async fn test_create() {
+        let mut conn = conn().await;
+
+        Interaction::create(
+            &mut conn,
+            &NewInteraction {
+                requester: "did:plc:viewer",
+                item: "at://did:plc:author/app.bsky.feed.post/a",
+                event: "app.bsky.feed.defs#interactionSeen",
+                feed_context: Some("music:spotify:track:recent"),
+            },
+        )
+        .await
+        .unwrap();
+
+        let row = sqlx::query!("select requester, event, feed_context from interactions")
+            .fetch_one(&mut conn)
+            .await
+            .unwrap();
+
+        assert_eq!("did:plc:viewer", row.requester);
+        assert_eq!("app.bsky.feed.defs#interactionSeen", row.event);
+        assert_eq!(
+            Some("music:spotify:track:recent".to_string()),
+            row.feed_context
+        );
+    }
+}
@@ -0,0 +1,118 @@
+use anyhow::{Context, Result};
+use chrono::{DateTime, Utc};
+use sqlx::{Executor, Sqlite};
+
+#[allow(dead_code)]
+pub struct Label {
+    /// The DID of the labeler that created this label
+    pub src: String,
+    /// The subject of the label, either a record uri or an account DID
+    pub uri: String,
+    /// The label value. Eg: `spam`
+    pub val: String,
+    /// The time the label was created at
+    pub cts: DateTime<Utc>,
+    /// The time the label expires at, if any
+    pub exp: Option<DateTime<Utc>>,
+}
+
+impl Label {
+    pub async fn create<'e, E>(executor: E, label: &Label) -> Result<()>
+    where
+        E: Executor<'e, Database = Sqlite>,
+    {
+        sqlx::query!(
+            "insert into labels (src, uri, val, cts, exp) values (?, ?, ?, ?, ?) on conflict(src, uri, val) do update set cts = excluded.cts, exp = excluded.exp",
+            label.src,
+            label.uri,
+            label.val,
+            label.cts,
+            label.exp,
+        )
+        .execute(executor)
+        .await
+        .context("failed to create label")?;
+
+        Ok(())
+    }
+
+    /// Removes a label, used when a labeler negates a previous label
+    pub async fn delete<'e, E>(executor: E, src: &str, uri: &str, val: &str) -> Result<()>
+    where
+        E: Executor<'e, Database = Sqlite>,
+    {
+        sqlx::query!(
+            "delete from labels where src = ? and uri = ? and val = ?",
+            src,
+            uri,
+            val
+        )
+        .execute(executor)
+        .await
+        .with_context(|| format!("failed to delete label {val} on {uri}"))?;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use sqlx::{Connection, SqliteConnection};
+
+    async fn conn() -> SqliteConnection {
+        let mut conn = SqliteConnection::connect("sqlite::memory:").await.unwrap();
+
+        sqlx::migrate!("./migrations/sqlite")
+            .run(&mut conn)
+            .await
+            .unwrap();
+
+        conn
+    }
+
+    fn label(val: &str) -> Label {
+        Label {
+            src: "did:plc:labeler".to_string(),
+            uri: "did:plc:author".to_string(),
+            val: val.to_string(),
+            cts: Utc::now(),
+            exp: None,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_relabelling_doesnt_duplicate() {
+        let mut conn = conn().await;
+
+        Label::create(&mut conn, &label("spam")).await.unwrap();
+        Label::create(&mut conn, &label("spam")).await.unwrap();
+
+        let count = sqlx::query_scalar!("select count(*) from labels")
+            .fetch_one(&mut conn)
+            .await
+            .unwrap();
+
+        assert_eq!(1, count);
+    }
+
+    #[tokio::test]
+    async fn test_delete_only_removes_matching_value() {
+        let mut conn = conn().await;
+
+        Label::create(&mut conn, &label("spam")).await.unwrap();
+        Label::create(&mut conn, &label("porn")).await.unwrap();
+
+        Label::delete(&mut conn, "did:plc:labeler", "did:plc:author", "spam")
+            .await
+            .unwrap();
+
+        let vals = sqlx::query_scalar!("select val from labels")
+            .fetch_all(&mut conn)
+            .await
+            .unwrap();
+
+        assert_eq!(vec!["porn".to_string()], vals);
+    }
+}
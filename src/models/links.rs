@@ -1,8 +1,11 @@
 use anyhow::{Context, Result};
-use chrono::Utc;
+use chrono::{DateTime, Utc};
 use sqlx::{Executor, Sqlite};
 
-use crate::link_finder::{FoundLink, Kind, Site};
+use crate::{
+    link_finder::{FoundLink, Kind, Site},
+    maintenance::StoredLink,
+};
 
 #[allow(dead_code)]
 pub struct Link {
@@ -32,6 +35,140 @@ impl Link {
 
         Ok(())
     }
+
+    /// Deletes links created before `cutoff` that no post contains anymore, returning how many
+    /// were deleted
+    pub async fn delete_orphaned<'e, E>(executor: E, cutoff: DateTime<Utc>) -> Result<u64>
+    where
+        E: Executor<'e, Database = Sqlite>,
+    {
+        let deleted = sqlx::query!(
+            "delete from links where created_at < ? and url not in (select url from post_links)",
+            cutoff
+        )
+        .execute(executor)
+        .await
+        .context("failed to delete orphaned links")?
+        .rows_affected();
+
+        Ok(deleted)
+    }
+
+    pub async fn all<'e, E>(executor: E) -> Result<Vec<StoredLink>>
+    where
+        E: Executor<'e, Database = Sqlite>,
+    {
+        let links = sqlx::query_as!(
+            StoredLink,
+            r#"select url as "url!", kind as "kind: Kind", site as "site: Site" from links"#
+        )
+        .fetch_all(executor)
+        .await
+        .context("failed to get links")?;
+
+        Ok(links)
+    }
+
+    pub async fn reclassify<'e, E>(executor: E, url: &str, kind: &Kind, site: &Site) -> Result<()>
+    where
+        E: Executor<'e, Database = Sqlite>,
+    {
+        sqlx::query!(
+            "update links set kind = ?, site = ? where url = ?",
+            kind,
+            site,
+            url
+        )
+        .execute(executor)
+        .await
+        .context("failed to reclassify link")?;
+
+        Ok(())
+    }
+
+    /// Deletes the posts that contain `url` and no other link, returning how many were deleted
+    pub async fn delete_posts_only_with<'e, E>(executor: E, url: &str) -> Result<u64>
+    where
+        E: Executor<'e, Database = Sqlite>,
+    {
+        let deleted = sqlx::query!(
+            r#"
+            delete from posts
+            where uri in (select post_uri from post_links where url = ?1)
+            and not exists (
+                select 1 from post_links as other
+                where other.post_uri = posts.uri and other.url != ?1
+            )
+            "#,
+            url
+        )
+        .execute(executor)
+        .await
+        .context("failed to delete posts with link")?
+        .rows_affected();
+
+        Ok(deleted)
+    }
+
+    pub async fn remove_from_posts<'e, E>(executor: E, url: &str) -> Result<()>
+    where
+        E: Executor<'e, Database = Sqlite>,
+    {
+        sqlx::query!("delete from post_links where url = ?", url)
+            .execute(executor)
+            .await
+            .context("failed to remove link from posts")?;
+
+        Ok(())
+    }
+
+    /// Deletes the link, returning how many were deleted
+    pub async fn delete<'e, E>(executor: E, url: &str) -> Result<u64>
+    where
+        E: Executor<'e, Database = Sqlite>,
+    {
+        let deleted = sqlx::query!("delete from links where url = ?", url)
+            .execute(executor)
+            .await
+            .context("failed to delete link")?
+            .rows_affected();
+
+        Ok(deleted)
+    }
+
+    /// Records that the post with `post_uri` contains `url`, which doesn't have to be a music link
+    pub async fn add_url_to_post<'e, E>(executor: E, post_uri: &str, url: &str) -> Result<()>
+    where
+        E: Executor<'e, Database = Sqlite>,
+    {
+        sqlx::query!(
+            "insert into post_urls (post_uri, url) values (?, ?) on conflict do nothing",
+            post_uri,
+            url,
+        )
+        .execute(executor)
+        .await
+        .context("failed to add url to post")?;
+
+        Ok(())
+    }
+
+    /// Records that the post with `post_uri` contains `link`
+    pub async fn add_to_post<'e, E>(executor: E, post_uri: &str, link: &FoundLink<'_>) -> Result<()>
+    where
+        E: Executor<'e, Database = Sqlite>,
+    {
+        sqlx::query!(
+            "insert into post_links (post_uri, url) values (?, ?) on conflict do nothing",
+            post_uri,
+            link.url,
+        )
+        .execute(executor)
+        .await
+        .context("failed to add link to post")?;
+
+        Ok(())
+    }
 }
 
 #[cfg(test)]
@@ -43,7 +180,10 @@ mod tests {
     async fn conn() -> SqliteConnection {
         let mut conn = SqliteConnection::connect("sqlite::memory:").await.unwrap();
 
-        sqlx::migrate!("./migrations").run(&mut conn).await.unwrap();
+        sqlx::migrate!("./migrations/sqlite")
+            .run(&mut conn)
+            .await
+            .unwrap();
 
         conn
     }
@@ -1,2 +1,10 @@
+pub mod blocked_authors;
+pub mod blocked_links;
+pub mod cursors;
+pub mod engagements;
+pub mod heartbeats;
+pub mod interactions;
+pub mod labels;
 pub mod links;
 pub mod posts;
+pub mod preferences;
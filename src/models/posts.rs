@@ -1,28 +1,114 @@
+use std::sync::atomic::{AtomicI64, Ordering};
+
 use anyhow::{Context, Result};
 use chrono::{DateTime, Utc};
 use sqlx::{Executor, Sqlite};
 
+use crate::{
+    algos::ReplyPolicy,
+    link_finder::{Kind, Site},
+    maintenance::Stats,
+};
+
+/// The `indexed_at` of a post being created now. It's in whole microseconds, like the
+/// chronological cursor, and always later than the last one, so posts written in the same batch
+/// never share a timestamp that a cursor would skip the rest of them at
+pub fn next_indexed_at() -> DateTime<Utc> {
+    static LAST: AtomicI64 = AtomicI64::new(0);
+
+    let now = Utc::now().timestamp_micros();
+    let last = LAST
+        .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |last| {
+            Some(now.max(last + 1))
+        })
+        .expect("the update always succeeds");
+
+    DateTime::from_timestamp_micros(now.max(last + 1)).expect("now is a valid time")
+}
+
 #[allow(dead_code)]
 pub struct Post {
     /// The full uri. Eg: `at://did:plc:asdfghjkl/app.bsky.feed.post/qwertyuiop`
     pub uri: String,
     /// The record CID
     pub cid: String,
+    /// The author's DID. Eg: `did:plc:asdfghjkl`
+    pub author: String,
     /// The time this post was indexed at
     pub indexed_at: DateTime<Utc>,
+    /// The uri of the root of the thread, if this post is a reply
+    pub reply_root: Option<String>,
+    /// The uri of the post this is replying to, if this post is a reply
+    pub reply_parent: Option<String>,
+    /// The uri of the quoted record, if this post is a quote
+    pub quote: Option<String>,
+    /// The site of the post's first music link
+    pub site: Option<Site>,
+    /// The kind of the post's first music link
+    pub kind: Option<Kind>,
+    /// How many likes and reposts the post has gotten since it was indexed
+    pub engagement: i64,
+}
+
+pub struct NewPost<'a> {
+    pub uri: &'a str,
+    pub cid: String,
+    pub author: &'a str,
+    pub reply_root: Option<&'a str>,
+    pub reply_parent: Option<&'a str>,
+    pub quote: Option<&'a str>,
+    /// See [`crate::spam::fingerprint`]
+    pub fingerprint: Option<&'a str>,
+    /// Lowercased language tags, like `en` or `pt-br`
+    pub langs: &'a [String],
+}
+
+/// Which posts to get. The empty lists don't filter anything
+#[derive(Debug, Clone, Copy, Default)]
+pub struct PostFilter<'a> {
+    pub reply_policy: ReplyPolicy,
+    /// Posts carrying any of these labels, on either the post or its author, are left out
+    pub excluded_labels: &'a [String],
+    /// Only posts with a link to one of these sites
+    pub sites: &'a [Site],
+    /// Only posts with a link of one of these kinds
+    pub kinds: &'a [Kind],
+    /// Only posts in one of these languages. `en` also matches `en-us`
+    pub languages: &'a [String],
+    pub min_engagement: i64,
+    pub excluded_authors: &'a [String],
+}
+
+/// How many posts an author has had indexed recently
+pub struct AuthorStats {
+    /// Posts indexed since the start of the rate limit window
+    pub recent: i64,
+    /// Posts with the same fingerprint indexed since the start of the duplicate window
+    pub duplicates: i64,
 }
 
 impl Post {
-    pub async fn create<'e, E>(executor: E, uri: &str, cid: String) -> Result<()>
+    pub async fn create<'e, E>(executor: E, post: &NewPost<'_>) -> Result<()>
     where
         E: Executor<'e, Database = Sqlite>,
     {
-        let now = Utc::now();
+        let now = next_indexed_at();
+        let langs = if post.langs.is_empty() {
+            None
+        } else {
+            Some(serde_json::to_string(post.langs)?)
+        };
         sqlx::query!(
-            "insert into posts (uri, cid, indexed_at) values (?, ?, ?) on conflict(uri) do nothing",
-            uri,
-            cid,
+            "insert into posts (uri, cid, author, indexed_at, reply_root, reply_parent, quote, fingerprint, langs) values (?, ?, ?, ?, ?, ?, ?, ?, ?) on conflict(uri) do nothing",
+            post.uri,
+            post.cid,
+            post.author,
             now,
+            post.reply_root,
+            post.reply_parent,
+            post.quote,
+            post.fingerprint,
+            langs,
         )
         .execute(executor)
         .await
@@ -43,41 +129,190 @@ impl Post {
         Ok(())
     }
 
-    pub async fn get_all<'e, E>(executor: E, limit: u8) -> Result<Vec<Post>>
+    pub async fn uris<'e, E>(executor: E) -> Result<Vec<String>>
     where
         E: Executor<'e, Database = Sqlite>,
     {
-        let posts = sqlx::query!(
-            "select * from posts order by indexed_at desc, cid desc limit ?",
-            limit
+        let uris = sqlx::query_scalar!(r#"select uri as "uri!" from posts"#)
+            .fetch_all(executor)
+            .await
+            .context("failed to get post uris")?;
+
+        Ok(uris)
+    }
+
+    /// Adds `change` likes and reposts to the post at `uri`. Does nothing if we don't have it
+    pub async fn add_engagement<'e, E>(executor: E, uri: &str, change: i64) -> Result<()>
+    where
+        E: Executor<'e, Database = Sqlite>,
+    {
+        sqlx::query!(
+            "update posts set engagement = engagement + ? where uri = ?",
+            change,
+            uri
         )
-        .fetch_all(executor)
-        .await?
-        .into_iter()
-        .filter_map(|post| {
-            Some(Post {
-                uri: post.uri?,
-                cid: post.cid,
-                indexed_at: post.indexed_at.and_utc(),
-            })
+        .execute(executor)
+        .await
+        .with_context(|| format!("failed to add engagement to {uri}"))?;
+
+        Ok(())
+    }
+
+    /// Deletes every post indexed before `cutoff`, returning how many were deleted
+    pub async fn delete_older_than<'e, E>(executor: E, cutoff: DateTime<Utc>) -> Result<u64>
+    where
+        E: Executor<'e, Database = Sqlite>,
+    {
+        let deleted = sqlx::query!("delete from posts where indexed_at < ?", cutoff)
+            .execute(executor)
+            .await
+            .context("failed to delete old posts")?
+            .rows_affected();
+
+        Ok(deleted)
+    }
+
+    /// How many posts, authors, and links there are
+    pub async fn stats<'e, E>(executor: E) -> Result<Stats>
+    where
+        E: Executor<'e, Database = Sqlite>,
+    {
+        let stats = sqlx::query!(
+            r#"
+            select
+                count(*) as "posts!: i64",
+                count(distinct author) as "authors!: i64",
+                (select count(*) from links) as "links!: i64",
+                min(indexed_at) as "oldest: chrono::NaiveDateTime",
+                max(indexed_at) as "newest: chrono::NaiveDateTime"
+            from posts
+            "#
+        )
+        .fetch_one(executor)
+        .await
+        .context("failed to get stats")?;
+
+        Ok(Stats {
+            posts: stats.posts,
+            authors: stats.authors,
+            links: stats.links,
+            oldest_post: stats.oldest.map(|oldest| oldest.and_utc()),
+            newest_post: stats.newest.map(|newest| newest.and_utc()),
         })
-        .collect::<Vec<_>>();
+    }
 
-        Ok(posts)
+    pub async fn author_stats<'e, E>(
+        executor: E,
+        author: &str,
+        fingerprint: &str,
+        rate_limit_since: DateTime<Utc>,
+        duplicate_since: DateTime<Utc>,
+    ) -> Result<AuthorStats>
+    where
+        E: Executor<'e, Database = Sqlite>,
+    {
+        let stats = sqlx::query!(
+            r#"
+            select
+                count(*) filter (where indexed_at > ?2) as "recent!: i64",
+                count(*) filter (where indexed_at > ?3 and fingerprint = ?4) as "duplicates!: i64"
+            from posts
+            where author = ?1 and indexed_at > min(?2, ?3)
+            "#,
+            author,
+            rate_limit_since,
+            duplicate_since,
+            fingerprint,
+        )
+        .fetch_one(executor)
+        .await
+        .with_context(|| format!("failed to get stats for {author}"))?;
+
+        Ok(AuthorStats {
+            recent: stats.recent,
+            duplicates: stats.duplicates,
+        })
     }
 
-    pub async fn get_all_where_time_under<'e, E>(
+    /// Gets the most recent posts allowed by `filter`, skipping posts that are on the blocklist.
+    ///
+    /// If `before` is set, only posts indexed before that time are returned
+    pub async fn get_all<'e, E>(
         executor: E,
         limit: u8,
-        time: DateTime<Utc>,
+        before: Option<DateTime<Utc>>,
+        filter: &PostFilter<'_>,
     ) -> Result<Vec<Post>>
     where
         E: Executor<'e, Database = Sqlite>,
     {
+        let reply_policy = filter.reply_policy;
+        let excluded_labels = serde_json::to_string(filter.excluded_labels)?;
+        let sites =
+            serde_json::to_string(&filter.sites.iter().map(Site::as_str).collect::<Vec<_>>())?;
+        let kinds =
+            serde_json::to_string(&filter.kinds.iter().map(Kind::as_str).collect::<Vec<_>>())?;
+        let languages = serde_json::to_string(filter.languages)?;
+        let excluded_authors = serde_json::to_string(filter.excluded_authors)?;
+        let now = Utc::now();
         let posts = sqlx::query!(
-            "select * from posts where indexed_at < ? order by indexed_at desc, cid desc limit ?",
-            time,
-            limit
+            r#"
+            select
+                uri, cid, author, indexed_at, reply_root, reply_parent, quote, engagement,
+                links.site as "site?: Site", links.kind as "kind?: Kind"
+            from posts
+            left join links on links.url = (
+                select url from post_links where post_links.post_uri = posts.uri limit 1
+            )
+            where (?1 is null or indexed_at < ?1)
+            and (
+                ?2 = 'all'
+                or (?2 = 'exclude_replies' and reply_root is null)
+                or (?2 = 'top_level' and reply_root is null and quote is null)
+                or (?2 = 'music_root' and (reply_root is null or reply_root in (select uri from posts)))
+            )
+            and not exists (
+                select 1 from labels
+                where labels.uri in (posts.uri, posts.author, 'at://' || posts.author)
+                and labels.val in (select value from json_each(?4))
+                and (labels.exp is null or labels.exp > ?5)
+            )
+            and author not in (select did from blocked_authors)
+            and not exists (
+                select 1 from blocked_post_links where blocked_post_links.post_uri = posts.uri
+            )
+            and (
+                (json_array_length(?6) = 0 and json_array_length(?7) = 0)
+                or exists (
+                    select 1 from post_links
+                    join links as filtered on filtered.url = post_links.url
+                    where post_links.post_uri = posts.uri
+                    and (json_array_length(?6) = 0 or filtered.site in (select value from json_each(?6)))
+                    and (json_array_length(?7) = 0 or filtered.kind in (select value from json_each(?7)))
+                )
+            )
+            and (
+                json_array_length(?8) = 0
+                or exists (
+                    select 1 from json_each(posts.langs) as lang, json_each(?8) as wanted
+                    where lang.value = wanted.value or lang.value like wanted.value || '-%'
+                )
+            )
+            and engagement >= ?9
+            and author not in (select value from json_each(?10))
+            order by indexed_at desc, cid desc
+            limit ?3
+            "#,
+            before,
+            reply_policy,
+            limit,
+            excluded_labels,
+            now,
+            sites,
+            kinds,
+            languages,
+            filter.min_engagement,
+            excluded_authors,
         )
         .fetch_all(executor)
         .await?
@@ -86,7 +321,14 @@ impl Post {
             Some(Post {
                 uri: post.uri?,
                 cid: post.cid,
+                author: post.author,
                 indexed_at: post.indexed_at.and_utc(),
+                reply_root: post.reply_root,
+                reply_parent: post.reply_parent,
+                quote: post.quote,
+                site: post.site,
+                kind: post.kind,
+                engagement: post.engagement,
             })
         })
         .collect::<Vec<_>>();
@@ -94,3 +336,181 @@ impl Post {
         Ok(posts)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::models::labels::Label;
+    use sqlx::{Connection, SqliteConnection};
+
+    async fn conn() -> SqliteConnection {
+        let mut conn = SqliteConnection::connect("sqlite::memory:").await.unwrap();
+
+        sqlx::migrate!("./migrations/sqlite")
+            .run(&mut conn)
+            .await
+            .unwrap();
+
+        conn
+    }
+
+    async fn create(
+        conn: &mut SqliteConnection,
+        uri: &str,
+        reply_root: Option<&str>,
+        quote: Option<&str>,
+    ) {
+        Post::create(
+            conn,
+            &NewPost {
+                uri,
+                cid: format!("cid-{uri}"),
+                author: "did:plc:author",
+                reply_root,
+                reply_parent: reply_root,
+                quote,
+                fingerprint: None,
+                langs: &[],
+            },
+        )
+        .await
+        .unwrap();
+    }
+
+    /// Creates a top level post, a quote, a reply to a post in the db, and a reply to a post that isn't
+    async fn seed(conn: &mut SqliteConnection) {
+        create(conn, "top", None, None).await;
+        create(conn, "quote", None, Some("elsewhere")).await;
+        create(conn, "music_reply", Some("top"), None).await;
+        create(conn, "other_reply", Some("elsewhere"), None).await;
+    }
+
+    async fn uris(conn: &mut SqliteConnection, reply_policy: ReplyPolicy) -> Vec<String> {
+        let filter = PostFilter {
+            reply_policy,
+            excluded_labels: &["spam".to_string()],
+            ..Default::default()
+        };
+        let mut uris = Post::get_all(conn, 20, None, &filter)
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|post| post.uri)
+            .collect::<Vec<_>>();
+        uris.sort();
+        uris
+    }
+
+    #[tokio::test]
+    async fn test_all_includes_everything() {
+        let mut conn = conn().await;
+        seed(&mut conn).await;
+
+        assert_eq!(
+            vec!["music_reply", "other_reply", "quote", "top"],
+            uris(&mut conn, ReplyPolicy::All).await
+        );
+    }
+
+    #[tokio::test]
+    async fn test_exclude_replies() {
+        let mut conn = conn().await;
+        seed(&mut conn).await;
+
+        assert_eq!(
+            vec!["quote", "top"],
+            uris(&mut conn, ReplyPolicy::ExcludeReplies).await
+        );
+    }
+
+    #[tokio::test]
+    async fn test_top_level_excludes_quotes() {
+        let mut conn = conn().await;
+        seed(&mut conn).await;
+
+        assert_eq!(vec!["top"], uris(&mut conn, ReplyPolicy::TopLevel).await);
+    }
+
+    #[tokio::test]
+    async fn test_music_root_only_keeps_replies_to_music_posts() {
+        let mut conn = conn().await;
+        seed(&mut conn).await;
+
+        assert_eq!(
+            vec!["music_reply", "quote", "top"],
+            uris(&mut conn, ReplyPolicy::MusicRoot).await
+        );
+    }
+
+    async fn label(conn: &mut SqliteConnection, uri: &str, val: &str) {
+        Label::create(
+            conn,
+            &Label {
+                src: "did:plc:labeler".to_string(),
+                uri: uri.to_string(),
+                val: val.to_string(),
+                cts: Utc::now(),
+                exp: None,
+            },
+        )
+        .await
+        .unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_excluded_post_label_hides_post() {
+        let mut conn = conn().await;
+        seed(&mut conn).await;
+        label(&mut conn, "quote", "spam").await;
+        label(&mut conn, "top", "not-excluded").await;
+
+        assert_eq!(
+            vec!["music_reply", "other_reply", "top"],
+            uris(&mut conn, ReplyPolicy::All).await
+        );
+    }
+
+    #[tokio::test]
+    async fn test_excluded_author_label_hides_all_their_posts() {
+        let mut conn = conn().await;
+        seed(&mut conn).await;
+        label(&mut conn, "did:plc:author", "spam").await;
+
+        assert!(uris(&mut conn, ReplyPolicy::All).await.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_blocklist_is_enforced_at_query_time() {
+        let mut conn = conn().await;
+        seed(&mut conn).await;
+        create(&mut conn, "blocked_link", None, None).await;
+        sqlx::query!(
+            "insert into post_urls (post_uri, url) values ('blocked_link', 'https://scam.example/a')"
+        )
+        .execute(&mut conn)
+        .await
+        .unwrap();
+        // inserted directly, so that the existing posts aren't purged
+        sqlx::query!(
+            "insert into blocked_links (url, created_at) values ('scam.example', '2024-11-22')"
+        )
+        .execute(&mut conn)
+        .await
+        .unwrap();
+
+        assert_eq!(
+            vec!["music_reply", "other_reply", "quote", "top"],
+            uris(&mut conn, ReplyPolicy::All).await
+        );
+
+        sqlx::query!(
+            "insert into blocked_authors (did, created_at) values ('did:plc:author', '2024-11-22')"
+        )
+        .execute(&mut conn)
+        .await
+        .unwrap();
+
+        assert!(uris(&mut conn, ReplyPolicy::All).await.is_empty());
+    }
+}
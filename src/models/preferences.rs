@@ -0,0 +1,190 @@
+use std::time::Duration;
+
+use anyhow::{Context, Result};
+use chrono::{DateTime, Utc};
+use sqlx::{Executor, Sqlite, SqliteConnection};
+
+/// What a viewer preference is about
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, sqlx::Type)]
+#[sqlx(rename_all = "lowercase")]
+pub enum Dimension {
+    /// An author's DID
+    Author,
+    /// A link's site. Eg: `spotify`
+    Site,
+    /// A link's kind. Eg: `album`
+    Kind,
+}
+
+impl Dimension {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Dimension::Author => "author",
+            Dimension::Site => "site",
+            Dimension::Kind => "kind",
+        }
+    }
+}
+
+impl std::str::FromStr for Dimension {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "author" => Ok(Dimension::Author),
+            "site" => Ok(Dimension::Site),
+            "kind" => Ok(Dimension::Kind),
+            _ => Err(anyhow::anyhow!("unknown dimension {s}")),
+        }
+    }
+}
+
+/// How much a viewer wants to see more or less of something. Positive scores mean more
+pub struct Preference {
+    pub dimension: Dimension,
+    pub value: String,
+    pub score: f64,
+    pub updated_at: DateTime<Utc>,
+}
+
+impl Preference {
+    /// The score, halved for every `half_life` that passed between when it was last updated and `now`
+    pub fn decayed_score(&self, now: DateTime<Utc>, half_life: Duration) -> f64 {
+        decay(self.score, now - self.updated_at, half_life)
+    }
+
+    pub async fn get_all_for_viewer<'e, E>(executor: E, viewer: &str) -> Result<Vec<Preference>>
+    where
+        E: Executor<'e, Database = Sqlite>,
+    {
+        let preferences = sqlx::query!(
+            r#"select dimension as "dimension: Dimension", value, score, updated_at from viewer_preferences where viewer = ?"#,
+            viewer
+        )
+        .fetch_all(executor)
+        .await
+        .with_context(|| format!("failed to get preferences for {viewer}"))?
+        .into_iter()
+        .map(|preference| Preference {
+            dimension: preference.dimension,
+            value: preference.value,
+            score: preference.score,
+            updated_at: preference.updated_at.and_utc(),
+        })
+        .collect();
+
+        Ok(preferences)
+    }
+
+    /// Decays the viewer's current score up to `now`, and then adds `delta` to it
+    pub async fn adjust(
+        conn: &mut SqliteConnection,
+        viewer: &str,
+        dimension: Dimension,
+        value: &str,
+        delta: f64,
+        now: DateTime<Utc>,
+        half_life: Duration,
+    ) -> Result<()> {
+        let current = sqlx::query!(
+            "select score, updated_at from viewer_preferences where viewer = ? and dimension = ? and value = ?",
+            viewer,
+            dimension,
+            value
+        )
+        .fetch_optional(&mut *conn)
+        .await?;
+
+        let score = current
+            .map(|current| decay(current.score, now - current.updated_at.and_utc(), half_life))
+            .unwrap_or(0.0)
+            + delta;
+
+        sqlx::query!(
+            "insert into viewer_preferences (viewer, dimension, value, score, updated_at) values (?, ?, ?, ?, ?) on conflict(viewer, dimension, value) do update set score = excluded.score, updated_at = excluded.updated_at",
+            viewer,
+            dimension,
+            value,
+            score,
+            now,
+        )
+        .execute(&mut *conn)
+        .await
+        .with_context(|| format!("failed to adjust preference for {viewer}"))?;
+
+        Ok(())
+    }
+}
+
+/// `score`, halved for every `half_life` in `elapsed`
+pub fn decay(score: f64, elapsed: chrono::Duration, half_life: Duration) -> f64 {
+    let elapsed = elapsed.to_std().unwrap_or_default();
+    score * 0.5f64.powf(elapsed.as_secs_f64() / half_life.as_secs_f64())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use sqlx::Connection;
+
+    async fn conn() -> SqliteConnection {
+        let mut conn = SqliteConnection::connect("sqlite::memory:").await.unwrap();
+
+        sqlx::migrate!("./migrations/sqlite")
+            .run(&mut conn)
+            .await
+            .unwrap();
+
+        conn
+    }
+
+    const DAY: Duration = Duration::from_secs(24 * 60 * 60);
+
+    #[test]
+    fn test_decay_halves_every_half_life() {
+        assert_eq!(4.0, decay(4.0, chrono::Duration::zero(), DAY));
+        assert_eq!(2.0, decay(4.0, chrono::Duration::days(1), DAY));
+        assert_eq!(1.0, decay(4.0, chrono::Duration::days(2), DAY));
+    }
+
+    #[tokio::test]
+    async fn test_adjust_decays_before_adding() {
+        let mut conn = conn().await;
+        let now = DateTime::from_timestamp(1_700_000_000, 0).unwrap();
+
+        Preference::adjust(
+            &mut conn,
+            "viewer",
+            Dimension::Site,
+            "spotify",
+            -4.0,
+            now,
+            DAY,
+        )
+        .await
+        .unwrap();
+        Preference::adjust(
+            &mut conn,
+            "viewer",
+            Dimension::Site,
+            "spotify",
+            1.0,
+            now + chrono::Duration::days(1),
+            DAY,
+        )
+        .await
+        .unwrap();
+
+        let preferences = Preference::get_all_for_viewer(&mut conn, "viewer")
+            .await
+            .unwrap();
+
+        assert_eq!(1, preferences.len());
+        assert_eq!(-1.0, preferences[0].score);
+        assert_eq!(
+            -0.5,
+            preferences[0].decayed_score(now + chrono::Duration::days(2), DAY)
+        );
+    }
+}
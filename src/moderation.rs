@@ -0,0 +1,76 @@
+use anyhow::{Context, Result};
+use atrium_api::com::atproto::label::subscribe_labels::Labels;
+use tokio_util::sync::CancellationToken;
+
+use crate::{
+    firehose::{self, Backoff, LabelsHandler},
+    models::labels::Label,
+    storage::{Database, Storage},
+};
+
+/// Subscribes to a labeler's label stream, storing every label it emits.
+///
+/// The seq of the last stored labels is kept in the cursors table under the labeler's name, so
+/// a reconnect, or a restart, resumes where the stream left off. Connections that end or fail
+/// are retried with a backoff, until `shutdown` is cancelled
+pub async fn start_label_ingest(
+    db: Database,
+    labeler: &str,
+    shutdown: CancellationToken,
+) -> Result<()> {
+    let mut backoff = Backoff::default();
+    loop {
+        let cursor = db.cursor(labeler).await?;
+        let store = LabelStore {
+            db: db.clone(),
+            labeler: labeler.to_string(),
+        };
+        match firehose::listen_labels(labeler, store, cursor, shutdown.clone())
+            .await
+            .with_context(|| format!("failed while listening to labeler {labeler}"))
+        {
+            Ok(Some(_)) => backoff.reset(),
+            Ok(None) => {}
+            Err(err) => tracing::warn!(labeler, "{err:?}"),
+        }
+        if shutdown.is_cancelled() {
+            return Ok(());
+        }
+
+        let delay = backoff.next_delay();
+        tracing::info!(labeler, "reconnecting to labeler in {delay:?}");
+        tokio::select! {
+            _ = tokio::time::sleep(delay) => {}
+            _ = shutdown.cancelled() => return Ok(()),
+        }
+    }
+}
+
+struct LabelStore {
+    db: Database,
+    labeler: String,
+}
+
+impl LabelsHandler for LabelStore {
+    async fn handle_labels(&self, labels: &Labels) -> Result<()> {
+        for label in &labels.labels {
+            if label.neg.unwrap_or(false) {
+                self.db
+                    .delete_label(label.src.as_str(), &label.uri, &label.val)
+                    .await?;
+            } else {
+                let label = Label {
+                    src: label.src.to_string(),
+                    uri: label.uri.clone(),
+                    val: label.val.clone(),
+                    cts: label.cts.as_ref().to_utc(),
+                    exp: label.exp.as_ref().map(|exp| exp.as_ref().to_utc()),
+                };
+                self.db.create_label(&label).await?;
+            }
+        }
+        self.db.set_cursor(&self.labeler, labels.seq).await?;
+
+        Ok(())
+    }
+}
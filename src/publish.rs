@@ -0,0 +1,724 @@
+//! Publishing the `app.bsky.feed.generator` records of the configured feeds to the publisher's
+//! repo, so the app can find them
+
+use std::path::Path;
+
+use anyhow::{bail, Context, Result};
+use chrono::{SecondsFormat, Utc};
+use ipld_core::cid::{multihash::Multihash, Cid};
+use reqwest::{header::CONTENT_TYPE, RequestBuilder, Response};
+use serde::Deserialize;
+use serde_json::{json, Value};
+use sha2::{Digest, Sha256};
+
+use crate::{
+    atproto::AtUriBuf,
+    feeds::{FeedDefinition, Feeds},
+};
+
+const COLLECTION: &str = "app.bsky.feed.generator";
+/// The multicodec of raw bytes, which blobs are addressed as
+const RAW: u64 = 0x55;
+/// The multicodec of sha2-256
+const SHA2_256: u64 = 0x12;
+
+#[derive(clap::Args)]
+pub struct Args {
+    /// The handle or DID of the publisher's account
+    #[arg(long, env = "FEEDGEN_PUBLISH_IDENTIFIER")]
+    identifier: String,
+    /// An app password of the publisher's account
+    #[arg(long, env = "FEEDGEN_PUBLISH_PASSWORD", hide_env_values = true)]
+    password: String,
+    /// The PDS the publisher's account lives on
+    #[arg(
+        long,
+        env = "FEEDGEN_PUBLISH_PDS",
+        default_value = "https://bsky.social"
+    )]
+    pds: String,
+    /// Only print what would change
+    #[arg(long)]
+    dry_run: bool,
+}
+
+/// A change to a feed generator record
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Change {
+    Create {
+        rkey: String,
+    },
+    /// `fields` are the record fields that changed
+    Update {
+        rkey: String,
+        fields: Vec<&'static str>,
+    },
+    /// The record points to our service, but its feed isn't configured anymore
+    Delete {
+        rkey: String,
+    },
+}
+
+impl std::fmt::Display for Change {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Change::Create { rkey } => write!(f, "+ {rkey}"),
+            Change::Update { rkey, fields } => write!(f, "~ {rkey} ({})", fields.join(", ")),
+            Change::Delete { rkey } => write!(f, "- {rkey}"),
+        }
+    }
+}
+
+/// Brings the publisher's feed generator records in line with `feeds`, and returns what changed.
+/// With `--dry-run`, only returns what would change
+pub async fn publish(
+    args: &Args,
+    feeds: &Feeds,
+    service_did: &str,
+    publisher_did: &str,
+) -> Result<Vec<Change>> {
+    let pds = Pds::login(&args.pds, &args.identifier, &args.password).await?;
+    if pds.did != publisher_did {
+        bail!(
+            "logged in as {}, but the feeds are published by {publisher_did}",
+            pds.did
+        );
+    }
+
+    let existing = pds.list_records().await?;
+    let avatars = feeds
+        .iter()
+        .map(|feed| feed.avatar.as_deref().map(Avatar::read).transpose())
+        .collect::<Result<Vec<_>>>()?;
+
+    let mut changes = vec![];
+    for (feed, avatar) in feeds.iter().zip(&avatars) {
+        let current = existing
+            .iter()
+            .find(|(rkey, _)| *rkey == feed.rkey)
+            .map(|(_, record)| record);
+        let change = match current {
+            None => Some(Change::Create {
+                rkey: feed.rkey.clone(),
+            }),
+            Some(current) => {
+                let fields = changed_fields(current, feed, avatar.as_ref(), service_did);
+                (!fields.is_empty()).then(|| Change::Update {
+                    rkey: feed.rkey.clone(),
+                    fields,
+                })
+            }
+        };
+        let Some(change) = change else {
+            continue;
+        };
+
+        if !args.dry_run {
+            let avatar = match avatar {
+                // only upload the avatar if it changed
+                Some(avatar) if change_includes(&change, "avatar") => {
+                    Some(pds.upload_blob(avatar).await?)
+                }
+                Some(_) => current.and_then(|current| current.get("avatar").cloned()),
+                None => None,
+            };
+            let record = record(feed, current, avatar, service_did);
+            pds.put_record(&feed.rkey, &record).await?;
+        }
+        changes.push(change);
+    }
+
+    for (rkey, record) in &existing {
+        let ours = record.get("did").and_then(Value::as_str) == Some(service_did);
+        if ours && feeds.get(rkey).is_none() {
+            let change = Change::Delete { rkey: rkey.clone() };
+            if !args.dry_run {
+                pds.delete_record(rkey).await?;
+            }
+            changes.push(change);
+        }
+    }
+
+    Ok(changes)
+}
+
+fn change_includes(change: &Change, field: &str) -> bool {
+    match change {
+        Change::Create { .. } => true,
+        Change::Update { fields, .. } => fields.contains(&field),
+        Change::Delete { .. } => false,
+    }
+}
+
+/// The fields of `current` that don't match `feed`
+fn changed_fields(
+    current: &Value,
+    feed: &FeedDefinition,
+    avatar: Option<&Avatar>,
+    service_did: &str,
+) -> Vec<&'static str> {
+    let str_field = |field: &str| current.get(field).and_then(Value::as_str);
+    let current_avatar = current.pointer("/avatar/ref/$link").and_then(Value::as_str);
+
+    let mut fields = vec![];
+    if str_field("did") != Some(service_did) {
+        fields.push("did");
+    }
+    if str_field("displayName") != Some(&feed.display_name) {
+        fields.push("displayName");
+    }
+    if str_field("description") != feed.description.as_deref() {
+        fields.push("description");
+    }
+    if current_avatar != avatar.map(|avatar| avatar.cid.as_str()) {
+        fields.push("avatar");
+    }
+    fields
+}
+
+/// The record for `feed`. Fields of `current` we don't manage, like `createdAt`, are kept
+fn record(
+    feed: &FeedDefinition,
+    current: Option<&Value>,
+    avatar: Option<Value>,
+    service_did: &str,
+) -> Value {
+    let mut record = current.cloned().unwrap_or_else(|| {
+        json!({
+            "$type": COLLECTION,
+            "createdAt": Utc::now().to_rfc3339_opts(SecondsFormat::Millis, true),
+        })
+    });
+    let fields = record.as_object_mut().expect("records are objects");
+
+    fields.insert("did".to_string(), service_did.into());
+    fields.insert("displayName".to_string(), feed.display_name.clone().into());
+    match &feed.description {
+        Some(description) => fields.insert("description".to_string(), description.clone().into()),
+        None => fields.remove("description"),
+    };
+    match avatar {
+        Some(avatar) => fields.insert("avatar".to_string(), avatar),
+        None => fields.remove("avatar"),
+    };
+
+    record
+}
+
+/// An avatar image on disk
+struct Avatar {
+    bytes: Vec<u8>,
+    mime_type: &'static str,
+    /// What the cid of the blob will be once it's uploaded
+    cid: String,
+}
+
+impl Avatar {
+    fn read(path: &Path) -> Result<Self> {
+        let mime_type = match path.extension().and_then(|ext| ext.to_str()) {
+            Some("png") => "image/png",
+            Some("jpg" | "jpeg") => "image/jpeg",
+            _ => bail!("avatar {} must be a png or jpeg", path.display()),
+        };
+        let bytes = std::fs::read(path)
+            .with_context(|| format!("failed to read avatar {}", path.display()))?;
+        let cid = blob_cid(&bytes);
+
+        Ok(Self {
+            bytes,
+            mime_type,
+            cid,
+        })
+    }
+}
+
+/// The cid a PDS gives a blob with these contents
+fn blob_cid(bytes: &[u8]) -> String {
+    let digest = Sha256::digest(bytes);
+    let hash = Multihash::<64>::wrap(SHA2_256, &digest).expect("sha256 digests fit");
+    Cid::new_v1(RAW, hash).to_string()
+}
+
+/// A logged in session on a PDS
+struct Pds {
+    client: reqwest::Client,
+    url: String,
+    did: String,
+    access_jwt: String,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Session {
+    did: String,
+    access_jwt: String,
+}
+
+#[derive(Deserialize)]
+struct ListRecords {
+    cursor: Option<String>,
+    records: Vec<ListedRecord>,
+}
+
+#[derive(Deserialize)]
+struct ListedRecord {
+    uri: String,
+    value: Value,
+}
+
+impl Pds {
+    async fn login(url: &str, identifier: &str, password: &str) -> Result<Self> {
+        let client = reqwest::Client::new();
+        let url = url.trim_end_matches('/').to_string();
+
+        let session = send(
+            client
+                .post(format!("{url}/xrpc/com.atproto.server.createSession"))
+                .json(&json!({ "identifier": identifier, "password": password })),
+        )
+        .await
+        .context("failed to log in")?
+        .json::<Session>()
+        .await?;
+
+        Ok(Self {
+            client,
+            url,
+            did: session.did,
+            access_jwt: session.access_jwt,
+        })
+    }
+
+    /// Every feed generator record in the repo, by rkey
+    async fn list_records(&self) -> Result<Vec<(String, Value)>> {
+        let mut records = vec![];
+        let mut cursor: Option<String> = None;
+
+        loop {
+            let mut query = vec![
+                ("repo", self.did.as_str()),
+                ("collection", COLLECTION),
+                ("limit", "100"),
+            ];
+            if let Some(cursor) = &cursor {
+                query.push(("cursor", cursor.as_str()));
+            }
+            let page = send(
+                self.client
+                    .get(self.xrpc("com.atproto.repo.listRecords"))
+                    .query(&query),
+            )
+            .await
+            .context("failed to list feed generator records")?
+            .json::<ListRecords>()
+            .await?;
+
+            for record in page.records {
+                let uri = record
+                    .uri
+                    .parse::<AtUriBuf>()
+                    .with_context(|| format!("listed record has an invalid uri {}", record.uri))?;
+                if let Some(rkey) = uri.rkey {
+                    records.push((rkey, record.value));
+                }
+            }
+
+            match page.cursor {
+                Some(next) if !next.is_empty() => cursor = Some(next),
+                _ => break,
+            }
+        }
+
+        Ok(records)
+    }
+
+    /// Uploads `avatar`, and returns the blob to put in the record
+    async fn upload_blob(&self, avatar: &Avatar) -> Result<Value> {
+        let response = send(
+            self.authed(self.client.post(self.xrpc("com.atproto.repo.uploadBlob")))
+                .header(CONTENT_TYPE, avatar.mime_type)
+                .body(avatar.bytes.clone()),
+        )
+        .await
+        .context("failed to upload avatar")?
+        .json::<Value>()
+        .await?;
+
+        response
+            .get("blob")
+            .cloned()
+            .context("uploadBlob response has no blob")
+    }
+
+    async fn put_record(&self, rkey: &str, record: &Value) -> Result<()> {
+        send(
+            self.authed(self.client.post(self.xrpc("com.atproto.repo.putRecord")))
+                .json(&json!({
+                    "repo": self.did,
+                    "collection": COLLECTION,
+                    "rkey": rkey,
+                    "record": record,
+                })),
+        )
+        .await
+        .with_context(|| format!("failed to put record {rkey}"))?;
+
+        Ok(())
+    }
+
+    async fn delete_record(&self, rkey: &str) -> Result<()> {
+        send(
+            self.authed(self.client.post(self.xrpc("com.atproto.repo.deleteRecord")))
+                .json(&json!({
+                    "repo": self.did,
+                    "collection": COLLECTION,
+                    "rkey": rkey,
+                })),
+        )
+        .await
+        .with_context(|| format!("failed to delete record {rkey}"))?;
+
+        Ok(())
+    }
+
+    fn xrpc(&self, method: &str) -> String {
+        format!("{}/xrpc/{method}", self.url)
+    }
+
+    fn authed(&self, request: RequestBuilder) -> RequestBuilder {
+        request.bearer_auth(&self.access_jwt)
+    }
+}
+
+/// Sends `request`, turning XRPC errors into errors
+async fn send(request: RequestBuilder) -> Result<Response> {
+    let response = request.send().await?;
+    let status = response.status();
+    if status.is_success() {
+        return Ok(response);
+    }
+
+    let body = response.text().await.unwrap_or_default();
+    match serde_json::from_str::<Value>(&body) {
+        Ok(error) => bail!(
+            "{status}: {} {}",
+            error["error"].as_str().unwrap_or_default(),
+            error["message"].as_str().unwrap_or_default()
+        ),
+        Err(_) => bail!("{status}: {body}"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::{
+        collections::BTreeMap,
+        path::PathBuf,
+        sync::{Arc, Mutex},
+    };
+
+    use axum::{
+        body::Bytes,
+        extract::{Query, State},
+        http::{header::AUTHORIZATION, HeaderMap, StatusCode},
+        routing::{get, post},
+        Json, Router,
+    };
+
+    use crate::feeds::{Algorithm, Filters};
+
+    const SERVICE_DID: &str = "did:web:feed.example.com";
+    const PUBLISHER_DID: &str = "did:plc:pub6kzfhyqdrwv3lm2xe5a7t";
+
+    /// A PDS with a single repo, that only knows about feed generator records
+    #[derive(Default)]
+    struct MockPds {
+        records: BTreeMap<String, Value>,
+        uploads: usize,
+    }
+
+    type Shared = Arc<Mutex<MockPds>>;
+
+    fn authorized(headers: &HeaderMap) -> Result<(), StatusCode> {
+        match headers.get(AUTHORIZATION).and_then(|v| v.to_str().ok()) {
+            Some("Bearer access-jwt") => Ok(()),
+            _ => Err(StatusCode::UNAUTHORIZED),
+        }
+    }
+
+    async fn create_session(Json(input): Json<Value>) -> Result<Json<Value>, StatusCode> {
+        if input["password"] != "app-password" {
+            return Err(StatusCode::UNAUTHORIZED);
+        }
+        Ok(Json(json!({
+            "did": PUBLISHER_DID,
+            "handle": "publisher.example.com",
+            "accessJwt": "access-jwt",
+            "refreshJwt": "refresh-jwt",
+        })))
+    }
+
+    async fn list_records(
+        State(pds): State<Shared>,
+        Query(query): Query<BTreeMap<String, String>>,
+    ) -> Json<Value> {
+        assert_eq!(PUBLISHER_DID, query["repo"]);
+        let records = pds
+            .lock()
+            .unwrap()
+            .records
+            .iter()
+            .map(|(rkey, value)| {
+                json!({
+                    "uri": format!("at://{PUBLISHER_DID}/{COLLECTION}/{rkey}"),
+                    "cid": "cid",
+                    "value": value,
+                })
+            })
+            .collect::<Vec<_>>();
+        Json(json!({ "records": records }))
+    }
+
+    async fn put_record(
+        State(pds): State<Shared>,
+        headers: HeaderMap,
+        Json(input): Json<Value>,
+    ) -> Result<Json<Value>, StatusCode> {
+        authorized(&headers)?;
+        let rkey = input["rkey"].as_str().unwrap().to_string();
+        pds.lock()
+            .unwrap()
+            .records
+            .insert(rkey, input["record"].clone());
+        Ok(Json(json!({ "uri": "uri", "cid": "cid" })))
+    }
+
+    async fn delete_record(
+        State(pds): State<Shared>,
+        headers: HeaderMap,
+        Json(input): Json<Value>,
+    ) -> Result<Json<Value>, StatusCode> {
+        authorized(&headers)?;
+        pds.lock()
+            .unwrap()
+            .records
+            .remove(input["rkey"].as_str().unwrap());
+        Ok(Json(json!({})))
+    }
+
+    async fn upload_blob(
+        State(pds): State<Shared>,
+        headers: HeaderMap,
+        body: Bytes,
+    ) -> Result<Json<Value>, StatusCode> {
+        authorized(&headers)?;
+        pds.lock().unwrap().uploads += 1;
+        Ok(Json(json!({
+            "blob": {
+                "$type": "blob",
+                "ref": { "$link": blob_cid(&body) },
+                "mimeType": headers[CONTENT_TYPE].to_str().unwrap(),
+                "size": body.len(),
+            }
+        })))
+    }
+
+    /// Serves a mock PDS on a random port, and returns its url
+    async fn serve(pds: Shared) -> String {
+        let app = Router::new()
+            .route(
+                "/xrpc/com.atproto.server.createSession",
+                post(create_session),
+            )
+            .route("/xrpc/com.atproto.repo.listRecords", get(list_records))
+            .route("/xrpc/com.atproto.repo.putRecord", post(put_record))
+            .route("/xrpc/com.atproto.repo.deleteRecord", post(delete_record))
+            .route("/xrpc/com.atproto.repo.uploadBlob", post(upload_blob))
+            .with_state(pds);
+
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+
+        format!("http://{addr}/")
+    }
+
+    fn args(pds: String, dry_run: bool) -> Args {
+        Args {
+            identifier: "publisher.example.com".to_string(),
+            password: "app-password".to_string(),
+            pds,
+            dry_run,
+        }
+    }
+
+    fn feed(rkey: &str, display_name: &str, avatar: Option<PathBuf>) -> FeedDefinition {
+        FeedDefinition {
+            rkey: rkey.to_string(),
+            display_name: display_name.to_string(),
+            description: None,
+            avatar,
+            algorithm: Algorithm::Chronological,
+            filters: Filters::default(),
+        }
+    }
+
+    fn existing(did: &str, display_name: &str) -> Value {
+        json!({
+            "$type": COLLECTION,
+            "did": did,
+            "displayName": display_name,
+            "createdAt": "2024-11-01T00:00:00.000Z",
+        })
+    }
+
+    /// A pds with an outdated `music` record, a record for a feed that isn't configured anymore,
+    /// and a record for someone else's feed generator
+    fn mock_pds() -> Shared {
+        let mut pds = MockPds::default();
+        pds.records
+            .insert("music".to_string(), existing(SERVICE_DID, "Old name"));
+        pds.records
+            .insert("gone".to_string(), existing(SERVICE_DID, "Gone"));
+        pds.records.insert(
+            "elsewhere".to_string(),
+            existing("did:web:other.example.com", "Elsewhere"),
+        );
+        Arc::new(Mutex::new(pds))
+    }
+
+    fn avatar() -> PathBuf {
+        let path = std::env::temp_dir().join(format!("avatar-{}.png", std::process::id()));
+        std::fs::write(&path, b"not really a png").unwrap();
+        path
+    }
+
+    #[tokio::test]
+    async fn test_publish() {
+        let pds = mock_pds();
+        let url = serve(pds.clone()).await;
+        let feeds = Feeds::new(vec![
+            feed("music", "Music", None),
+            feed("new", "New", Some(avatar())),
+        ]);
+
+        let changes = publish(
+            &args(url.clone(), false),
+            &feeds,
+            SERVICE_DID,
+            PUBLISHER_DID,
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(
+            vec![
+                Change::Update {
+                    rkey: "music".to_string(),
+                    fields: vec!["displayName"],
+                },
+                Change::Create {
+                    rkey: "new".to_string()
+                },
+                Change::Delete {
+                    rkey: "gone".to_string()
+                },
+            ],
+            changes
+        );
+
+        {
+            let pds = pds.lock().unwrap();
+            assert_eq!(
+                vec!["elsewhere", "music", "new"],
+                pds.records.keys().collect::<Vec<_>>()
+            );
+            let music = &pds.records["music"];
+            assert_eq!("Music", music["displayName"]);
+            assert_eq!("2024-11-01T00:00:00.000Z", music["createdAt"]);
+            let new = &pds.records["new"];
+            assert_eq!(SERVICE_DID, new["did"]);
+            assert_eq!("image/png", new["avatar"]["mimeType"]);
+            assert_eq!(1, pds.uploads);
+        }
+
+        // publishing again changes nothing, and doesn't upload the avatar again
+        let changes = publish(&args(url, false), &feeds, SERVICE_DID, PUBLISHER_DID)
+            .await
+            .unwrap();
+        assert_eq!(Vec::<Change>::new(), changes);
+        assert_eq!(1, pds.lock().unwrap().uploads);
+    }
+
+    #[tokio::test]
+    async fn test_dry_run() {
+        let pds = mock_pds();
+        let url = serve(pds.clone()).await;
+        let feeds = Feeds::new(vec![feed("music", "Old name", Some(avatar()))]);
+
+        let changes = publish(&args(url, true), &feeds, SERVICE_DID, PUBLISHER_DID)
+            .await
+            .unwrap();
+
+        assert_eq!(
+            vec![
+                Change::Update {
+                    rkey: "music".to_string(),
+                    fields: vec!["avatar"],
+                },
+                Change::Delete {
+                    rkey: "gone".to_string()
+                },
+            ],
+            changes
+        );
+        let pds = pds.lock().unwrap();
+        assert_eq!(3, pds.records.len());
+        assert_eq!(None, pds.records["music"].get("avatar"));
+        assert_eq!(0, pds.uploads);
+    }
+
+    #[tokio::test]
+    async fn test_login_failure() {
+        let url = serve(mock_pds()).await;
+        let args = Args {
+            password: "wrong".to_string(),
+            ..args(url, false)
+        };
+
+        let err = publish(&args, &Feeds::default(), SERVICE_DID, PUBLISHER_DID)
+            .await
+            .unwrap_err();
+
+        assert!(format!("{err:#}").contains("failed to log in"), "{err:#}");
+    }
+
+    #[tokio::test]
+    async fn test_wrong_account() {
+        let url = serve(mock_pds()).await;
+
+        let err = publish(
+            &args(url, false),
+            &Feeds::default(),
+            SERVICE_DID,
+            "did:plc:else5xkq2vmz7nwr3ly4dpt6",
+        )
+        .await
+        .unwrap_err();
+
+        assert!(
+            err.to_string().contains("did:plc:else5xkq2vmz7nwr3ly4dpt6"),
+            "{err}"
+        );
+    }
+
+    #[test]
+    fn test_blob_cid() {
+        assert_eq!(
+            "bafkreihdwdcefgh4dqkjv67uzcmw7ojee6xedzdetojuzjevtenxquvyku",
+            blob_cid(b"")
+        );
+    }
+}
@@ -0,0 +1,237 @@
+use std::{collections::HashMap, time::Duration};
+
+use anyhow::Result;
+use chrono::Utc;
+
+use crate::storage::Storage;
+
+pub struct SpamConfig {
+    /// The maximum amount of music posts an author can have indexed within `rate_limit_window`
+    pub max_posts_per_window: u32,
+    pub rate_limit_window: Duration,
+    /// How long after a post an author can't post a near-identical one
+    pub duplicate_window: Duration,
+}
+
+impl Default for SpamConfig {
+    fn default() -> Self {
+        Self {
+            max_posts_per_window: 10,
+            rate_limit_window: Duration::from_secs(60 * 60),
+            duplicate_window: Duration::from_secs(24 * 60 * 60),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Verdict {
+    Allowed,
+    RateLimited,
+    Duplicate,
+}
+
+/// The fingerprints of posts that passed the check but aren't written yet, by author
+#[derive(Debug, Default)]
+pub struct Pending(HashMap<String, Vec<String>>);
+
+impl Pending {
+    pub fn add(&mut self, author: &str, fingerprint: &str) {
+        self.0
+            .entry(author.to_string())
+            .or_default()
+            .push(fingerprint.to_string());
+    }
+}
+
+/// Checks whether a new post by `author` with the given `fingerprint` should be indexed. Posts in
+/// `pending` count as much as the ones already written, so a burst of posts that are written
+/// together is checked against itself too
+pub async fn check(
+    storage: &impl Storage,
+    config: &SpamConfig,
+    pending: &Pending,
+    author: &str,
+    fingerprint: &str,
+) -> Result<Verdict> {
+    let now = Utc::now();
+    let stats = storage
+        .author_stats(
+            author,
+            fingerprint,
+            now - config.rate_limit_window,
+            now - config.duplicate_window,
+        )
+        .await?;
+    let pending = pending.0.get(author).map(Vec::as_slice).unwrap_or_default();
+
+    Ok(
+        if stats.duplicates > 0 || pending.iter().any(|pending| pending == fingerprint) {
+            Verdict::Duplicate
+        } else if stats.recent + pending.len() as i64 >= i64::from(config.max_posts_per_window) {
+            Verdict::RateLimited
+        } else {
+            Verdict::Allowed
+        },
+    )
+}
+
+/// Normalizes a post's text, so that posts that only differ in case, whitespace, or link
+/// tracking parameters (like spotify's `?si=`) end up with the same fingerprint
+pub fn fingerprint(text: &str) -> String {
+    text.split_whitespace()
+        .map(|word| {
+            if word.starts_with("http://") || word.starts_with("https://") {
+                word.split('?').next().unwrap_or(word)
+            } else {
+                word
+            }
+        })
+        .collect::<Vec<_>>()
+        .join(" ")
+        .to_lowercase()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::{
+        models::posts::{NewPost, Post},
+        storage::SqliteStorage,
+    };
+
+    async fn create(storage: &SqliteStorage, uri: &str, text: &str) {
+        Post::create(
+            storage.pool(),
+            &NewPost {
+                uri,
+                cid: "cid".to_string(),
+                author: "did:plc:author",
+                reply_root: None,
+                reply_parent: None,
+                quote: None,
+                fingerprint: Some(&fingerprint(text)),
+                langs: &[],
+            },
+        )
+        .await
+        .unwrap();
+    }
+
+    #[test]
+    fn test_fingerprint_ignores_case_whitespace_and_tracking() {
+        assert_eq!(
+            fingerprint("New  song!\nhttps://open.spotify.com/track/abc?si=123"),
+            fingerprint("new song! https://open.spotify.com/track/abc?si=456"),
+        );
+        assert_ne!(
+            fingerprint("new song! https://open.spotify.com/track/abc"),
+            fingerprint("new song! https://open.spotify.com/track/def"),
+        );
+    }
+
+    #[tokio::test]
+    async fn test_duplicates_are_suppressed() {
+        let storage = SqliteStorage::memory().await;
+        let config = SpamConfig::default();
+        create(
+            &storage,
+            "a",
+            "listen https://open.spotify.com/track/abc?si=1",
+        )
+        .await;
+
+        let duplicate = fingerprint("Listen https://open.spotify.com/track/abc?si=2");
+        let different = fingerprint("listen https://open.spotify.com/track/def");
+
+        assert_eq!(
+            Verdict::Duplicate,
+            check(
+                &storage,
+                &config,
+                &Pending::default(),
+                "did:plc:author",
+                &duplicate
+            )
+            .await
+            .unwrap()
+        );
+        assert_eq!(
+            Verdict::Allowed,
+            check(
+                &storage,
+                &config,
+                &Pending::default(),
+                "did:plc:author",
+                &different
+            )
+            .await
+            .unwrap()
+        );
+        assert_eq!(
+            Verdict::Allowed,
+            check(
+                &storage,
+                &config,
+                &Pending::default(),
+                "did:plc:other",
+                &duplicate
+            )
+            .await
+            .unwrap()
+        );
+    }
+
+    #[tokio::test]
+    async fn test_rate_limit() {
+        let storage = SqliteStorage::memory().await;
+        let config = SpamConfig {
+            max_posts_per_window: 2,
+            ..Default::default()
+        };
+        create(&storage, "a", "one").await;
+
+        assert_eq!(
+            Verdict::Allowed,
+            check(
+                &storage,
+                &config,
+                &Pending::default(),
+                "did:plc:author",
+                "two"
+            )
+            .await
+            .unwrap()
+        );
+
+        let mut pending = Pending::default();
+        pending.add("did:plc:author", "two");
+        assert_eq!(
+            Verdict::RateLimited,
+            check(&storage, &config, &pending, "did:plc:author", "three")
+                .await
+                .unwrap()
+        );
+        assert_eq!(
+            Verdict::Duplicate,
+            check(&storage, &config, &pending, "did:plc:author", "two")
+                .await
+                .unwrap()
+        );
+
+        create(&storage, "b", "two").await;
+
+        assert_eq!(
+            Verdict::RateLimited,
+            check(
+                &storage,
+                &config,
+                &Pending::default(),
+                "did:plc:author",
+                "three"
+            )
+            .await
+            .unwrap()
+        );
+    }
+}
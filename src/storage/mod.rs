@@ -0,0 +1,340 @@
+use std::{
+    future::Future,
+    time::{Duration, Instant},
+};
+
+use anyhow::Result;
+use chrono::{DateTime, Utc};
+
+use crate::{
+    batch::Write,
+    feedback::Adjustment,
+    maintenance::{LinkUpdate, PruneReport, ReindexReport, Stats, StoredLink},
+    metrics,
+    models::{
+        blocked_authors::BlockedAuthor,
+        blocked_links::BlockedLink,
+        heartbeats::Heartbeat,
+        interactions::NewInteraction,
+        labels::Label,
+        posts::{AuthorStats, Post, PostFilter},
+        preferences::Preference,
+    },
+};
+
+pub use postgres::PostgresStorage;
+pub use sqlite::SqliteStorage;
+
+mod postgres;
+mod sqlite;
+#[cfg(test)]
+mod tests;
+
+/// Everything the feed needs from a database.
+///
+/// Methods that make more than one change do so in a single transaction
+pub trait Storage {
+    /// Brings the schema up to date
+    fn migrate(&self) -> impl Future<Output = Result<()>> + Send;
+
+    /// Applies a batch of ingest writes, in order
+    fn write_batch(&self, writes: &[Write]) -> impl Future<Output = Result<()>> + Send;
+
+    /// See [`Post::get_all`]
+    fn get_posts(
+        &self,
+        limit: u8,
+        before: Option<DateTime<Utc>>,
+        filter: &PostFilter<'_>,
+    ) -> impl Future<Output = Result<Vec<Post>>> + Send;
+
+    fn author_stats(
+        &self,
+        author: &str,
+        fingerprint: &str,
+        rate_limit_since: DateTime<Utc>,
+        duplicate_since: DateTime<Utc>,
+    ) -> impl Future<Output = Result<AuthorStats>> + Send;
+
+    /// The uri of every stored post
+    fn post_uris(&self) -> impl Future<Output = Result<Vec<String>>> + Send;
+
+    /// The uri of every like and repost counted in a post's engagement
+    fn engagement_uris(&self) -> impl Future<Output = Result<Vec<String>>> + Send;
+
+    fn is_author_blocked(&self, did: &str) -> impl Future<Output = Result<bool>> + Send;
+
+    /// Checks whether any of `urls` contains a blocked link
+    fn any_link_blocked(&self, urls: &[&str]) -> impl Future<Output = Result<bool>> + Send;
+
+    fn blocked_authors(&self) -> impl Future<Output = Result<Vec<BlockedAuthor>>> + Send;
+
+    /// Blocks an author and removes their posts, returning how many were removed
+    fn block_author(
+        &self,
+        did: &str,
+        reason: Option<&str>,
+    ) -> impl Future<Output = Result<u64>> + Send;
+
+    fn unblock_author(&self, did: &str) -> impl Future<Output = Result<()>> + Send;
+
+    fn blocked_links(&self) -> impl Future<Output = Result<Vec<BlockedLink>>> + Send;
+
+    /// Blocks a link and removes the posts containing it, returning how many were removed
+    fn block_link(
+        &self,
+        url: &str,
+        reason: Option<&str>,
+    ) -> impl Future<Output = Result<u64>> + Send;
+
+    fn unblock_link(&self, url: &str) -> impl Future<Output = Result<()>> + Send;
+
+    fn create_label(&self, label: &Label) -> impl Future<Output = Result<()>> + Send;
+
+    fn delete_label(
+        &self,
+        src: &str,
+        uri: &str,
+        val: &str,
+    ) -> impl Future<Output = Result<()>> + Send;
+
+    /// Stores the interactions sent by a viewer, along with the changes they make to their
+    /// preferences
+    fn store_interactions(
+        &self,
+        interactions: &[NewInteraction<'_>],
+        adjustments: &[Adjustment<'_>],
+        now: DateTime<Utc>,
+        half_life: Duration,
+    ) -> impl Future<Output = Result<()>> + Send;
+
+    fn preferences(&self, viewer: &str) -> impl Future<Output = Result<Vec<Preference>>> + Send;
+
+    /// Deletes posts indexed before `cutoff`, and links created before it that are no longer in
+    /// any post
+    fn prune(&self, cutoff: DateTime<Utc>) -> impl Future<Output = Result<PruneReport>> + Send;
+
+    /// Every link, with how it was classified when it was stored
+    fn links(&self) -> impl Future<Output = Result<Vec<StoredLink>>> + Send;
+
+    /// Applies every update in a single transaction
+    fn update_links(
+        &self,
+        updates: &[LinkUpdate],
+    ) -> impl Future<Output = Result<ReindexReport>> + Send;
+
+    fn stats(&self) -> impl Future<Output = Result<Stats>> + Send;
+
+    /// Reclaims free space and refreshes the query planner's statistics. Cheap enough to run
+    /// periodically while ingesting
+    fn optimize(&self) -> impl Future<Output = Result<()>> + Send;
+
+    /// Rewrites the whole database to compact it. Blocks writes for as long as it takes, so it's
+    /// only run from `prune --optimize`
+    fn vacuum(&self) -> impl Future<Output = Result<()>> + Send;
+
+    /// The last event we processed from `service`'s firehose, if any
+    fn cursor(&self, service: &str) -> impl Future<Output = Result<Option<i64>>> + Send;
+
+    fn set_cursor(&self, service: &str, seq: i64) -> impl Future<Output = Result<()>> + Send;
+
+    /// The latest heartbeat of whatever is ingesting `relay`, if anything ever has
+    fn heartbeat(&self, relay: &str) -> impl Future<Output = Result<Option<Heartbeat>>> + Send;
+
+    fn set_heartbeat(&self, heartbeat: &Heartbeat) -> impl Future<Output = Result<()>> + Send;
+
+    /// Checks that the database can be reached
+    fn ping(&self) -> impl Future<Output = Result<()>> + Send;
+}
+
+/// The database picked by `DATABASE_URL`
+#[derive(Clone)]
+pub enum Database {
+    Sqlite(SqliteStorage),
+    Postgres(PostgresStorage),
+}
+
+impl Database {
+    /// Connects to postgres for `postgres://` urls, and to sqlite otherwise
+    pub async fn connect(url: &str) -> Result<Self> {
+        if url.starts_with("postgres://") || url.starts_with("postgresql://") {
+            Ok(Self::Postgres(PostgresStorage::connect(url).await?))
+        } else {
+            Ok(Self::Sqlite(SqliteStorage::connect(url).await?))
+        }
+    }
+
+    /// Waits for every connection to be returned, and closes them
+    pub async fn close(&self) {
+        match self {
+            Database::Sqlite(db) => db.close().await,
+            Database::Postgres(db) => db.close().await,
+        }
+    }
+}
+
+/// Calls a method on whichever backend is in use, and records how long it took
+macro_rules! dispatch {
+    ($self:ident.$method:ident($($arg:expr),*)) => {{
+        let start = Instant::now();
+        let result = match $self {
+            Database::Sqlite(db) => db.$method($($arg),*).await,
+            Database::Postgres(db) => db.$method($($arg),*).await,
+        };
+        metrics::db_query(stringify!($method), start.elapsed());
+        result
+    }};
+}
+
+impl Storage for Database {
+    async fn migrate(&self) -> Result<()> {
+        dispatch!(self.migrate())
+    }
+
+    async fn write_batch(&self, writes: &[Write]) -> Result<()> {
+        dispatch!(self.write_batch(writes))
+    }
+
+    async fn get_posts(
+        &self,
+        limit: u8,
+        before: Option<DateTime<Utc>>,
+        filter: &PostFilter<'_>,
+    ) -> Result<Vec<Post>> {
+        dispatch!(self.get_posts(limit, before, filter))
+    }
+
+    async fn author_stats(
+        &self,
+        author: &str,
+        fingerprint: &str,
+        rate_limit_since: DateTime<Utc>,
+        duplicate_since: DateTime<Utc>,
+    ) -> Result<AuthorStats> {
+        dispatch!(self.author_stats(author, fingerprint, rate_limit_since, duplicate_since))
+    }
+
+    async fn post_uris(&self) -> Result<Vec<String>> {
+        dispatch!(self.post_uris())
+    }
+
+    async fn engagement_uris(&self) -> Result<Vec<String>> {
+        dispatch!(self.engagement_uris())
+    }
+
+    async fn is_author_blocked(&self, did: &str) -> Result<bool> {
+        dispatch!(self.is_author_blocked(did))
+    }
+
+    async fn any_link_blocked(&self, urls: &[&str]) -> Result<bool> {
+        dispatch!(self.any_link_blocked(urls))
+    }
+
+    async fn blocked_authors(&self) -> Result<Vec<BlockedAuthor>> {
+        dispatch!(self.blocked_authors())
+    }
+
+    async fn block_author(&self, did: &str, reason: Option<&str>) -> Result<u64> {
+        dispatch!(self.block_author(did, reason))
+    }
+
+    async fn unblock_author(&self, did: &str) -> Result<()> {
+        dispatch!(self.unblock_author(did))
+    }
+
+    async fn blocked_links(&self) -> Result<Vec<BlockedLink>> {
+        dispatch!(self.blocked_links())
+    }
+
+    async fn block_link(&self, url: &str, reason: Option<&str>) -> Result<u64> {
+        dispatch!(self.block_link(url, reason))
+    }
+
+    async fn unblock_link(&self, url: &str) -> Result<()> {
+        dispatch!(self.unblock_link(url))
+    }
+
+    async fn create_label(&self, label: &Label) -> Result<()> {
+        dispatch!(self.create_label(label))
+    }
+
+    async fn delete_label(&self, src: &str, uri: &str, val: &str) -> Result<()> {
+        dispatch!(self.delete_label(src, uri, val))
+    }
+
+    async fn store_interactions(
+        &self,
+        interactions: &[NewInteraction<'_>],
+        adjustments: &[Adjustment<'_>],
+        now: DateTime<Utc>,
+        half_life: Duration,
+    ) -> Result<()> {
+        dispatch!(self.store_interactions(interactions, adjustments, now, half_life))
+    }
+
+    async fn preferences(&self, viewer: &str) -> Result<Vec<Preference>> {
+        dispatch!(self.preferences(viewer))
+    }
+
+    async fn prune(&self, cutoff: DateTime<Utc>) -> Result<PruneReport> {
+        dispatch!(self.prune(cutoff))
+    }
+
+    async fn links(&self) -> Result<Vec<StoredLink>> {
+        dispatch!(self.links())
+    }
+
+    async fn update_links(&self, updates: &[LinkUpdate]) -> Result<ReindexReport> {
+        dispatch!(self.update_links(updates))
+    }
+
+    async fn stats(&self) -> Result<Stats> {
+        dispatch!(self.stats())
+    }
+
+    async fn optimize(&self) -> Result<()> {
+        dispatch!(self.optimize())
+    }
+
+    async fn vacuum(&self) -> Result<()> {
+        dispatch!(self.vacuum())
+    }
+
+    async fn cursor(&self, service: &str) -> Result<Option<i64>> {
+        dispatch!(self.cursor(service))
+    }
+
+    async fn set_cursor(&self, service: &str, seq: i64) -> Result<()> {
+        dispatch!(self.set_cursor(service, seq))
+    }
+
+    async fn heartbeat(&self, relay: &str) -> Result<Option<Heartbeat>> {
+        dispatch!(self.heartbeat(relay))
+    }
+
+    async fn set_heartbeat(&self, heartbeat: &Heartbeat) -> Result<()> {
+        dispatch!(self.set_heartbeat(heartbeat))
+    }
+
+    async fn ping(&self) -> Result<()> {
+        dispatch!(self.ping())
+    }
+}
+
+/// Whether `err` is likely to go away if the same thing is tried again, like a locked database or
+/// a dropped connection, rather than being a problem with what was written
+pub fn is_transient(err: &anyhow::Error) -> bool {
+    err.chain()
+        .filter_map(|err| err.downcast_ref::<sqlx::Error>())
+        .any(|err| match err {
+            sqlx::Error::Io(_) | sqlx::Error::PoolTimedOut => true,
+            sqlx::Error::Database(err) => matches!(
+                err.code().as_deref(),
+                // sqlite's SQLITE_BUSY and SQLITE_LOCKED
+                Some("5" | "6")
+                    // postgres's serialization failures, deadlocks, and connection errors
+                    | Some("40001" | "40P01" | "08000" | "08003" | "08006")
+            ),
+            _ => false,
+        })
+}
@@ -0,0 +1,751 @@
+use std::time::Duration;
+
+use anyhow::{Context, Result};
+use chrono::{DateTime, Utc};
+use sqlx::{
+    postgres::{PgPoolOptions, PgRow},
+    PgPool, Row,
+};
+
+use super::Storage;
+use crate::{
+    batch::Write,
+    feedback::Adjustment,
+    link_finder::{Kind, Site},
+    maintenance::{LinkUpdate, PruneReport, ReindexReport, Stats, StoredLink},
+    models::{
+        blocked_authors::BlockedAuthor,
+        blocked_links::BlockedLink,
+        heartbeats::Heartbeat,
+        interactions::NewInteraction,
+        labels::Label,
+        posts::{next_indexed_at, AuthorStats, Post, PostFilter},
+        preferences::{decay, Preference},
+    },
+};
+
+const CONNECTIONS: u32 = 16;
+
+/// Postgres handles concurrent writers itself, so unlike sqlite a single pool is used for
+/// everything.
+///
+/// The queries mirror the ones in [`crate::models`], but are checked at runtime, since the
+/// compile time checks can only run against one database
+#[derive(Clone)]
+pub struct PostgresStorage {
+    pool: PgPool,
+}
+
+impl PostgresStorage {
+    pub async fn connect(url: &str) -> Result<Self> {
+        let pool = PgPoolOptions::new()
+            .max_connections(CONNECTIONS)
+            .connect(url)
+            .await
+            .context("failed to connect to db")?;
+
+        Ok(Self::new(pool))
+    }
+
+    pub fn new(pool: PgPool) -> Self {
+        Self { pool }
+    }
+
+    pub async fn close(&self) {
+        self.pool.close().await;
+    }
+
+    /// The pool everything goes through, for tests that need to look at the tables directly
+    #[cfg(test)]
+    pub fn pool(&self) -> &PgPool {
+        &self.pool
+    }
+}
+
+fn post_from_row(row: PgRow) -> Result<Post> {
+    Ok(Post {
+        uri: row.try_get("uri")?,
+        cid: row.try_get("cid")?,
+        author: row.try_get("author")?,
+        indexed_at: row.try_get("indexed_at")?,
+        reply_root: row.try_get("reply_root")?,
+        reply_parent: row.try_get("reply_parent")?,
+        quote: row.try_get("quote")?,
+        site: row
+            .try_get::<Option<&str>, _>("site")?
+            .map(str::parse)
+            .transpose()?,
+        kind: row
+            .try_get::<Option<&str>, _>("kind")?
+            .map(str::parse)
+            .transpose()?,
+        engagement: row.try_get("engagement")?,
+    })
+}
+
+impl Storage for PostgresStorage {
+    async fn migrate(&self) -> Result<()> {
+        sqlx::migrate!("./migrations/postgres")
+            .run(&self.pool)
+            .await
+            .context("failed to run migrations")?;
+
+        Ok(())
+    }
+
+    async fn write_batch(&self, writes: &[Write]) -> Result<()> {
+        let mut tx = self.pool.begin().await?;
+
+        for write in writes {
+            match write {
+                Write::Create(post) => {
+                    let now = next_indexed_at();
+                    sqlx::query(
+                        "insert into posts (uri, cid, author, indexed_at, reply_root, reply_parent, quote, fingerprint, langs) values ($1, $2, $3, $4, $5, $6, $7, $8, $9) on conflict(uri) do nothing",
+                    )
+                    .bind(&post.uri)
+                    .bind(&post.cid)
+                    .bind(&post.author)
+                    .bind(now)
+                    .bind(&post.reply_root)
+                    .bind(&post.reply_parent)
+                    .bind(&post.quote)
+                    .bind(&post.fingerprint)
+                    .bind((!post.langs.is_empty()).then_some(&post.langs))
+                    .execute(&mut *tx)
+                    .await
+                    .context("failed to create post")?;
+
+                    for link in &post.links {
+                        sqlx::query(
+                            "insert into links (url, kind, site, created_at) values ($1, $2, $3, $4) on conflict(url) do update set count = links.count + 1",
+                        )
+                        .bind(&link.url)
+                        .bind(link.kind.as_str())
+                        .bind(link.site.as_str())
+                        .bind(now)
+                        .execute(&mut *tx)
+                        .await
+                        .context("failed to create link")?;
+
+                        sqlx::query(
+                            "insert into post_links (post_uri, url) values ($1, $2) on conflict do nothing",
+                        )
+                        .bind(&post.uri)
+                        .bind(&link.url)
+                        .execute(&mut *tx)
+                        .await
+                        .context("failed to add link to post")?;
+                    }
+                    for url in &post.urls {
+                        sqlx::query(
+                            "insert into post_urls (post_uri, url) values ($1, $2) on conflict do nothing",
+                        )
+                        .bind(&post.uri)
+                        .bind(url)
+                        .execute(&mut *tx)
+                        .await
+                        .context("failed to add url to post")?;
+                    }
+                }
+                Write::Delete(uri) => {
+                    sqlx::query("delete from posts where uri = $1")
+                        .bind(uri)
+                        .execute(&mut *tx)
+                        .await
+                        .with_context(|| format!("failed to delete post with uri {uri}"))?;
+                }
+                Write::Engage { uri, subject } => {
+                    let created = sqlx::query(
+                        "insert into engagements (uri, subject) select $1, uri from posts where uri = $2 on conflict do nothing",
+                    )
+                    .bind(uri)
+                    .bind(subject)
+                    .execute(&mut *tx)
+                    .await
+                    .with_context(|| format!("failed to create engagement {uri}"))?;
+
+                    if created.rows_affected() == 1 {
+                        sqlx::query("update posts set engagement = engagement + 1 where uri = $1")
+                            .bind(subject)
+                            .execute(&mut *tx)
+                            .await
+                            .with_context(|| format!("failed to add engagement to {subject}"))?;
+                    }
+                }
+                Write::Disengage(uri) => {
+                    let subject: Option<String> = sqlx::query_scalar(
+                        "delete from engagements where uri = $1 returning subject",
+                    )
+                    .bind(uri)
+                    .fetch_optional(&mut *tx)
+                    .await
+                    .with_context(|| format!("failed to delete engagement {uri}"))?;
+
+                    if let Some(subject) = subject {
+                        sqlx::query("update posts set engagement = engagement - 1 where uri = $1")
+                            .bind(&subject)
+                            .execute(&mut *tx)
+                            .await
+                            .with_context(|| {
+                                format!("failed to remove engagement from {subject}")
+                            })?;
+                    }
+                }
+            }
+        }
+
+        tx.commit().await?;
+
+        Ok(())
+    }
+
+    async fn get_posts(
+        &self,
+        limit: u8,
+        before: Option<DateTime<Utc>>,
+        filter: &PostFilter<'_>,
+    ) -> Result<Vec<Post>> {
+        let posts = sqlx::query(
+            r#"
+            select
+                uri, cid, author, indexed_at, reply_root, reply_parent, quote, engagement,
+                links.site, links.kind
+            from posts
+            left join links on links.url = (
+                select url from post_links where post_links.post_uri = posts.uri limit 1
+            )
+            where ($1::timestamptz is null or indexed_at < $1)
+            and (
+                $2 = 'all'
+                or ($2 = 'exclude_replies' and reply_root is null)
+                or ($2 = 'top_level' and reply_root is null and quote is null)
+                or ($2 = 'music_root' and (reply_root is null or reply_root in (select uri from posts)))
+            )
+            and not exists (
+                select 1 from labels
+                where labels.uri in (posts.uri, posts.author, 'at://' || posts.author)
+                and labels.val = any($4)
+                and (labels.exp is null or labels.exp > $5)
+            )
+            and author not in (select did from blocked_authors)
+            and not exists (
+                select 1 from blocked_post_links where blocked_post_links.post_uri = posts.uri
+            )
+            and (
+                (cardinality($6::text[]) = 0 and cardinality($7::text[]) = 0)
+                or exists (
+                    select 1 from post_links
+                    join links as filtered on filtered.url = post_links.url
+                    where post_links.post_uri = posts.uri
+                    and (cardinality($6::text[]) = 0 or filtered.site = any($6))
+                    and (cardinality($7::text[]) = 0 or filtered.kind = any($7))
+                )
+            )
+            and (
+                cardinality($8::text[]) = 0
+                or exists (
+                    select 1 from unnest(posts.langs) as lang, unnest($8::text[]) as wanted
+                    where lang = wanted or lang like wanted || '-%'
+                )
+            )
+            and engagement >= $9
+            and author <> all($10)
+            order by indexed_at desc, cid desc
+            limit $3
+            "#,
+        )
+        .bind(before)
+        .bind(filter.reply_policy.as_str())
+        .bind(i64::from(limit))
+        .bind(filter.excluded_labels)
+        .bind(Utc::now())
+        .bind(filter.sites.iter().map(Site::as_str).collect::<Vec<_>>())
+        .bind(filter.kinds.iter().map(Kind::as_str).collect::<Vec<_>>())
+        .bind(filter.languages)
+        .bind(filter.min_engagement)
+        .bind(filter.excluded_authors)
+        .fetch_all(&self.pool)
+        .await
+        .context("failed to get posts")?
+        .into_iter()
+        .map(post_from_row)
+        .collect::<Result<Vec<_>>>()?;
+
+        Ok(posts)
+    }
+
+    async fn author_stats(
+        &self,
+        author: &str,
+        fingerprint: &str,
+        rate_limit_since: DateTime<Utc>,
+        duplicate_since: DateTime<Utc>,
+    ) -> Result<AuthorStats> {
+        let row = sqlx::query(
+            r#"
+            select
+                count(*) filter (where indexed_at > $2) as recent,
+                count(*) filter (where indexed_at > $3 and fingerprint = $4) as duplicates
+            from posts
+            where author = $1 and indexed_at > least($2, $3)
+            "#,
+        )
+        .bind(author)
+        .bind(rate_limit_since)
+        .bind(duplicate_since)
+        .bind(fingerprint)
+        .fetch_one(&self.pool)
+        .await
+        .with_context(|| format!("failed to get stats for {author}"))?;
+
+        Ok(AuthorStats {
+            recent: row.try_get("recent")?,
+            duplicates: row.try_get("duplicates")?,
+        })
+    }
+
+    async fn post_uris(&self) -> Result<Vec<String>> {
+        let uris = sqlx::query_scalar("select uri from posts")
+            .fetch_all(&self.pool)
+            .await
+            .context("failed to get post uris")?;
+
+        Ok(uris)
+    }
+
+    async fn engagement_uris(&self) -> Result<Vec<String>> {
+        let uris = sqlx::query_scalar("select uri from engagements")
+            .fetch_all(&self.pool)
+            .await
+            .context("failed to get engagement uris")?;
+
+        Ok(uris)
+    }
+
+    async fn is_author_blocked(&self, did: &str) -> Result<bool> {
+        let blocked =
+            sqlx::query_scalar("select exists(select 1 from blocked_authors where did = $1)")
+                .bind(did)
+                .fetch_one(&self.pool)
+                .await?;
+
+        Ok(blocked)
+    }
+
+    async fn any_link_blocked(&self, urls: &[&str]) -> Result<bool> {
+        let blocked: Vec<String> = sqlx::query_scalar("select url from blocked_links")
+            .fetch_all(&self.pool)
+            .await?;
+
+        Ok(blocked
+            .iter()
+            .any(|blocked| urls.iter().any(|url| BlockedLink::blocks(blocked, url))))
+    }
+
+    async fn blocked_authors(&self) -> Result<Vec<BlockedAuthor>> {
+        let blocked = sqlx::query("select * from blocked_authors order by created_at desc")
+            .fetch_all(&self.pool)
+            .await?
+            .into_iter()
+            .map(|row| {
+                Ok(BlockedAuthor {
+                    did: row.try_get("did")?,
+                    reason: row.try_get("reason")?,
+                    created_at: row.try_get("created_at")?,
+                })
+            })
+            .collect::<Result<_>>()?;
+
+        Ok(blocked)
+    }
+
+    async fn block_author(&self, did: &str, reason: Option<&str>) -> Result<u64> {
+        let mut tx = self.pool.begin().await?;
+
+        sqlx::query(
+            "insert into blocked_authors (did, reason, created_at) values ($1, $2, $3) on conflict(did) do update set reason = excluded.reason",
+        )
+        .bind(did)
+        .bind(reason)
+        .bind(Utc::now())
+        .execute(&mut *tx)
+        .await
+        .context("failed to create blocked author")?;
+
+        let purged = sqlx::query("delete from posts where author = $1")
+            .bind(did)
+            .execute(&mut *tx)
+            .await
+            .with_context(|| format!("failed to purge posts by {did}"))?
+            .rows_affected();
+
+        tx.commit().await?;
+
+        Ok(purged)
+    }
+
+    async fn unblock_author(&self, did: &str) -> Result<()> {
+        sqlx::query("delete from blocked_authors where did = $1")
+            .bind(did)
+            .execute(&self.pool)
+            .await
+            .with_context(|| format!("failed to delete blocked author {did}"))?;
+
+        Ok(())
+    }
+
+    async fn blocked_links(&self) -> Result<Vec<BlockedLink>> {
+        let blocked = sqlx::query("select * from blocked_links order by created_at desc")
+            .fetch_all(&self.pool)
+            .await?
+            .into_iter()
+            .map(|row| {
+                Ok(BlockedLink {
+                    url: row.try_get("url")?,
+                    reason: row.try_get("reason")?,
+                    created_at: row.try_get("created_at")?,
+                })
+            })
+            .collect::<Result<_>>()?;
+
+        Ok(blocked)
+    }
+
+    async fn block_link(&self, url: &str, reason: Option<&str>) -> Result<u64> {
+        let mut tx = self.pool.begin().await?;
+
+        sqlx::query(
+            "insert into blocked_links (url, reason, created_at) values ($1, $2, $3) on conflict(url) do update set reason = excluded.reason",
+        )
+        .bind(url)
+        .bind(reason)
+        .bind(Utc::now())
+        .execute(&mut *tx)
+        .await
+        .context("failed to create blocked link")?;
+
+        let purged = sqlx::query(
+            "delete from posts where uri in (select post_uri from blocked_post_links where blocked_url = $1)",
+        )
+        .bind(url)
+        .execute(&mut *tx)
+        .await
+        .with_context(|| format!("failed to purge posts linking to {url}"))?
+        .rows_affected();
+
+        tx.commit().await?;
+
+        Ok(purged)
+    }
+
+    async fn unblock_link(&self, url: &str) -> Result<()> {
+        sqlx::query("delete from blocked_links where url = $1")
+            .bind(url)
+            .execute(&self.pool)
+            .await
+            .with_context(|| format!("failed to delete blocked link {url}"))?;
+
+        Ok(())
+    }
+
+    async fn create_label(&self, label: &Label) -> Result<()> {
+        sqlx::query(
+            "insert into labels (src, uri, val, cts, exp) values ($1, $2, $3, $4, $5) on conflict(src, uri, val) do update set cts = excluded.cts, exp = excluded.exp",
+        )
+        .bind(&label.src)
+        .bind(&label.uri)
+        .bind(&label.val)
+        .bind(label.cts)
+        .bind(label.exp)
+        .execute(&self.pool)
+        .await
+        .context("failed to create label")?;
+
+        Ok(())
+    }
+
+    async fn delete_label(&self, src: &str, uri: &str, val: &str) -> Result<()> {
+        sqlx::query("delete from labels where src = $1 and uri = $2 and val = $3")
+            .bind(src)
+            .bind(uri)
+            .bind(val)
+            .execute(&self.pool)
+            .await
+            .with_context(|| format!("failed to delete label {val} on {uri}"))?;
+
+        Ok(())
+    }
+
+    async fn store_interactions(
+        &self,
+        interactions: &[NewInteraction<'_>],
+        adjustments: &[Adjustment<'_>],
+        now: DateTime<Utc>,
+        half_life: Duration,
+    ) -> Result<()> {
+        let mut tx = self.pool.begin().await?;
+
+        for interaction in interactions {
+            sqlx::query(
+                "insert into interactions (requester, item, event, feed_context, created_at) values ($1, $2, $3, $4, $5)",
+            )
+            .bind(interaction.requester)
+            .bind(interaction.item)
+            .bind(interaction.event)
+            .bind(interaction.feed_context)
+            .bind(now)
+            .execute(&mut *tx)
+            .await
+            .context("failed to create interaction")?;
+        }
+
+        for adjustment in adjustments {
+            // decays the current score up to `now`, and then adds the delta to it
+            let current: Option<(f64, DateTime<Utc>)> = sqlx::query_as(
+                "select score, updated_at from viewer_preferences where viewer = $1 and dimension = $2 and value = $3 for update",
+            )
+            .bind(adjustment.viewer)
+            .bind(adjustment.dimension.as_str())
+            .bind(adjustment.value)
+            .fetch_optional(&mut *tx)
+            .await?;
+
+            let score = current
+                .map(|(score, updated_at)| decay(score, now - updated_at, half_life))
+                .unwrap_or(0.0)
+                + adjustment.delta;
+
+            sqlx::query(
+                "insert into viewer_preferences (viewer, dimension, value, score, updated_at) values ($1, $2, $3, $4, $5) on conflict(viewer, dimension, value) do update set score = excluded.score, updated_at = excluded.updated_at",
+            )
+            .bind(adjustment.viewer)
+            .bind(adjustment.dimension.as_str())
+            .bind(adjustment.value)
+            .bind(score)
+            .bind(now)
+            .execute(&mut *tx)
+            .await
+            .with_context(|| format!("failed to adjust preference for {}", adjustment.viewer))?;
+        }
+
+        tx.commit().await?;
+
+        Ok(())
+    }
+
+    async fn preferences(&self, viewer: &str) -> Result<Vec<Preference>> {
+        let preferences = sqlx::query(
+            "select dimension, value, score, updated_at from viewer_preferences where viewer = $1",
+        )
+        .bind(viewer)
+        .fetch_all(&self.pool)
+        .await
+        .with_context(|| format!("failed to get preferences for {viewer}"))?
+        .into_iter()
+        .map(|row| {
+            Ok(Preference {
+                dimension: row.try_get::<&str, _>("dimension")?.parse()?,
+                value: row.try_get("value")?,
+                score: row.try_get("score")?,
+                updated_at: row.try_get("updated_at")?,
+            })
+        })
+        .collect::<Result<_>>()?;
+
+        Ok(preferences)
+    }
+
+    async fn prune(&self, cutoff: DateTime<Utc>) -> Result<PruneReport> {
+        let mut tx = self.pool.begin().await?;
+
+        let posts = sqlx::query("delete from posts where indexed_at < $1")
+            .bind(cutoff)
+            .execute(&mut *tx)
+            .await
+            .context("failed to delete old posts")?
+            .rows_affected();
+        let links = sqlx::query(
+            "delete from links where created_at < $1 and url not in (select url from post_links)",
+        )
+        .bind(cutoff)
+        .execute(&mut *tx)
+        .await
+        .context("failed to delete orphaned links")?
+        .rows_affected();
+
+        tx.commit().await?;
+
+        Ok(PruneReport { posts, links })
+    }
+
+    async fn links(&self) -> Result<Vec<StoredLink>> {
+        sqlx::query("select url, kind, site from links")
+            .fetch_all(&self.pool)
+            .await
+            .context("failed to get links")?
+            .into_iter()
+            .map(|row| {
+                Ok(StoredLink {
+                    url: row.try_get("url")?,
+                    kind: row.try_get::<&str, _>("kind")?.parse()?,
+                    site: row.try_get::<&str, _>("site")?.parse()?,
+                })
+            })
+            .collect()
+    }
+
+    async fn update_links(&self, updates: &[LinkUpdate]) -> Result<ReindexReport> {
+        let mut tx = self.pool.begin().await?;
+        let mut report = ReindexReport::default();
+
+        for update in updates {
+            match update {
+                LinkUpdate::Reclassify { url, kind, site } => {
+                    sqlx::query("update links set kind = $1, site = $2 where url = $3")
+                        .bind(kind.as_str())
+                        .bind(site.as_str())
+                        .bind(url)
+                        .execute(&mut *tx)
+                        .await
+                        .context("failed to reclassify link")?;
+                    report.reclassified += 1;
+                }
+                LinkUpdate::Remove { url } => {
+                    report.posts += sqlx::query(
+                        "delete from posts where uri in (select post_uri from post_links where url = $1) and not exists (select 1 from post_links as other where other.post_uri = posts.uri and other.url <> $1)",
+                    )
+                    .bind(url)
+                    .execute(&mut *tx)
+                    .await
+                    .context("failed to delete posts with link")?
+                    .rows_affected();
+                    sqlx::query("delete from post_links where url = $1")
+                        .bind(url)
+                        .execute(&mut *tx)
+                        .await
+                        .context("failed to remove link from posts")?;
+                    report.links += sqlx::query("delete from links where url = $1")
+                        .bind(url)
+                        .execute(&mut *tx)
+                        .await
+                        .context("failed to delete link")?
+                        .rows_affected();
+                }
+            }
+        }
+
+        tx.commit().await?;
+
+        Ok(report)
+    }
+
+    async fn stats(&self) -> Result<Stats> {
+        let row = sqlx::query(
+            "select count(*) as posts, count(distinct author) as authors, (select count(*) from links) as links, min(indexed_at) as oldest, max(indexed_at) as newest from posts",
+        )
+        .fetch_one(&self.pool)
+        .await
+        .context("failed to get stats")?;
+
+        Ok(Stats {
+            posts: row.try_get("posts")?,
+            authors: row.try_get("authors")?,
+            links: row.try_get("links")?,
+            oldest_post: row.try_get("oldest")?,
+            newest_post: row.try_get("newest")?,
+        })
+    }
+
+    async fn optimize(&self) -> Result<()> {
+        // autovacuum takes care of reclaiming space, but pruning can leave the statistics stale
+        sqlx::query("analyze")
+            .execute(&self.pool)
+            .await
+            .context("failed to analyze")?;
+
+        Ok(())
+    }
+
+    async fn vacuum(&self) -> Result<()> {
+        sqlx::query("vacuum analyze")
+            .execute(&self.pool)
+            .await
+            .context("failed to vacuum")?;
+
+        Ok(())
+    }
+
+    async fn cursor(&self, service: &str) -> Result<Option<i64>> {
+        let seq = sqlx::query_scalar("select seq from firehose_cursors where service = $1")
+            .bind(service)
+            .fetch_optional(&self.pool)
+            .await
+            .with_context(|| format!("failed to get cursor for {service}"))?;
+
+        Ok(seq)
+    }
+
+    async fn set_cursor(&self, service: &str, seq: i64) -> Result<()> {
+        sqlx::query(
+            "insert into firehose_cursors (service, seq, updated_at) values ($1, $2, $3) on conflict(service) do update set seq = excluded.seq, updated_at = excluded.updated_at",
+        )
+        .bind(service)
+        .bind(seq)
+        .bind(Utc::now())
+        .execute(&self.pool)
+        .await
+        .with_context(|| format!("failed to set cursor for {service}"))?;
+
+        Ok(())
+    }
+
+    async fn heartbeat(&self, relay: &str) -> Result<Option<Heartbeat>> {
+        let row = sqlx::query(
+            "select connected, seq, started_at, last_commit_at, updated_at from ingest_heartbeats where relay = $1",
+        )
+        .bind(relay)
+        .fetch_optional(&self.pool)
+        .await
+        .with_context(|| format!("failed to get heartbeat for {relay}"))?;
+
+        let Some(row) = row else {
+            return Ok(None);
+        };
+
+        Ok(Some(Heartbeat {
+            relay: relay.to_string(),
+            connected: row.try_get("connected")?,
+            cursor: row.try_get("seq")?,
+            started_at: row.try_get("started_at")?,
+            last_commit_at: row.try_get("last_commit_at")?,
+            updated_at: row.try_get("updated_at")?,
+        }))
+    }
+
+    async fn set_heartbeat(&self, heartbeat: &Heartbeat) -> Result<()> {
+        sqlx::query(
+            "insert into ingest_heartbeats (relay, connected, seq, started_at, last_commit_at, updated_at) values ($1, $2, $3, $4, $5, $6) on conflict(relay) do update set connected = excluded.connected, seq = excluded.seq, started_at = excluded.started_at, last_commit_at = excluded.last_commit_at, updated_at = excluded.updated_at",
+        )
+        .bind(&heartbeat.relay)
+        .bind(heartbeat.connected)
+        .bind(heartbeat.cursor)
+        .bind(heartbeat.started_at)
+        .bind(heartbeat.last_commit_at)
+        .bind(heartbeat.updated_at)
+        .execute(&self.pool)
+        .await
+        .with_context(|| format!("failed to set heartbeat for {}", heartbeat.relay))?;
+
+        Ok(())
+    }
+
+    async fn ping(&self) -> Result<()> {
+        sqlx::query("select 1")
+            .execute(&self.pool)
+            .await
+            .context("failed to ping db")?;
+
+        Ok(())
+    }
+}
@@ -0,0 +1,537 @@
+use std::{str::FromStr, time::Duration};
+
+use anyhow::{Context, Result};
+use chrono::{DateTime, Utc};
+use sqlx::{
+    sqlite::{
+        SqliteAutoVacuum, SqliteConnectOptions, SqliteJournalMode, SqlitePoolOptions,
+        SqliteSynchronous,
+    },
+    Pool, Sqlite,
+};
+
+use super::Storage;
+use crate::{
+    batch::Write,
+    feedback::Adjustment,
+    link_finder::FoundLink,
+    maintenance::{LinkUpdate, PruneReport, ReindexReport, Stats, StoredLink},
+    models::{
+        blocked_authors::BlockedAuthor,
+        blocked_links::BlockedLink,
+        cursors::Cursor,
+        engagements::Engagement,
+        heartbeats::Heartbeat,
+        interactions::{Interaction, NewInteraction},
+        labels::Label,
+        links::Link,
+        posts::{AuthorStats, NewPost, Post, PostFilter},
+        preferences::Preference,
+    },
+};
+
+/// SQLite only allows one writer at a time, so all writes go through a single connection, while
+/// reads get their own pool so feed requests don't queue up behind ingest
+#[derive(Clone)]
+pub struct SqliteStorage {
+    read: Pool<Sqlite>,
+    write: Pool<Sqlite>,
+}
+
+const READ_CONNECTIONS: u32 = 8;
+/// How long a connection waits for the database to be unlocked before failing
+const BUSY_TIMEOUT: Duration = Duration::from_secs(5);
+
+impl SqliteStorage {
+    pub async fn connect(url: &str) -> Result<Self> {
+        let options = SqliteConnectOptions::from_str(url)
+            .context("failed to parse db url")?
+            .journal_mode(SqliteJournalMode::Wal)
+            // safe with WAL, and a lot faster than FULL
+            .synchronous(SqliteSynchronous::Normal)
+            .busy_timeout(BUSY_TIMEOUT);
+
+        // so that `optimize` can reclaim free pages without vacuuming the whole database. this
+        // only takes effect on new databases, or on existing ones after they're vacuumed once
+        let write_options = options
+            .clone()
+            .create_if_missing(true)
+            .auto_vacuum(SqliteAutoVacuum::Incremental);
+        let write = SqlitePoolOptions::new()
+            .max_connections(1)
+            .connect_with(write_options)
+            .await
+            .context("failed to connect to db for writing")?;
+
+        let read = SqlitePoolOptions::new()
+            .max_connections(READ_CONNECTIONS)
+            .connect_with(options.read_only(true))
+            .await
+            .context("failed to connect to db for reading")?;
+
+        Ok(Self { read, write })
+    }
+
+    pub async fn close(&self) {
+        self.read.close().await;
+        self.write.close().await;
+    }
+
+    /// A migrated in-memory database. Reads and writes share its only connection
+    #[cfg(test)]
+    pub async fn memory() -> Self {
+        let pool = SqlitePoolOptions::new()
+            .max_connections(1)
+            .connect("sqlite::memory:")
+            .await
+            .unwrap();
+        let storage = Self {
+            read: pool.clone(),
+            write: pool,
+        };
+        storage.migrate().await.unwrap();
+
+        storage
+    }
+
+    /// The pool writes go through, for tests that need to look at the tables directly
+    #[cfg(test)]
+    pub fn pool(&self) -> &Pool<Sqlite> {
+        &self.write
+    }
+}
+
+impl Storage for SqliteStorage {
+    async fn migrate(&self) -> Result<()> {
+        sqlx::migrate!("./migrations/sqlite")
+            .run(&self.write)
+            .await
+            .context("failed to run migrations")?;
+
+        Ok(())
+    }
+
+    async fn write_batch(&self, writes: &[Write]) -> Result<()> {
+        let mut tx = self.write.begin().await?;
+
+        for write in writes {
+            match write {
+                Write::Create(post) => {
+                    let new = NewPost {
+                        uri: &post.uri,
+                        cid: post.cid.clone(),
+                        author: &post.author,
+                        reply_root: post.reply_root.as_deref(),
+                        reply_parent: post.reply_parent.as_deref(),
+                        quote: post.quote.as_deref(),
+                        fingerprint: post.fingerprint.as_deref(),
+                        langs: &post.langs,
+                    };
+                    Post::create(&mut *tx, &new).await?;
+
+                    for link in &post.links {
+                        let link = FoundLink {
+                            url: &link.url,
+                            kind: link.kind.clone(),
+                            site: link.site.clone(),
+                        };
+                        Link::create(&mut *tx, &link).await?;
+                        Link::add_to_post(&mut *tx, &post.uri, &link).await?;
+                    }
+                    for url in &post.urls {
+                        Link::add_url_to_post(&mut *tx, &post.uri, url).await?;
+                    }
+                }
+                Write::Delete(uri) => Post::delete(&mut *tx, uri).await?,
+                Write::Engage { uri, subject } => {
+                    if Engagement::create(&mut *tx, uri, subject).await? {
+                        Post::add_engagement(&mut *tx, subject, 1).await?;
+                    }
+                }
+                Write::Disengage(uri) => {
+                    if let Some(subject) = Engagement::delete(&mut *tx, uri).await? {
+                        Post::add_engagement(&mut *tx, &subject, -1).await?;
+                    }
+                }
+            }
+        }
+
+        tx.commit().await?;
+
+        Ok(())
+    }
+
+    async fn get_posts(
+        &self,
+        limit: u8,
+        before: Option<DateTime<Utc>>,
+        filter: &PostFilter<'_>,
+    ) -> Result<Vec<Post>> {
+        Post::get_all(&self.read, limit, before, filter).await
+    }
+
+    async fn author_stats(
+        &self,
+        author: &str,
+        fingerprint: &str,
+        rate_limit_since: DateTime<Utc>,
+        duplicate_since: DateTime<Utc>,
+    ) -> Result<AuthorStats> {
+        Post::author_stats(
+            &self.read,
+            author,
+            fingerprint,
+            rate_limit_since,
+            duplicate_since,
+        )
+        .await
+    }
+
+    async fn post_uris(&self) -> Result<Vec<String>> {
+        Post::uris(&self.read).await
+    }
+
+    async fn engagement_uris(&self) -> Result<Vec<String>> {
+        Engagement::uris(&self.read).await
+    }
+
+    async fn is_author_blocked(&self, did: &str) -> Result<bool> {
+        BlockedAuthor::is_blocked(&self.read, did).await
+    }
+
+    async fn any_link_blocked(&self, urls: &[&str]) -> Result<bool> {
+        BlockedLink::any_blocked(&self.read, urls).await
+    }
+
+    async fn blocked_authors(&self) -> Result<Vec<BlockedAuthor>> {
+        BlockedAuthor::get_all(&self.read).await
+    }
+
+    async fn block_author(&self, did: &str, reason: Option<&str>) -> Result<u64> {
+        let mut tx = self.write.begin().await?;
+        let purged = BlockedAuthor::create(&mut tx, did, reason).await?;
+        tx.commit().await?;
+
+        Ok(purged)
+    }
+
+    async fn unblock_author(&self, did: &str) -> Result<()> {
+        BlockedAuthor::delete(&self.write, did).await
+    }
+
+    async fn blocked_links(&self) -> Result<Vec<BlockedLink>> {
+        BlockedLink::get_all(&self.read).await
+    }
+
+    async fn block_link(&self, url: &str, reason: Option<&str>) -> Result<u64> {
+        let mut tx = self.write.begin().await?;
+        let purged = BlockedLink::create(&mut tx, url, reason).await?;
+        tx.commit().await?;
+
+        Ok(purged)
+    }
+
+    async fn unblock_link(&self, url: &str) -> Result<()> {
+        BlockedLink::delete(&self.write, url).await
+    }
+
+    async fn create_label(&self, label: &Label) -> Result<()> {
+        Label::create(&self.write, label).await
+    }
+
+    async fn delete_label(&self, src: &str, uri: &str, val: &str) -> Result<()> {
+        Label::delete(&self.write, src, uri, val).await
+    }
+
+    async fn store_interactions(
+        &self,
+        interactions: &[NewInteraction<'_>],
+        adjustments: &[Adjustment<'_>],
+        now: DateTime<Utc>,
+        half_life: Duration,
+    ) -> Result<()> {
+        let mut tx = self.write.begin().await?;
+
+        for interaction in interactions {
+            Interaction::create(&mut *tx, interaction).await?;
+        }
+        for adjustment in adjustments {
+            Preference::adjust(
+                &mut tx,
+                adjustment.viewer,
+                adjustment.dimension,
+                adjustment.value,
+                adjustment.delta,
+                now,
+                half_life,
+            )
+            .await?;
+        }
+
+        tx.commit().await?;
+
+        Ok(())
+    }
+
+    async fn preferences(&self, viewer: &str) -> Result<Vec<Preference>> {
+        Preference::get_all_for_viewer(&self.read, viewer).await
+    }
+
+    async fn prune(&self, cutoff: DateTime<Utc>) -> Result<PruneReport> {
+        let mut tx = self.write.begin().await?;
+
+        let posts = Post::delete_older_than(&mut *tx, cutoff).await?;
+        let links = Link::delete_orphaned(&mut *tx, cutoff).await?;
+
+        tx.commit().await?;
+
+        Ok(PruneReport { posts, links })
+    }
+
+    async fn links(&self) -> Result<Vec<StoredLink>> {
+        Link::all(&self.read).await
+    }
+
+    async fn update_links(&self, updates: &[LinkUpdate]) -> Result<ReindexReport> {
+        let mut tx = self.write.begin().await?;
+        let mut report = ReindexReport::default();
+
+        for update in updates {
+            match update {
+                LinkUpdate::Reclassify { url, kind, site } => {
+                    Link::reclassify(&mut *tx, url, kind, site).await?;
+                    report.reclassified += 1;
+                }
+                LinkUpdate::Remove { url } => {
+                    report.posts += Link::delete_posts_only_with(&mut *tx, url).await?;
+                    Link::remove_from_posts(&mut *tx, url).await?;
+                    report.links += Link::delete(&mut *tx, url).await?;
+                }
+            }
+        }
+
+        tx.commit().await?;
+
+        Ok(report)
+    }
+
+    async fn stats(&self) -> Result<Stats> {
+        Post::stats(&self.read).await
+    }
+
+    async fn optimize(&self) -> Result<()> {
+        sqlx::query("pragma incremental_vacuum")
+            .execute(&self.write)
+            .await
+            .context("failed to reclaim free pages")?;
+        sqlx::query("pragma optimize")
+            .execute(&self.write)
+            .await
+            .context("failed to optimize")?;
+
+        Ok(())
+    }
+
+    async fn vacuum(&self) -> Result<()> {
+        sqlx::query("vacuum")
+            .execute(&self.write)
+            .await
+            .context("failed to vacuum")?;
+
+        Ok(())
+    }
+
+    async fn cursor(&self, service: &str) -> Result<Option<i64>> {
+        Cursor::get(&self.read, service).await
+    }
+
+    async fn set_cursor(&self, service: &str, seq: i64) -> Result<()> {
+        Cursor::set(&self.write, service, seq).await
+    }
+
+    async fn heartbeat(&self, relay: &str) -> Result<Option<Heartbeat>> {
+        Heartbeat::get(&self.read, relay).await
+    }
+
+    async fn set_heartbeat(&self, heartbeat: &Heartbeat) -> Result<()> {
+        Heartbeat::set(&self.write, heartbeat).await
+    }
+
+    async fn ping(&self) -> Result<()> {
+        sqlx::query("select 1")
+            .execute(&self.read)
+            .await
+            .context("failed to ping db")?;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::{
+        sync::{
+            atomic::{AtomicBool, Ordering},
+            Arc,
+        },
+        time::Instant,
+    };
+
+    use atrium_api::app::bsky::feed::get_feed_skeleton::ParametersData;
+
+    use crate::{algos, server, storage::Database, AppState};
+
+    /// Temporary database file, deleted when dropped
+    struct TempDb(std::path::PathBuf);
+
+    impl TempDb {
+        fn new(name: &str) -> Self {
+            let path = std::env::temp_dir().join(format!("{name}-{}.sqlite", std::process::id()));
+            Self(path)
+        }
+
+        fn url(&self) -> String {
+            format!("sqlite://{}", self.0.display())
+        }
+    }
+
+    impl Drop for TempDb {
+        fn drop(&mut self) {
+            for suffix in ["", "-wal", "-shm"] {
+                let _ = std::fs::remove_file(format!("{}{suffix}", self.0.display()));
+            }
+        }
+    }
+
+    async fn insert(pool: &Pool<Sqlite>, i: usize) {
+        let uri = format!("at://did:plc:author{}/app.bsky.feed.post/{i}", i % 50);
+        Post::create(
+            pool,
+            &NewPost {
+                uri: &uri,
+                cid: format!("cid{i}"),
+                author: &format!("did:plc:author{}", i % 50),
+                reply_root: None,
+                reply_parent: None,
+                quote: None,
+                fingerprint: None,
+                langs: &[],
+            },
+        )
+        .await
+        .unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_pools_use_wal() {
+        let db = TempDb::new("wal");
+        let storage = SqliteStorage::connect(&db.url()).await.unwrap();
+
+        let mode = sqlx::query_scalar::<_, String>("pragma journal_mode")
+            .fetch_one(&storage.read)
+            .await
+            .unwrap();
+
+        assert_eq!("wal", mode);
+    }
+
+    #[tokio::test]
+    async fn test_new_databases_vacuum_incrementally() {
+        let db = TempDb::new("auto-vacuum");
+        let storage = SqliteStorage::connect(&db.url()).await.unwrap();
+        storage.migrate().await.unwrap();
+
+        let mode = sqlx::query_scalar::<_, i64>("pragma auto_vacuum")
+            .fetch_one(&storage.read)
+            .await
+            .unwrap();
+
+        // 2 is incremental
+        assert_eq!(2, mode);
+    }
+
+    #[tokio::test]
+    async fn test_read_pool_is_read_only() {
+        let db = TempDb::new("read-only");
+        let storage = SqliteStorage::connect(&db.url()).await.unwrap();
+        storage.migrate().await.unwrap();
+
+        insert(&storage.write, 0).await;
+        assert!(sqlx::query("delete from posts")
+            .execute(&storage.read)
+            .await
+            .is_err());
+    }
+
+    /// Requests `getFeedSkeleton` in a loop while posts are constantly being written, and checks
+    /// that the p99 latency stays low and doesn't grow as the table does.
+    ///
+    /// Run with `cargo test --release -- --ignored load_test`
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    #[ignore]
+    async fn load_test_feed_skeleton_p99_under_ingest() {
+        const PRELOAD: usize = 50_000;
+        const ROUNDS: usize = 5;
+        const REQUESTS_PER_ROUND: usize = 500;
+        const MAX_P99: Duration = Duration::from_millis(50);
+
+        let db = TempDb::new("load-test");
+        let storage = SqliteStorage::connect(&db.url()).await.unwrap();
+        storage.migrate().await.unwrap();
+
+        for i in 0..PRELOAD {
+            insert(&storage.write, i).await;
+        }
+
+        // sustained ingest for the whole test
+        let running = Arc::new(AtomicBool::new(true));
+        let writer = tokio::spawn({
+            let pool = storage.write.clone();
+            let running = running.clone();
+            async move {
+                let mut i = PRELOAD;
+                while running.load(Ordering::Relaxed) {
+                    insert(&pool, i).await;
+                    i += 1;
+                }
+                i - PRELOAD
+            }
+        });
+
+        let state = Arc::new(AppState {
+            config: server::test_config(),
+            db: Database::Sqlite(storage),
+            algos: algos::Registry::from_feeds(&Default::default()),
+            metrics: crate::metrics::test_handle(),
+            firehose: Default::default(),
+            auth: Default::default(),
+        });
+        let params = ParametersData {
+            feed: "at://did:plc:publisher/app.bsky.feed.generator/music".to_string(),
+            limit: Some(30.try_into().unwrap()),
+            cursor: None,
+        };
+
+        let mut p99s = vec![];
+        for _ in 0..ROUNDS {
+            let mut latencies = vec![];
+            for _ in 0..REQUESTS_PER_ROUND {
+                let start = Instant::now();
+                algos::feed("music", &state, &params, None).await.unwrap();
+                latencies.push(start.elapsed());
+            }
+            latencies.sort();
+            p99s.push(latencies[latencies.len() * 99 / 100]);
+        }
+
+        running.store(false, Ordering::Relaxed);
+        let written = writer.await.unwrap();
+        println!("wrote {written} posts during the test, p99s: {p99s:?}");
+
+        for p99 in p99s {
+            assert!(p99 < MAX_P99, "p99 of {p99:?} is over {MAX_P99:?}");
+        }
+    }
+}
@@ -0,0 +1,707 @@
+//! Tests every backend has to pass.
+//!
+//! They always run against sqlite. The postgres ones are ignored by default, and run against the
+//! database at `TEST_POSTGRES_URL`, each in its own schema, with
+//! `TEST_POSTGRES_URL=... cargo test storage::tests::postgres -- --ignored`
+
+use std::{str::FromStr, time::Duration};
+
+use chrono::{Timelike, Utc};
+use sqlx::{
+    postgres::{PgConnectOptions, PgPoolOptions},
+    Connection, PgConnection,
+};
+
+use super::*;
+use crate::{
+    algos::ReplyPolicy,
+    batch::{PendingLink, PendingPost},
+    link_finder::{Kind, Site},
+    models::{heartbeats::Heartbeat, preferences::Dimension},
+};
+
+async fn postgres(name: &str) -> PostgresStorage {
+    let url = std::env::var("TEST_POSTGRES_URL")
+        .expect("TEST_POSTGRES_URL has to be set to run the postgres tests");
+
+    let schema = format!("test_{name}");
+    let mut conn = PgConnection::connect(&url).await.unwrap();
+    sqlx::raw_sql(&format!(
+        "drop schema if exists {schema} cascade; create schema {schema}"
+    ))
+    .execute(&mut conn)
+    .await
+    .unwrap();
+
+    let options = PgConnectOptions::from_str(&url)
+        .unwrap()
+        .options([("search_path", &schema)]);
+    let pool = PgPoolOptions::new().connect_with(options).await.unwrap();
+    let storage = PostgresStorage::new(pool);
+    storage.migrate().await.unwrap();
+
+    storage
+}
+
+macro_rules! storage_tests {
+    ($($name:ident),* $(,)?) => {
+        mod sqlite {
+            $(
+                #[tokio::test]
+                async fn $name() {
+                    super::$name(&crate::storage::SqliteStorage::memory().await).await;
+                }
+            )*
+        }
+
+        mod postgres {
+            $(
+                #[tokio::test]
+                #[ignore = "needs a database at TEST_POSTGRES_URL"]
+                async fn $name() {
+                    let storage = super::postgres(stringify!($name)).await;
+                    super::$name(&storage).await;
+                }
+            )*
+        }
+    };
+}
+
+storage_tests!(
+    test_write_batch,
+    test_paging_through_a_batch,
+    test_reply_policy,
+    test_excluded_labels,
+    test_blocked_authors,
+    test_blocked_links,
+    test_author_stats,
+    test_preferences,
+    test_prune,
+    test_optimize,
+    test_cursor,
+    test_heartbeat,
+    test_ping,
+    test_filters,
+    test_engagement,
+    test_stats,
+    test_update_links,
+);
+
+const DAY: Duration = Duration::from_secs(24 * 60 * 60);
+
+fn post(uri: &str, author: &str, url: &str) -> PendingPost {
+    PendingPost {
+        uri: uri.to_string(),
+        cid: format!("cid-{uri}"),
+        author: author.to_string(),
+        reply_root: None,
+        reply_parent: None,
+        quote: None,
+        fingerprint: Some(format!("fingerprint-{uri}")),
+        langs: vec![],
+        links: vec![PendingLink {
+            url: url.to_string(),
+            kind: Kind::Album,
+            site: Site::Bandcamp,
+        }],
+        urls: vec![url.to_string()],
+    }
+}
+
+/// A post with a music link, that also links to `url`
+fn linking(uri: &str, url: &str) -> PendingPost {
+    let music = format!("https://x.bandcamp.com/album/{uri}");
+    PendingPost {
+        urls: vec![music.clone(), url.to_string()],
+        ..post(uri, "did:plc:author", &music)
+    }
+}
+
+fn reply(uri: &str, root: &str) -> PendingPost {
+    PendingPost {
+        reply_root: Some(root.to_string()),
+        reply_parent: Some(root.to_string()),
+        ..post(
+            uri,
+            "did:plc:author",
+            &format!("https://x.bandcamp.com/album/{uri}"),
+        )
+    }
+}
+
+fn engage(uri: &str, subject: &str) -> Write {
+    Write::Engage {
+        uri: uri.to_string(),
+        subject: subject.to_string(),
+    }
+}
+
+async fn uris(storage: &impl Storage, reply_policy: ReplyPolicy) -> Vec<String> {
+    uris_matching(
+        storage,
+        &PostFilter {
+            reply_policy,
+            excluded_labels: &["spam".to_string()],
+            ..Default::default()
+        },
+    )
+    .await
+}
+
+async fn uris_matching(storage: &impl Storage, filter: &PostFilter<'_>) -> Vec<String> {
+    let mut uris = storage
+        .get_posts(20, None, filter)
+        .await
+        .unwrap()
+        .into_iter()
+        .map(|post| post.uri)
+        .collect::<Vec<_>>();
+    uris.sort();
+    uris
+}
+
+async fn test_paging_through_a_batch(storage: &impl Storage) {
+    let writes = ["a", "b", "c", "d", "e"]
+        .into_iter()
+        .map(|uri| {
+            Write::Create(post(
+                uri,
+                "did:plc:author",
+                &format!("https://x.bandcamp.com/album/{uri}"),
+            ))
+        })
+        .collect::<Vec<_>>();
+    storage.write_batch(&writes).await.unwrap();
+
+    let mut pages = vec![];
+    let mut before = None;
+    loop {
+        let page = storage
+            .get_posts(2, before, &PostFilter::default())
+            .await
+            .unwrap();
+        let Some(last) = page.last() else {
+            break;
+        };
+        before = Some(last.indexed_at);
+        pages.push(page.into_iter().map(|post| post.uri).collect::<Vec<_>>());
+    }
+
+    assert_eq!(vec![vec!["e", "d"], vec!["c", "b"], vec!["a"]], pages);
+}
+
+async fn test_write_batch(storage: &impl Storage) {
+    storage
+        .write_batch(&[
+            Write::Create(post(
+                "a",
+                "did:plc:author",
+                "https://x.bandcamp.com/album/a",
+            )),
+            Write::Create(post(
+                "b",
+                "did:plc:author",
+                "https://x.bandcamp.com/album/b",
+            )),
+            Write::Delete("a".to_string()),
+        ])
+        .await
+        .unwrap();
+
+    let posts = storage
+        .get_posts(20, None, &PostFilter::default())
+        .await
+        .unwrap();
+
+    assert_eq!(1, posts.len());
+    assert_eq!("b", posts[0].uri);
+    assert_eq!("cid-b", posts[0].cid);
+    assert_eq!(Some(Site::Bandcamp), posts[0].site);
+    assert_eq!(Some(Kind::Album), posts[0].kind);
+
+    let later = posts[0].indexed_at + chrono::Duration::seconds(1);
+    assert!(storage
+        .get_posts(20, Some(later), &PostFilter::default())
+        .await
+        .unwrap()
+        .iter()
+        .any(|post| post.uri == "b"));
+    assert!(storage
+        .get_posts(20, Some(posts[0].indexed_at), &PostFilter::default())
+        .await
+        .unwrap()
+        .is_empty());
+}
+
+async fn test_reply_policy(storage: &impl Storage) {
+    storage
+        .write_batch(&[
+            Write::Create(post(
+                "top",
+                "did:plc:author",
+                "https://x.bandcamp.com/album/top",
+            )),
+            Write::Create(reply("music_reply", "top")),
+            Write::Create(reply("other_reply", "elsewhere")),
+        ])
+        .await
+        .unwrap();
+
+    assert_eq!(
+        vec!["music_reply", "other_reply", "top"],
+        uris(storage, ReplyPolicy::All).await
+    );
+    assert_eq!(vec!["top"], uris(storage, ReplyPolicy::TopLevel).await);
+    assert_eq!(
+        vec!["music_reply", "top"],
+        uris(storage, ReplyPolicy::MusicRoot).await
+    );
+}
+
+async fn test_excluded_labels(storage: &impl Storage) {
+    storage
+        .write_batch(&[
+            Write::Create(post("a", "did:plc:a", "https://x.bandcamp.com/album/a")),
+            Write::Create(post("b", "did:plc:b", "https://x.bandcamp.com/album/b")),
+        ])
+        .await
+        .unwrap();
+
+    let label = |uri: &str, val: &str| Label {
+        src: "did:plc:labeler".to_string(),
+        uri: uri.to_string(),
+        val: val.to_string(),
+        cts: Utc::now(),
+        exp: None,
+    };
+    storage.create_label(&label("a", "spam")).await.unwrap();
+    storage
+        .create_label(&label("did:plc:b", "not-excluded"))
+        .await
+        .unwrap();
+
+    assert_eq!(vec!["b"], uris(storage, ReplyPolicy::All).await);
+
+    storage
+        .delete_label("did:plc:labeler", "a", "spam")
+        .await
+        .unwrap();
+
+    assert_eq!(vec!["a", "b"], uris(storage, ReplyPolicy::All).await);
+}
+
+async fn test_blocked_authors(storage: &impl Storage) {
+    storage
+        .write_batch(&[
+            Write::Create(post(
+                "a",
+                "did:plc:spammer",
+                "https://x.bandcamp.com/album/a",
+            )),
+            Write::Create(post(
+                "b",
+                "did:plc:spammer",
+                "https://x.bandcamp.com/album/b",
+            )),
+            Write::Create(post("c", "did:plc:fine", "https://x.bandcamp.com/album/c")),
+        ])
+        .await
+        .unwrap();
+
+    let purged = storage
+        .block_author("did:plc:spammer", Some("spam"))
+        .await
+        .unwrap();
+
+    assert_eq!(2, purged);
+    assert_eq!(vec!["c"], uris(storage, ReplyPolicy::All).await);
+    assert!(storage.is_author_blocked("did:plc:spammer").await.unwrap());
+    assert!(!storage.is_author_blocked("did:plc:fine").await.unwrap());
+
+    let blocked = storage.blocked_authors().await.unwrap();
+    assert_eq!(1, blocked.len());
+    assert_eq!(Some("spam"), blocked[0].reason.as_deref());
+
+    storage.unblock_author("did:plc:spammer").await.unwrap();
+
+    assert!(!storage.is_author_blocked("did:plc:spammer").await.unwrap());
+}
+
+async fn test_blocked_links(storage: &impl Storage) {
+    storage
+        .write_batch(&[
+            Write::Create(linking("a", "https://scam.example/a")),
+            Write::Create(post(
+                "b",
+                "did:plc:author",
+                "https://x.bandcamp.com/album/b",
+            )),
+            Write::Create(linking("c", "https://notscam.example/c")),
+            Write::Create(linking("d", "https://www.scam.example/d")),
+        ])
+        .await
+        .unwrap();
+
+    let purged = storage.block_link("scam.example", None).await.unwrap();
+
+    assert_eq!(2, purged);
+    assert_eq!(vec!["b", "c"], uris(storage, ReplyPolicy::All).await);
+    assert!(storage
+        .any_link_blocked(&["https://x.bandcamp.com/album/b", "https://scam.example/c"])
+        .await
+        .unwrap());
+    assert!(!storage
+        .any_link_blocked(&["https://x.bandcamp.com/album/b"])
+        .await
+        .unwrap());
+    assert!(!storage
+        .any_link_blocked(&["https://notscam.example/c"])
+        .await
+        .unwrap());
+    assert_eq!(1, storage.blocked_links().await.unwrap().len());
+
+    storage.unblock_link("scam.example").await.unwrap();
+
+    assert!(!storage
+        .any_link_blocked(&["https://scam.example/c"])
+        .await
+        .unwrap());
+}
+
+async fn test_author_stats(storage: &impl Storage) {
+    storage
+        .write_batch(&[
+            Write::Create(post(
+                "a",
+                "did:plc:author",
+                "https://x.bandcamp.com/album/a",
+            )),
+            Write::Create(post(
+                "b",
+                "did:plc:author",
+                "https://x.bandcamp.com/album/b",
+            )),
+        ])
+        .await
+        .unwrap();
+
+    let an_hour_ago = Utc::now() - chrono::Duration::hours(1);
+    let stats = storage
+        .author_stats("did:plc:author", "fingerprint-a", an_hour_ago, an_hour_ago)
+        .await
+        .unwrap();
+
+    assert_eq!(2, stats.recent);
+    assert_eq!(1, stats.duplicates);
+
+    let stats = storage
+        .author_stats("did:plc:other", "fingerprint-a", an_hour_ago, an_hour_ago)
+        .await
+        .unwrap();
+
+    assert_eq!(0, stats.recent);
+    assert_eq!(0, stats.duplicates);
+}
+
+async fn test_preferences(storage: &impl Storage) {
+    let now = DateTime::from_timestamp(1_700_000_000, 0).unwrap();
+    let adjustment = |delta| Adjustment {
+        viewer: "did:plc:viewer",
+        dimension: Dimension::Site,
+        value: "spotify",
+        delta,
+    };
+    let interaction = NewInteraction {
+        requester: "did:plc:viewer",
+        item: "at://did:plc:author/app.bsky.feed.post/a",
+        event: "app.bsky.feed.defs#requestLess",
+        feed_context: Some("music:spotify:track:recent"),
+    };
+
+    storage
+        .store_interactions(&[interaction], &[adjustment(-4.0)], now, DAY)
+        .await
+        .unwrap();
+    storage
+        .store_interactions(
+            &[],
+            &[adjustment(1.0)],
+            now + chrono::Duration::days(1),
+            DAY,
+        )
+        .await
+        .unwrap();
+
+    let preferences = storage.preferences("did:plc:viewer").await.unwrap();
+
+    assert_eq!(1, preferences.len());
+    assert_eq!(Dimension::Site, preferences[0].dimension);
+    assert_eq!(-1.0, preferences[0].score);
+    assert!(storage
+        .preferences("did:plc:someone")
+        .await
+        .unwrap()
+        .is_empty());
+}
+
+async fn test_prune(storage: &impl Storage) {
+    storage
+        .write_batch(&[
+            Write::Create(post(
+                "old",
+                "did:plc:author",
+                "https://x.bandcamp.com/album/old",
+            )),
+            Write::Create(post(
+                "shared",
+                "did:plc:author",
+                "https://x.bandcamp.com/album/shared",
+            )),
+        ])
+        .await
+        .unwrap();
+    tokio::time::sleep(Duration::from_millis(10)).await;
+    let cutoff = Utc::now();
+    tokio::time::sleep(Duration::from_millis(10)).await;
+    storage
+        .write_batch(&[Write::Create(post(
+            "new",
+            "did:plc:author",
+            "https://x.bandcamp.com/album/shared",
+        ))])
+        .await
+        .unwrap();
+
+    let report = storage.prune(cutoff).await.unwrap();
+
+    // the shared link is still in a post that wasn't pruned
+    assert_eq!(PruneReport { posts: 2, links: 1 }, report);
+    assert_eq!(vec!["new"], uris(storage, ReplyPolicy::All).await);
+}
+
+async fn test_stats(storage: &impl Storage) {
+    let empty = storage.stats().await.unwrap();
+    assert_eq!(
+        (0, 0, 0, None),
+        (empty.posts, empty.authors, empty.links, empty.oldest_post)
+    );
+
+    storage
+        .write_batch(&[
+            Write::Create(post("a", "did:plc:a", "https://x.bandcamp.com/album/a")),
+            Write::Create(post("b", "did:plc:a", "https://x.bandcamp.com/album/b")),
+            Write::Create(post("c", "did:plc:c", "https://x.bandcamp.com/album/b")),
+        ])
+        .await
+        .unwrap();
+
+    let stats = storage.stats().await.unwrap();
+    assert_eq!((3, 2, 2), (stats.posts, stats.authors, stats.links));
+    assert!(stats.oldest_post.unwrap() <= stats.newest_post.unwrap());
+}
+
+async fn test_update_links(storage: &impl Storage) {
+    let mut both = post("both", "did:plc:a", "https://x.bandcamp.com/album/kept");
+    both.links.push(PendingLink {
+        url: "https://example.com/gone".to_string(),
+        kind: Kind::Track,
+        site: Site::Spotify,
+    });
+    storage
+        .write_batch(&[
+            Write::Create(both),
+            Write::Create(post("only", "did:plc:b", "https://example.com/gone")),
+        ])
+        .await
+        .unwrap();
+
+    let report = storage
+        .update_links(&[
+            LinkUpdate::Reclassify {
+                url: "https://x.bandcamp.com/album/kept".to_string(),
+                kind: Kind::Track,
+                site: Site::Bandcamp,
+            },
+            LinkUpdate::Remove {
+                url: "https://example.com/gone".to_string(),
+            },
+        ])
+        .await
+        .unwrap();
+
+    assert_eq!(
+        ReindexReport {
+            reclassified: 1,
+            links: 1,
+            posts: 1,
+        },
+        report
+    );
+    assert_eq!(
+        vec![StoredLink {
+            url: "https://x.bandcamp.com/album/kept".to_string(),
+            kind: Kind::Track,
+            site: Site::Bandcamp,
+        }],
+        storage.links().await.unwrap()
+    );
+    assert_eq!(vec!["both"], uris(storage, ReplyPolicy::All).await);
+}
+
+async fn test_optimize(storage: &impl Storage) {
+    storage.optimize().await.unwrap();
+    storage.vacuum().await.unwrap();
+}
+
+async fn test_cursor(storage: &impl Storage) {
+    assert_eq!(None, storage.cursor("bsky.network").await.unwrap());
+
+    storage.set_cursor("bsky.network", 10).await.unwrap();
+    storage.set_cursor("bsky.network", 20).await.unwrap();
+    storage.set_cursor("other.relay", 5).await.unwrap();
+
+    assert_eq!(Some(20), storage.cursor("bsky.network").await.unwrap());
+}
+
+async fn test_heartbeat(storage: &impl Storage) {
+    assert_eq!(None, storage.heartbeat("bsky.network").await.unwrap());
+
+    let now = Utc::now().with_nanosecond(0).unwrap();
+    let heartbeat = Heartbeat {
+        relay: "bsky.network".to_string(),
+        connected: true,
+        cursor: None,
+        started_at: now - DAY,
+        last_commit_at: None,
+        updated_at: now - DAY,
+    };
+    storage.set_heartbeat(&heartbeat).await.unwrap();
+
+    let heartbeat = Heartbeat {
+        cursor: Some(20),
+        last_commit_at: Some(now),
+        updated_at: now,
+        ..heartbeat
+    };
+    storage.set_heartbeat(&heartbeat).await.unwrap();
+
+    assert_eq!(
+        Some(heartbeat),
+        storage.heartbeat("bsky.network").await.unwrap()
+    );
+    assert_eq!(None, storage.heartbeat("other.relay").await.unwrap());
+}
+
+async fn test_ping(storage: &impl Storage) {
+    storage.ping().await.unwrap();
+}
+
+async fn test_filters(storage: &impl Storage) {
+    let mut english = post("english", "did:plc:a", "https://open.spotify.com/track/a");
+    english.links[0].site = Site::Spotify;
+    english.links[0].kind = Kind::Track;
+    english.langs = vec!["en-us".to_string()];
+    let mut portuguese = post("portuguese", "did:plc:b", "https://x.bandcamp.com/album/b");
+    portuguese.langs = vec!["pt".to_string()];
+    // a bandcamp album, with a spotify track as its second link
+    let mut both = post("both", "did:plc:c", "https://x.bandcamp.com/album/c");
+    both.links.push(PendingLink {
+        url: "https://open.spotify.com/track/c".to_string(),
+        kind: Kind::Track,
+        site: Site::Spotify,
+    });
+    storage
+        .write_batch(&[
+            Write::Create(english),
+            Write::Create(portuguese),
+            Write::Create(both),
+        ])
+        .await
+        .unwrap();
+
+    let filter = |sites, kinds, languages, excluded_authors| PostFilter {
+        sites,
+        kinds,
+        languages,
+        excluded_authors,
+        ..Default::default()
+    };
+
+    assert_eq!(
+        vec!["both", "english"],
+        uris_matching(storage, &filter(&[Site::Spotify], &[], &[], &[])).await
+    );
+    assert_eq!(
+        vec!["both", "portuguese"],
+        uris_matching(storage, &filter(&[], &[Kind::Album], &[], &[])).await
+    );
+    // the site and kind have to match on the same link
+    assert_eq!(
+        Vec::<String>::new(),
+        uris_matching(storage, &filter(&[Site::Spotify], &[Kind::Album], &[], &[])).await
+    );
+    let english = ["en".to_string()];
+    assert_eq!(
+        vec!["english"],
+        uris_matching(storage, &filter(&[], &[], &english, &[])).await
+    );
+    let excluded = ["did:plc:b".to_string()];
+    assert_eq!(
+        vec!["both", "english"],
+        uris_matching(storage, &filter(&[], &[], &[], &excluded)).await
+    );
+}
+
+async fn test_engagement(storage: &impl Storage) {
+    storage
+        .write_batch(&[
+            Write::Create(post("a", "did:plc:a", "https://x.bandcamp.com/album/a")),
+            Write::Create(post("b", "did:plc:b", "https://x.bandcamp.com/album/b")),
+            engage("like-1", "a"),
+            engage("repost-1", "a"),
+            engage("like-2", "b"),
+            engage("like-3", "not-indexed"),
+            // the same like again, from a replayed commit
+            engage("like-1", "a"),
+        ])
+        .await
+        .unwrap();
+
+    let filter = PostFilter {
+        min_engagement: 2,
+        ..Default::default()
+    };
+    let posts = storage.get_posts(20, None, &filter).await.unwrap();
+
+    assert_eq!(1, posts.len());
+    assert_eq!("a", posts[0].uri);
+    assert_eq!(2, posts[0].engagement);
+
+    storage
+        .write_batch(&[
+            Write::Disengage("repost-1".to_string()),
+            Write::Disengage("repost-1".to_string()),
+            Write::Disengage("like-3".to_string()),
+        ])
+        .await
+        .unwrap();
+
+    let posts = storage.get_posts(20, None, &filter).await.unwrap();
+    assert!(posts.is_empty());
+    let posts = storage
+        .get_posts(20, None, &PostFilter::default())
+        .await
+        .unwrap();
+    let engagement = posts
+        .iter()
+        .map(|post| (post.uri.as_str(), post.engagement))
+        .collect::<Vec<_>>();
+    assert_eq!(vec![("b", 1), ("a", 1)], engagement);
+
+    let mut uris = storage.post_uris().await.unwrap();
+    uris.sort();
+    assert_eq!(vec!["a", "b"], uris);
+    let mut uris = storage.engagement_uris().await.unwrap();
+    uris.sort();
+    assert_eq!(vec!["like-1", "like-2"], uris);
+}
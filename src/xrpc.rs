@@ -0,0 +1,127 @@
+//! Errors for the xrpc methods we serve
+
+use axum::{
+    extract::rejection::{JsonRejection, QueryRejection},
+    http::StatusCode,
+    response::{IntoResponse, Response},
+    Json,
+};
+use serde_json::json;
+
+/// An error responded as `{ "error": <name>, "message": <message> }`, like AppViews expect
+#[derive(Debug)]
+pub enum XrpcError {
+    /// The requested feed isn't one we serve
+    UnknownFeed,
+    /// The parameters or body are missing or malformed
+    InvalidRequest(String),
+    AuthenticationRequired,
+    /// Logged when responded with, but only a generic message is sent
+    InternalServerError(anyhow::Error),
+}
+
+impl XrpcError {
+    pub fn name(&self) -> &'static str {
+        match self {
+            XrpcError::UnknownFeed => "UnknownFeed",
+            XrpcError::InvalidRequest(_) => "InvalidRequest",
+            XrpcError::AuthenticationRequired => "AuthenticationRequired",
+            XrpcError::InternalServerError(_) => "InternalServerError",
+        }
+    }
+
+    pub fn status(&self) -> StatusCode {
+        match self {
+            XrpcError::UnknownFeed | XrpcError::InvalidRequest(_) => StatusCode::BAD_REQUEST,
+            XrpcError::AuthenticationRequired => StatusCode::UNAUTHORIZED,
+            XrpcError::InternalServerError(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+
+    fn message(&self) -> String {
+        match self {
+            XrpcError::UnknownFeed => "Unknown feed".to_string(),
+            XrpcError::InvalidRequest(message) => message.clone(),
+            XrpcError::AuthenticationRequired => "Authentication required".to_string(),
+            XrpcError::InternalServerError(_) => "Internal server error".to_string(),
+        }
+    }
+}
+
+impl From<anyhow::Error> for XrpcError {
+    fn from(err: anyhow::Error) -> Self {
+        XrpcError::InternalServerError(err)
+    }
+}
+
+impl From<QueryRejection> for XrpcError {
+    fn from(rejection: QueryRejection) -> Self {
+        XrpcError::InvalidRequest(rejection.body_text())
+    }
+}
+
+impl From<JsonRejection> for XrpcError {
+    fn from(rejection: JsonRejection) -> Self {
+        XrpcError::InvalidRequest(rejection.body_text())
+    }
+}
+
+impl IntoResponse for XrpcError {
+    fn into_response(self) -> Response {
+        if let XrpcError::InternalServerError(err) = &self {
+            tracing::error!("{err:?}");
+        }
+
+        let body = json!({
+            "error": self.name(),
+            "message": self.message(),
+        });
+        (self.status(), Json(body)).into_response()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use anyhow::anyhow;
+    use axum::body::to_bytes;
+    use serde_json::Value;
+
+    async fn respond(err: XrpcError) -> (StatusCode, Value) {
+        let response = err.into_response();
+        let status = response.status();
+        let body = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+
+        (status, serde_json::from_slice(&body).unwrap())
+    }
+
+    #[tokio::test]
+    async fn test_response() {
+        let (status, body) = respond(XrpcError::UnknownFeed).await;
+        assert_eq!(StatusCode::BAD_REQUEST, status);
+        assert_eq!(
+            json!({ "error": "UnknownFeed", "message": "Unknown feed" }),
+            body
+        );
+
+        let (status, body) = respond(XrpcError::InvalidRequest("Bad cursor".to_string())).await;
+        assert_eq!(StatusCode::BAD_REQUEST, status);
+        assert_eq!(
+            json!({ "error": "InvalidRequest", "message": "Bad cursor" }),
+            body
+        );
+    }
+
+    #[tokio::test]
+    async fn test_internal_error_is_not_leaked() {
+        let err = anyhow!("connection to postgres://user:password@db failed");
+        let (status, body) = respond(err.into()).await;
+
+        assert_eq!(StatusCode::INTERNAL_SERVER_ERROR, status);
+        assert_eq!(
+            json!({ "error": "InternalServerError", "message": "Internal server error" }),
+            body
+        );
+    }
+}